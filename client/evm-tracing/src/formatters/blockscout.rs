@@ -87,6 +87,10 @@ pub struct BlockscoutCall {
 	pub gas: U256,
 	/// Gas used by this context.
 	pub gas_used: U256,
+	/// Subcalls not tracked due to `Listener::with_max_tracked_frames`, whose gas is
+	/// accounted in this context.
+	#[serde(skip_serializing_if = "core::ops::Not::not")]
+	pub subcalls_truncated: bool,
 	#[serde(flatten)]
 	pub inner: BlockscoutCallInner,
 }
@@ -14,7 +14,7 @@
 // You should have received a copy of the GNU General Public License
 // along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
 
-use super::blockscout::BlockscoutCallInner;
+use super::blockscout::{BlockscoutCall, BlockscoutCallInner};
 use crate::types::{
 	single::{Call, TransactionTrace},
 	CallResult, CallType, CreateResult,
@@ -25,9 +25,9 @@ use crate::listeners::call_list::Listener;
 use crate::types::serialization::*;
 use serde::Serialize;
 
-use codec::{Decode, Encode};
+use codec::{Compact, Decode, Encode, EncodeLike, Output};
 use ethereum_types::{H160, U256};
-use sp_std::{cmp::Ordering, vec::Vec};
+use sp_std::{cmp::Ordering, collections::btree_map::BTreeMap, vec::Vec};
 
 pub struct Formatter;
 
@@ -39,209 +39,216 @@ impl super::ResponseFormatter for Formatter {
 		// Remove empty BTreeMaps pushed to `entries`.
 		// I.e. InvalidNonce or other pallet_evm::runner exits
 		listener.entries.retain(|x| !x.is_empty());
-		let mut traces = Vec::new();
-		for entry in listener.entries.iter() {
-			let mut result: Vec<Call> = entry
-				.into_iter()
-				.filter_map(|(_, it)| {
-					let from = it.from;
-					let trace_address = it.trace_address.clone();
-					let value = it.value;
-					let gas = it.gas;
-					let gas_used = it.gas_used;
-					let inner = it.inner.clone();
-					Some(Call::CallTracer(CallTracerCall {
-						from: from,
-						gas: gas,
-						gas_used: gas_used,
-						trace_address: Some(trace_address.clone()),
-						inner: match inner.clone() {
-							BlockscoutCallInner::Call {
-								input,
-								to,
-								res,
-								call_type,
-							} => CallTracerInner::Call {
-								call_type: match call_type {
-									CallType::Call => "CALL".as_bytes().to_vec(),
-									CallType::CallCode => "CALLCODE".as_bytes().to_vec(),
-									CallType::DelegateCall => "DELEGATECALL".as_bytes().to_vec(),
-									CallType::StaticCall => "STATICCALL".as_bytes().to_vec(),
-								},
-								to,
-								input,
-								res,
-								value: Some(value),
-							},
-							BlockscoutCallInner::Create { init, res } => CallTracerInner::Create {
-								input: init,
-								error: match res {
-									CreateResult::Success { .. } => None,
-									CreateResult::Error { ref error } => Some(error.clone()),
-								},
-								to: match res {
-									CreateResult::Success {
-										created_contract_address_hash,
-										..
-									} => Some(created_contract_address_hash),
-									CreateResult::Error { .. } => None,
-								},
-								output: match res {
-									CreateResult::Success {
-										created_contract_code,
-										..
-									} => Some(created_contract_code),
-									CreateResult::Error { .. } => None,
-								},
-								value: value,
-								call_type: "CREATE".as_bytes().to_vec(),
-							},
-							BlockscoutCallInner::SelfDestruct { balance, to } => {
-								CallTracerInner::SelfDestruct {
-									value: balance,
-									to,
-									call_type: "SELFDESTRUCT".as_bytes().to_vec(),
-								}
-							}
+		let traces: Vec<_> = listener
+			.entries
+			.iter()
+			.filter_map(nest)
+			.map(TransactionTrace::CallListNested)
+			.collect();
+		if traces.is_empty() {
+			return None;
+		}
+		return Some(traces);
+	}
+}
+
+/// Nests the calls of a transaction in the call list into the tree of its context call, or
+/// `None` if they don't form a single tree.
+pub fn nest(entry: &BTreeMap<u32, BlockscoutCall>) -> Option<Call> {
+	let mut result: Vec<Call> = entry
+		.into_iter()
+		.filter_map(|(_, it)| {
+			let from = it.from;
+			let trace_address = it.trace_address.clone();
+			let value = it.value;
+			let gas = it.gas;
+			let gas_used = it.gas_used;
+			let inner = it.inner.clone();
+			Some(Call::CallTracer(CallTracerCall {
+				from: from,
+				gas: gas,
+				gas_used: gas_used,
+				subcalls_truncated: it.subcalls_truncated,
+				trace_address: Some(trace_address.clone()),
+				inner: match inner.clone() {
+					BlockscoutCallInner::Call {
+						input,
+						to,
+						res,
+						call_type,
+					} => CallTracerInner::Call {
+						call_type: match call_type {
+							CallType::Call => "CALL".as_bytes().to_vec(),
+							CallType::CallCode => "CALLCODE".as_bytes().to_vec(),
+							CallType::DelegateCall => "DELEGATECALL".as_bytes().to_vec(),
+							CallType::StaticCall => "STATICCALL".as_bytes().to_vec(),
 						},
-						calls: Vec::new(),
-					}))
-				})
-				.map(|x| x)
-				.collect();
-			// Geth's `callTracer` expects a tree of nested calls and we have a stack.
-			//
-			// We iterate over the sorted stack, and push each children to it's
-			// parent (the item which's `trace_address` matches &T[0..T.len()-1]) until there
-			// is a single item on the list.
-			//
-			// The last remaining item is the context call with all it's descendants. I.e.
-			//
-			// 		# Input
-			// 		[]
-			// 		[0]
-			// 		[0,0]
-			// 		[0,0,0]
-			// 		[0,1]
-			// 		[0,1,0]
-			// 		[0,1,1]
-			// 		[0,1,2]
-			// 		[1]
-			// 		[1,0]
-			//
-			// 		# Sorted
-			// 		[0,0,0] -> pop 0 and push to [0,0]
-			// 		[0,1,0] -> pop 0 and push to [0,1]
-			// 		[0,1,1] -> pop 1 and push to [0,1]
-			// 		[0,1,2] -> pop 2 and push to [0,1]
-			// 		[0,0] -> pop 0 and push to [0]
-			// 		[0,1] -> pop 1 and push to [0]
-			// 		[1,0] -> pop 0 and push to [1]
-			// 		[0] -> pop 0 and push to root
-			// 		[1] -> pop 1 and push to root
-			// 		[]
-			//
-			// 		# Result
-			// 		root {
-			// 			calls: {
-			// 				0 { 0 { 0 }, 1 { 0, 1, 2 }},
-			// 				1 { 0 },
-			// 			}
-			// 		}
-			if result.len() > 1 {
-				// Sort the stack. Assume there is no `Ordering::Equal`, as we are
-				// sorting by index.
-				//
-				// We consider an item to be `Ordering::Less` when:
-				// 	- Is closer to the root or
-				//	- Is greater than its sibling.
-				result.sort_by(|a, b| match (a, b) {
-					(
-						Call::CallTracer(CallTracerCall {
-							trace_address: Some(a),
-							..
-						}),
-						Call::CallTracer(CallTracerCall {
-							trace_address: Some(b),
-							..
-						}),
-					) => {
-						let a_len = a.len();
-						let b_len = b.len();
-						let sibling_greater_than = |a: &Vec<u32>, b: &Vec<u32>| -> bool {
-							for (i, a_value) in a.iter().enumerate() {
-								if a_value > &b[i] {
-									return true;
-								} else if a_value < &b[i] {
-									return false;
-								} else {
-									continue;
-								}
-							}
-							return false;
-						};
-						if b_len > a_len || (a_len == b_len && sibling_greater_than(&a, &b)) {
-							Ordering::Less
-						} else {
-							Ordering::Greater
+						to,
+						input,
+						res,
+						value: Some(value),
+					},
+					BlockscoutCallInner::Create { init, res } => CallTracerInner::Create {
+						input: init,
+						error: match res {
+							CreateResult::Success { .. } => None,
+							CreateResult::Error { ref error } => Some(error.clone()),
+						},
+						to: match res {
+							CreateResult::Success {
+								created_contract_address_hash,
+								..
+							} => Some(created_contract_address_hash),
+							CreateResult::Error { .. } => None,
+						},
+						output: match res {
+							CreateResult::Success {
+								created_contract_code,
+								..
+							} => Some(created_contract_code),
+							CreateResult::Error { .. } => None,
+						},
+						value: value,
+						call_type: "CREATE".as_bytes().to_vec(),
+					},
+					BlockscoutCallInner::SelfDestruct { balance, to } => {
+						CallTracerInner::SelfDestruct {
+							value: balance,
+							to,
+							call_type: "SELFDESTRUCT".as_bytes().to_vec(),
 						}
 					}
-					_ => unreachable!(),
-				});
-				// Stack pop-and-push.
-				while result.len() > 1 {
-					let mut last = result.pop().unwrap();
-					// Find the parent index.
-					if let Some(index) =
-						result
-							.iter()
-							.position(|current| match (last.clone(), current) {
-								(
-									Call::CallTracer(CallTracerCall {
-										trace_address: Some(a),
-										..
-									}),
-									Call::CallTracer(CallTracerCall {
-										trace_address: Some(b),
-										..
-									}),
-								) => &b[..] == &a[0..a.len() - 1],
-								_ => unreachable!(),
-							}) {
-						// Remove `trace_address` from result.
-						if let Call::CallTracer(CallTracerCall {
-							ref mut trace_address,
-							..
-						}) = last
-						{
-							*trace_address = None;
-						}
-						// Push the children to parent.
-						if let Some(Call::CallTracer(CallTracerCall { calls, .. })) =
-							result.get_mut(index)
-						{
-							calls.push(last);
+				},
+				calls: Vec::new(),
+			}))
+		})
+		.map(|x| x)
+		.collect();
+	// Geth's `callTracer` expects a tree of nested calls and we have a stack.
+	//
+	// We iterate over the sorted stack, and push each children to it's
+	// parent (the item which's `trace_address` matches &T[0..T.len()-1]) until there
+	// is a single item on the list.
+	//
+	// The last remaining item is the context call with all it's descendants. I.e.
+	//
+	// 		# Input
+	// 		[]
+	// 		[0]
+	// 		[0,0]
+	// 		[0,0,0]
+	// 		[0,1]
+	// 		[0,1,0]
+	// 		[0,1,1]
+	// 		[0,1,2]
+	// 		[1]
+	// 		[1,0]
+	//
+	// 		# Sorted
+	// 		[0,0,0] -> pop 0 and push to [0,0]
+	// 		[0,1,0] -> pop 0 and push to [0,1]
+	// 		[0,1,1] -> pop 1 and push to [0,1]
+	// 		[0,1,2] -> pop 2 and push to [0,1]
+	// 		[0,0] -> pop 0 and push to [0]
+	// 		[0,1] -> pop 1 and push to [0]
+	// 		[1,0] -> pop 0 and push to [1]
+	// 		[0] -> pop 0 and push to root
+	// 		[1] -> pop 1 and push to root
+	// 		[]
+	//
+	// 		# Result
+	// 		root {
+	// 			calls: {
+	// 				0 { 0 { 0 }, 1 { 0, 1, 2 }},
+	// 				1 { 0 },
+	// 			}
+	// 		}
+	if result.len() > 1 {
+		// Sort the stack. Assume there is no `Ordering::Equal`, as we are
+		// sorting by index.
+		//
+		// We consider an item to be `Ordering::Less` when:
+		// 	- Is closer to the root or
+		//	- Is greater than its sibling.
+		result.sort_by(|a, b| match (a, b) {
+			(
+				Call::CallTracer(CallTracerCall {
+					trace_address: Some(a),
+					..
+				}),
+				Call::CallTracer(CallTracerCall {
+					trace_address: Some(b),
+					..
+				}),
+			) => {
+				let a_len = a.len();
+				let b_len = b.len();
+				let sibling_greater_than = |a: &Vec<u32>, b: &Vec<u32>| -> bool {
+					for (i, a_value) in a.iter().enumerate() {
+						if a_value > &b[i] {
+							return true;
+						} else if a_value < &b[i] {
+							return false;
+						} else {
+							continue;
 						}
 					}
+					return false;
+				};
+				if b_len > a_len || (a_len == b_len && sibling_greater_than(&a, &b)) {
+					Ordering::Less
+				} else {
+					Ordering::Greater
 				}
 			}
-			// Remove `trace_address` from result.
-			if let Some(Call::CallTracer(CallTracerCall { trace_address, .. })) = result.get_mut(0)
-			{
-				*trace_address = None;
-			}
-			if result.len() == 1 {
-				traces.push(TransactionTrace::CallListNested(result.pop().unwrap()));
+			_ => unreachable!(),
+		});
+		// Stack pop-and-push. The tree is built iteratively, and `last` is only borrowed
+		// while searching its parent, so that neither the depth of the tree nor the size of
+		// the subtrees matter.
+		while result.len() > 1 {
+			let mut last = result.pop().unwrap();
+			// Find the parent index.
+			if let Some(index) = result.iter().position(|current| match (&last, current) {
+				(
+					Call::CallTracer(CallTracerCall {
+						trace_address: Some(a),
+						..
+					}),
+					Call::CallTracer(CallTracerCall {
+						trace_address: Some(b),
+						..
+					}),
+				) => &b[..] == &a[0..a.len() - 1],
+				_ => unreachable!(),
+			}) {
+				// Remove `trace_address` from result.
+				if let Call::CallTracer(CallTracerCall {
+					ref mut trace_address,
+					..
+				}) = last
+				{
+					*trace_address = None;
+				}
+				// Push the children to parent.
+				if let Some(Call::CallTracer(CallTracerCall { calls, .. })) = result.get_mut(index)
+				{
+					calls.push(last);
+				}
 			}
 		}
-		if traces.is_empty() {
-			return None;
-		}
-		return Some(traces);
 	}
+	// Remove `trace_address` from result.
+	if let Some(Call::CallTracer(CallTracerCall { trace_address, .. })) = result.get_mut(0) {
+		*trace_address = None;
+	}
+	if result.len() == 1 {
+		return result.pop();
+	}
+	None
 }
 
-#[derive(Clone, Eq, PartialEq, Debug, Encode, Decode, Serialize)]
+#[derive(Clone, Eq, PartialEq, Debug, Decode, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CallTracerCall {
 	pub from: H160,
@@ -254,6 +261,10 @@ pub struct CallTracerCall {
 	pub gas: U256,
 	/// Gas used by this context.
 	pub gas_used: U256,
+	/// Subcalls not tracked due to `Listener::with_max_tracked_frames`, whose gas is
+	/// accounted in this context.
+	#[serde(skip_serializing_if = "core::ops::Not::not")]
+	pub subcalls_truncated: bool,
 
 	#[serde(flatten)]
 	pub inner: CallTracerInner,
@@ -262,6 +273,56 @@ pub struct CallTracerCall {
 	pub calls: Vec<Call>,
 }
 
+impl Drop for CallTracerCall {
+	// The subcalls are moved to an explicit stack before being dropped, so that dropping a
+	// deep tree doesn't recurse once per level.
+	fn drop(&mut self) {
+		let mut stack = sp_std::mem::take(&mut self.calls);
+		while let Some(mut call) = stack.pop() {
+			if let Call::CallTracer(call) = &mut call {
+				stack.append(&mut call.calls);
+			}
+		}
+	}
+}
+
+// Encoded as the derived implementation would, but walking the subcalls with an explicit stack
+// so that encoding a deep tree doesn't recurse once per level.
+impl Encode for CallTracerCall {
+	fn encode_to<T: Output + ?Sized>(&self, dest: &mut T) {
+		self.encode_fields_to(dest);
+		let mut stack: Vec<&Call> = self.calls.iter().rev().collect();
+		while let Some(call) = stack.pop() {
+			match call {
+				Call::Blockscout(call) => {
+					0u8.encode_to(dest);
+					call.encode_to(dest);
+				}
+				Call::CallTracer(call) => {
+					1u8.encode_to(dest);
+					call.encode_fields_to(dest);
+					stack.extend(call.calls.iter().rev());
+				}
+			}
+		}
+	}
+}
+
+impl EncodeLike for CallTracerCall {}
+
+impl CallTracerCall {
+	/// Encodes the fields of the call, followed by the amount of its subcalls.
+	fn encode_fields_to<T: Output + ?Sized>(&self, dest: &mut T) {
+		self.from.encode_to(dest);
+		self.trace_address.encode_to(dest);
+		self.gas.encode_to(dest);
+		self.gas_used.encode_to(dest);
+		self.subcalls_truncated.encode_to(dest);
+		self.inner.encode_to(dest);
+		Compact(self.calls.len() as u32).encode_to(dest);
+	}
+}
+
 #[derive(Clone, Eq, PartialEq, Debug, Encode, Decode, Serialize)]
 #[serde(untagged)]
 pub enum CallTracerInner {
@@ -304,3 +365,148 @@ pub enum CallTracerInner {
 		value: U256,
 	},
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::formatters::ResponseFormatter;
+
+	/// Maximum depth of the EVM call stack.
+	const MAX_CALL_DEPTH: u32 = 1024;
+
+	/// Stack of the threads checking that the deepest call tree is handled without recursing
+	/// once per level, a fraction of the default stack of the test threads.
+	const SMALL_STACK: usize = 128 * 1024;
+
+	fn deepest_call_list() -> Listener {
+		let mut listener = Listener::default();
+		let mut entry = BTreeMap::new();
+		for depth in 0..MAX_CALL_DEPTH {
+			entry.insert(
+				depth,
+				BlockscoutCall {
+					from: H160::repeat_byte(0x01),
+					trace_address: vec![0; depth as usize],
+					subtraces: 1,
+					value: U256::zero(),
+					gas: 10_000.into(),
+					gas_used: 1_000.into(),
+					subcalls_truncated: false,
+					inner: BlockscoutCallInner::Call {
+						call_type: CallType::Call,
+						to: H160::repeat_byte(0x01),
+						input: Vec::new(),
+						res: CallResult::Output(Vec::new()),
+					},
+				},
+			);
+		}
+		listener.entries.push(entry);
+		listener
+	}
+
+	/// Stack of the threads of the RPC server, which serialize the responses. Unlike the rest of
+	/// the handling of the tree, serde serializes each level of nesting in a nested call, so this
+	/// bounds the stack it may use per level.
+	const RPC_SERVER_STACK: usize = 2 * 1024 * 1024;
+
+	/// Runs `f` on a thread with a stack of `stack_size` bytes, failing if it overflows.
+	fn on_stack<R: Send + 'static>(stack_size: usize, f: impl FnOnce() -> R + Send + 'static) -> R {
+		std::thread::Builder::new()
+			.stack_size(stack_size)
+			.spawn(f)
+			.expect("thread to be spawned")
+			.join()
+			.expect("thread not to overflow its stack")
+	}
+
+	/// Depth of the nested call tree of the first trace, and its deepest call.
+	fn nested_depth(traces: &[TransactionTrace]) -> (u32, Option<&CallTracerCall>) {
+		let mut depth = 0;
+		let mut deepest = None;
+		let mut current = match &traces[0] {
+			TransactionTrace::CallListNested(call) => Some(call),
+			_ => None,
+		};
+		while let Some(Call::CallTracer(call)) = current {
+			assert_eq!(call.trace_address, None);
+			depth += 1;
+			deepest = Some(call);
+			current = call.calls.first();
+		}
+		(depth, deepest)
+	}
+
+	#[test]
+	fn deepest_call_tree_is_serialized_on_the_rpc_server_stack() {
+		let serialized = on_stack(RPC_SERVER_STACK, || {
+			let traces = Formatter::format(deepest_call_list()).expect("traces to be formatted");
+			assert_eq!(traces.len(), 1);
+			assert_eq!(nested_depth(&traces).0, MAX_CALL_DEPTH);
+			// As the RPC server, which converts the response to a JSON value first.
+			let value = serde_json::to_value(&traces).expect("traces to be converted");
+			let from_value = serde_json::to_string(&value).expect("value to be serialized");
+			drop(value);
+			vec![
+				serde_json::to_string(&traces).expect("traces to be serialized"),
+				from_value,
+			]
+		});
+		for json in serialized {
+			assert_eq!(
+				json.matches("\"calls\"").count(),
+				MAX_CALL_DEPTH as usize - 1
+			);
+		}
+	}
+
+	#[test]
+	fn deepest_call_tree_is_nested_encoded_and_dropped_on_a_small_stack() {
+		let (depth, encoded_len, node_len) = on_stack(SMALL_STACK, || {
+			let traces = Formatter::format(deepest_call_list()).expect("traces to be formatted");
+			let (depth, deepest) = nested_depth(&traces);
+			let node_len = deepest.expect("tree not to be empty").encode().len();
+			let encoded_len = traces[0].encode().len();
+			drop(traces);
+			(depth, encoded_len, node_len)
+		});
+		assert_eq!(depth, MAX_CALL_DEPTH);
+		// The variant of the trace, then each call with the variant of its `Call` and the
+		// amount of its subcalls, which is encoded in a byte whether it is 0 or 1.
+		assert_eq!(encoded_len, 1 + MAX_CALL_DEPTH as usize * (1 + node_len));
+	}
+
+	#[test]
+	fn call_tree_encoding_round_trips() {
+		let mut listener = Listener::default();
+		let mut entry = BTreeMap::new();
+		for (index, trace_address) in [vec![], vec![0], vec![0, 0], vec![1]].iter().enumerate() {
+			entry.insert(
+				index as u32,
+				BlockscoutCall {
+					from: H160::repeat_byte(index as u8),
+					trace_address: trace_address.clone(),
+					subtraces: 0,
+					value: U256::zero(),
+					gas: 10_000.into(),
+					gas_used: 1_000.into(),
+					subcalls_truncated: index == 1,
+					inner: BlockscoutCallInner::Call {
+						call_type: CallType::Call,
+						to: H160::repeat_byte(0x01),
+						input: vec![index as u8],
+						res: CallResult::Output(Vec::new()),
+					},
+				},
+			);
+		}
+		listener.entries.push(entry);
+		let traces = Formatter::format(listener).expect("traces to be formatted");
+
+		let encoded = traces.encode();
+		assert_eq!(
+			Vec::<TransactionTrace>::decode(&mut &encoded[..]).expect("traces to be decoded"),
+			traces
+		);
+	}
+}
@@ -16,11 +16,13 @@
 
 pub mod blockscout;
 pub mod call_tracer;
+pub mod parity;
 pub mod raw;
 pub mod trace_filter;
 
 pub use blockscout::Formatter as Blockscout;
 pub use call_tracer::Formatter as CallTracer;
+pub use parity::Formatter as Parity;
 pub use raw::Formatter as Raw;
 pub use trace_filter::Formatter as TraceFilter;
 
@@ -0,0 +1,424 @@
+// Copyright 2019-2022 PureStake Inc.
+// This file is part of Moonbeam.
+
+// Moonbeam is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Moonbeam is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Conversion of the call tree of each transaction into the flat list of traces of the
+//! Parity/OpenEthereum `trace_*` RPC methods. The traces are listed in the order the calls were
+//! entered, each with the path of its call in the tree (`traceAddress`) and its amount of
+//! direct subcalls (`subtraces`). Transactions whose calls don't form a single tree are listed
+//! as `trace_filter` does.
+
+use super::call_tracer::{self, CallTracerCall, CallTracerInner};
+use super::trace_filter;
+use crate::listeners::call_list::Listener;
+use crate::types::{
+	block::{
+		RewardType, TransactionTrace, TransactionTraceAction, TransactionTraceOutput,
+		TransactionTraceResult,
+	},
+	single::Call,
+	CallResult, CallType, ContextType, CreateType,
+};
+use ethereum_types::{H160, H256, U256};
+use sp_std::vec::Vec;
+
+pub struct Formatter;
+
+impl super::ResponseFormatter for Formatter {
+	type Listener = Listener;
+	type Response = Vec<TransactionTrace>;
+
+	fn format(mut listener: Listener) -> Option<Vec<TransactionTrace>> {
+		// Remove empty BTreeMaps pushed to `entries`.
+		// I.e. InvalidNonce or other pallet_evm::runner exits
+		listener.entries.retain(|x| !x.is_empty());
+		let mut traces = Vec::new();
+		for (eth_tx_index, entry) in listener.entries.iter().enumerate() {
+			let mut tx_traces = match call_tracer::nest(entry) {
+				Some(Call::CallTracer(root)) => flatten(&root, eth_tx_index as u32),
+				_ => Vec::new(),
+			};
+			// Calls whose parent is missing are left out of the tree, and are then listed as
+			// the call list reports them.
+			if tx_traces.len() != entry.len() {
+				tx_traces = trace_filter::transaction_traces(entry, eth_tx_index as u32);
+			}
+			traces.append(&mut tx_traces);
+		}
+		Some(traces)
+	}
+}
+
+/// Flattens the call tree of the transaction at `transaction_position` in the block. The tree
+/// is walked with an explicit stack, so that its depth doesn't matter.
+pub fn flatten(root: &CallTracerCall, transaction_position: u32) -> Vec<TransactionTrace> {
+	let mut traces = Vec::new();
+	// Children are pushed in reverse order to be visited in the order they were entered.
+	let mut stack = vec![(root, Vec::new())];
+	while let Some((call, trace_address)) = stack.pop() {
+		for (index, child) in call.calls.iter().enumerate().rev() {
+			if let Call::CallTracer(child) = child {
+				let mut child_address = trace_address.clone();
+				child_address.push(index as u32);
+				stack.push((child, child_address));
+			}
+		}
+		let (action, output) = action_and_output(call);
+		traces.push(TransactionTrace {
+			action,
+			// Can't be known here, must be inserted upstream.
+			block_hash: H256::default(),
+			// Can't be known here, must be inserted upstream.
+			block_number: 0,
+			output,
+			subtraces: call.calls.len() as u32,
+			subcalls_truncated: call.subcalls_truncated,
+			trace_address,
+			// Can't be known here, must be inserted upstream.
+			transaction_hash: H256::default(),
+			transaction_position,
+		});
+	}
+	traces
+}
+
+/// Trace of a reward of the block or uncle author, which is not part of any transaction. The
+/// block and transaction fields are left to the caller.
+pub fn reward(author: H160, reward_type: RewardType, value: U256) -> TransactionTrace {
+	TransactionTrace {
+		action: TransactionTraceAction::Reward {
+			author,
+			reward_type,
+			value,
+		},
+		block_hash: H256::default(),
+		block_number: 0,
+		output: TransactionTraceOutput::Result(TransactionTraceResult::Reward),
+		subtraces: 0,
+		subcalls_truncated: false,
+		trace_address: Vec::new(),
+		transaction_hash: H256::default(),
+		transaction_position: 0,
+	}
+}
+
+fn action_and_output(call: &CallTracerCall) -> (TransactionTraceAction, TransactionTraceOutput) {
+	match &call.inner {
+		CallTracerInner::Call {
+			call_type,
+			to,
+			input,
+			res,
+			value,
+		} => (
+			TransactionTraceAction::Call {
+				call_type: match ContextType::from(call_type.clone()) {
+					Some(ContextType::Call(call_type)) => call_type,
+					_ => CallType::Call,
+				},
+				from: call.from,
+				gas: call.gas,
+				input: input.clone(),
+				to: *to,
+				value: value.unwrap_or_default(),
+			},
+			match res {
+				CallResult::Output(output) => {
+					TransactionTraceOutput::Result(TransactionTraceResult::Call {
+						gas_used: call.gas_used,
+						output: output.clone(),
+					})
+				}
+				CallResult::Error(error) => TransactionTraceOutput::Error(error.clone()),
+			},
+		),
+		CallTracerInner::Create {
+			input,
+			to,
+			output,
+			error,
+			value,
+			..
+		} => (
+			TransactionTraceAction::Create {
+				creation_method: CreateType::Create,
+				from: call.from,
+				gas: call.gas,
+				init: input.clone(),
+				value: *value,
+			},
+			match error {
+				Some(error) => TransactionTraceOutput::Error(error.clone()),
+				None => TransactionTraceOutput::Result(TransactionTraceResult::Create {
+					address: to.unwrap_or_default(),
+					code: output.clone().unwrap_or_default(),
+					gas_used: call.gas_used,
+				}),
+			},
+		),
+		CallTracerInner::SelfDestruct { to, value, .. } => (
+			TransactionTraceAction::Suicide {
+				address: call.from,
+				balance: *value,
+				refund_address: *to,
+			},
+			TransactionTraceOutput::Result(TransactionTraceResult::Suicide),
+		),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::formatters::{
+		blockscout::{BlockscoutCall, BlockscoutCallInner},
+		ResponseFormatter,
+	};
+	use evm_tracing_events::{
+		evm::CreateScheme,
+		gasometer::Snapshot,
+		runtime::{ExitReason, ExitRevert, ExitSucceed},
+		Context, EvmEvent, GasometerEvent,
+	};
+	use std::collections::btree_map::BTreeMap;
+
+	fn call(
+		from: u8,
+		to: u8,
+		call_type: CallType,
+		trace_address: Vec<u32>,
+		subtraces: u32,
+		res: CallResult,
+	) -> BlockscoutCall {
+		BlockscoutCall {
+			from: H160::repeat_byte(from),
+			trace_address,
+			subtraces,
+			value: U256::zero(),
+			gas: 10_000.into(),
+			gas_used: 1_000.into(),
+			subcalls_truncated: false,
+			inner: BlockscoutCallInner::Call {
+				call_type,
+				to: H160::repeat_byte(to),
+				input: vec![0x12, 0x34],
+				res,
+			},
+		}
+	}
+
+	fn record_gas(listener: &mut Listener, gas: u64) {
+		listener.gasometer_event(GasometerEvent::RecordCost {
+			cost: 0,
+			snapshot: Snapshot {
+				gas_limit: gas,
+				memory_gas: 0,
+				used_gas: 0,
+				refunded_gas: 0,
+			},
+		});
+	}
+
+	fn enter_call(listener: &mut Listener, from: u8, to: u8, is_static: bool) {
+		listener.evm_event(EvmEvent::Call {
+			code_address: H160::repeat_byte(to),
+			transfer: None,
+			input: vec![0x12, 0x34],
+			target_gas: None,
+			is_static,
+			context: Context {
+				address: H160::repeat_byte(to),
+				caller: H160::repeat_byte(from),
+				apparent_value: U256::zero(),
+			},
+		});
+	}
+
+	fn exit(listener: &mut Listener, reason: ExitReason, return_value: Vec<u8>) {
+		listener.evm_event(EvmEvent::Exit {
+			reason,
+			return_value,
+		});
+	}
+
+	#[test]
+	fn nested_call_transaction_matches_golden_trace() {
+		// 0x01 calls 0x02, which calls 0x03 (whose static call to 0x04 reverts), creates 0x05
+		// and then self destructs. Each frame is left with 10 000 gas after using 1 000, except
+		// the create, left with 5 000 gas after using 500.
+		let mut listener = Listener::default();
+		listener.evm_event(EvmEvent::TransactCall {
+			caller: H160::repeat_byte(0x01),
+			address: H160::repeat_byte(0x02),
+			value: U256::zero(),
+			data: vec![0x12, 0x34],
+			gas_limit: 11_000,
+		});
+		record_gas(&mut listener, 11_000);
+		enter_call(&mut listener, 0x01, 0x02, false);
+		enter_call(&mut listener, 0x02, 0x03, false);
+		record_gas(&mut listener, 11_000);
+		enter_call(&mut listener, 0x03, 0x04, true);
+		record_gas(&mut listener, 11_000);
+		record_gas(&mut listener, 10_000);
+		exit(
+			&mut listener,
+			ExitReason::Revert(ExitRevert::Reverted),
+			Vec::new(),
+		);
+		record_gas(&mut listener, 10_000);
+		exit(
+			&mut listener,
+			ExitReason::Succeed(ExitSucceed::Returned),
+			vec![0x56],
+		);
+		listener.evm_event(EvmEvent::Create {
+			caller: H160::repeat_byte(0x02),
+			address: H160::repeat_byte(0x05),
+			scheme: CreateScheme::Legacy {
+				caller: H160::repeat_byte(0x02),
+			},
+			value: 7.into(),
+			init_code: vec![0x60, 0x00],
+			target_gas: None,
+		});
+		record_gas(&mut listener, 5_500);
+		record_gas(&mut listener, 5_000);
+		exit(
+			&mut listener,
+			ExitReason::Succeed(ExitSucceed::Returned),
+			vec![0x00],
+		);
+		listener.evm_event(EvmEvent::Suicide {
+			address: H160::repeat_byte(0x02),
+			target: H160::repeat_byte(0x01),
+			balance: 3.into(),
+		});
+		record_gas(&mut listener, 10_000);
+		exit(
+			&mut listener,
+			ExitReason::Succeed(ExitSucceed::Stopped),
+			Vec::new(),
+		);
+		listener.finish_transaction();
+
+		let traces = Formatter::format(listener).expect("traces to be formatted");
+		let golden: serde_json::Value =
+			serde_json::from_str(include_str!("../../golden/parity_nested_call.json"))
+				.expect("golden trace to be valid JSON");
+		assert_eq!(
+			serde_json::to_value(traces).expect("traces to be serialized"),
+			golden
+		);
+	}
+
+	#[test]
+	fn calls_not_forming_a_tree_are_listed() {
+		// The parent of the second call, at `[1]`, is missing.
+		let mut entry = BTreeMap::new();
+		entry.insert(
+			0,
+			call(
+				0x01,
+				0x02,
+				CallType::Call,
+				vec![],
+				2,
+				CallResult::Output(vec![]),
+			),
+		);
+		entry.insert(
+			1,
+			call(
+				0x02,
+				0x03,
+				CallType::Call,
+				vec![1, 0],
+				0,
+				CallResult::Output(vec![]),
+			),
+		);
+		let mut listener = Listener::default();
+		listener.entries.push(entry);
+
+		let traces = Formatter::format(listener).expect("traces to be formatted");
+		let trace_addresses: Vec<_> = traces
+			.iter()
+			.map(|trace| (trace.trace_address.clone(), trace.subtraces))
+			.collect();
+		assert_eq!(trace_addresses, vec![(vec![], 2), (vec![1, 0], 0)]);
+	}
+
+	#[test]
+	fn reward_trace_has_null_result() {
+		let trace = reward(H160::repeat_byte(0x01), RewardType::Uncle, 2.into());
+
+		let value = serde_json::to_value(trace).expect("trace to be serialized");
+		assert_eq!(value["type"], "reward");
+		assert_eq!(
+			value["action"],
+			serde_json::json!({
+				"author": "0x0101010101010101010101010101010101010101",
+				"rewardType": "uncle",
+				"value": "0x2",
+			})
+		);
+		assert_eq!(value["result"], serde_json::Value::Null);
+		assert_eq!(value["subtraces"], 0);
+	}
+
+	#[test]
+	fn deepest_call_tree_is_flattened_on_a_small_stack() {
+		// Maximum depth of the EVM call stack.
+		const MAX_CALL_DEPTH: u32 = 1024;
+
+		let trace_addresses = std::thread::Builder::new()
+			.stack_size(128 * 1024)
+			.spawn(|| {
+				let mut entry = BTreeMap::new();
+				for depth in 0..MAX_CALL_DEPTH {
+					let subtraces = (depth + 1 < MAX_CALL_DEPTH) as u32;
+					entry.insert(
+						depth,
+						call(
+							0x01,
+							0x01,
+							CallType::Call,
+							vec![0; depth as usize],
+							subtraces,
+							CallResult::Output(Vec::new()),
+						),
+					);
+				}
+				let mut listener = Listener::default();
+				listener.entries.push(entry);
+
+				let traces = Formatter::format(listener).expect("traces to be formatted");
+				serde_json::to_string(&traces).expect("traces to be serialized");
+				traces
+					.into_iter()
+					.map(|trace| (trace.trace_address.len(), trace.subtraces))
+					.collect::<Vec<_>>()
+			})
+			.expect("thread to be spawned")
+			.join()
+			.expect("thread not to overflow its stack");
+
+		let expected: Vec<_> = (0..MAX_CALL_DEPTH as usize)
+			.map(|depth| (depth, (depth + 1 < MAX_CALL_DEPTH as usize) as u32))
+			.collect();
+		assert_eq!(trace_addresses, expected);
+	}
+}
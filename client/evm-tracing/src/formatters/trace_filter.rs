@@ -14,7 +14,7 @@
 // You should have received a copy of the GNU General Public License
 // along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
 
-use super::blockscout::BlockscoutCallInner as CallInner;
+use super::blockscout::{BlockscoutCall, BlockscoutCallInner as CallInner};
 use crate::listeners::call_list::Listener;
 use crate::types::{
 	block::{
@@ -23,6 +23,7 @@ use crate::types::{
 	CallResult, CreateResult, CreateType,
 };
 use ethereum_types::H256;
+use sp_std::{collections::btree_map::BTreeMap, vec::Vec};
 
 pub struct Formatter;
 
@@ -36,99 +37,243 @@ impl super::ResponseFormatter for Formatter {
 		listener.entries.retain(|x| !x.is_empty());
 		let mut traces = Vec::new();
 		for (eth_tx_index, entry) in listener.entries.iter().enumerate() {
-			let mut tx_traces: Vec<_> = entry
-				.into_iter()
-				.map(|(_, trace)| match trace.inner.clone() {
-					CallInner::Call {
-						input,
-						to,
-						res,
-						call_type,
-					} => TransactionTrace {
-						action: TransactionTraceAction::Call {
-							call_type,
-							from: trace.from,
-							gas: trace.gas,
-							input,
-							to,
-							value: trace.value,
-						},
-						// Can't be known here, must be inserted upstream.
-						block_hash: H256::default(),
-						// Can't be known here, must be inserted upstream.
-						block_number: 0,
-						output: match res {
-							CallResult::Output(output) => {
-								TransactionTraceOutput::Result(TransactionTraceResult::Call {
-									gas_used: trace.gas_used,
-									output,
-								})
-							}
-							CallResult::Error(error) => TransactionTraceOutput::Error(error),
-						},
-						subtraces: trace.subtraces,
-						trace_address: trace.trace_address.clone(),
-						// Can't be known here, must be inserted upstream.
-						transaction_hash: H256::default(),
-						transaction_position: eth_tx_index as u32,
-					},
-					CallInner::Create { init, res } => {
-						TransactionTrace {
-							action: TransactionTraceAction::Create {
-								creation_method: CreateType::Create,
-								from: trace.from,
-								gas: trace.gas,
-								init,
-								value: trace.value,
-							},
-							// Can't be known here, must be inserted upstream.
-							block_hash: H256::default(),
-							// Can't be known here, must be inserted upstream.
-							block_number: 0,
-							output: match res {
-								CreateResult::Success {
-									created_contract_address_hash,
-									created_contract_code,
-								} => {
-									TransactionTraceOutput::Result(TransactionTraceResult::Create {
-										gas_used: trace.gas_used,
-										code: created_contract_code,
-										address: created_contract_address_hash,
-									})
-								}
-								CreateResult::Error { error } => {
-									TransactionTraceOutput::Error(error)
-								}
-							},
-							subtraces: trace.subtraces,
-							trace_address: trace.trace_address.clone(),
-							// Can't be known here, must be inserted upstream.
-							transaction_hash: H256::default(),
-							transaction_position: eth_tx_index as u32,
-						}
+			traces.append(&mut transaction_traces(entry, eth_tx_index as u32));
+		}
+		Some(traces)
+	}
+}
+
+/// Traces of the calls of the transaction at `eth_tx_index` in the block, as listed by the call
+/// list, whether they form a single tree or not.
+pub fn transaction_traces(
+	entry: &BTreeMap<u32, BlockscoutCall>,
+	eth_tx_index: u32,
+) -> Vec<TransactionTrace> {
+	entry
+		.into_iter()
+		.map(|(_, trace)| match trace.inner.clone() {
+			CallInner::Call {
+				input,
+				to,
+				res,
+				call_type,
+			} => TransactionTrace {
+				action: TransactionTraceAction::Call {
+					call_type,
+					from: trace.from,
+					gas: trace.gas,
+					input,
+					to,
+					value: trace.value,
+				},
+				// Can't be known here, must be inserted upstream.
+				block_hash: H256::default(),
+				// Can't be known here, must be inserted upstream.
+				block_number: 0,
+				output: match res {
+					CallResult::Output(output) => {
+						TransactionTraceOutput::Result(TransactionTraceResult::Call {
+							gas_used: trace.gas_used,
+							output,
+						})
 					}
-					CallInner::SelfDestruct { balance, to } => TransactionTrace {
-						action: TransactionTraceAction::Suicide {
-							address: trace.from,
-							balance,
-							refund_address: to,
-						},
-						// Can't be known here, must be inserted upstream.
-						block_hash: H256::default(),
-						// Can't be known here, must be inserted upstream.
-						block_number: 0,
-						output: TransactionTraceOutput::Result(TransactionTraceResult::Suicide),
-						subtraces: trace.subtraces,
-						trace_address: trace.trace_address.clone(),
-						// Can't be known here, must be inserted upstream.
-						transaction_hash: H256::default(),
-						transaction_position: eth_tx_index as u32,
+					CallResult::Error(error) => TransactionTraceOutput::Error(error),
+				},
+				subtraces: trace.subtraces,
+				subcalls_truncated: trace.subcalls_truncated,
+				trace_address: trace.trace_address.clone(),
+				// Can't be known here, must be inserted upstream.
+				transaction_hash: H256::default(),
+				transaction_position: eth_tx_index,
+			},
+			CallInner::Create { init, res } => {
+				TransactionTrace {
+					action: TransactionTraceAction::Create {
+						creation_method: CreateType::Create,
+						from: trace.from,
+						gas: trace.gas,
+						init,
+						value: trace.value,
+					},
+					// Can't be known here, must be inserted upstream.
+					block_hash: H256::default(),
+					// Can't be known here, must be inserted upstream.
+					block_number: 0,
+					output: match res {
+						CreateResult::Success {
+							created_contract_address_hash,
+							created_contract_code,
+						} => TransactionTraceOutput::Result(TransactionTraceResult::Create {
+							gas_used: trace.gas_used,
+							code: created_contract_code,
+							address: created_contract_address_hash,
+						}),
+						CreateResult::Error { error } => TransactionTraceOutput::Error(error),
 					},
-				})
-				.collect();
+					subtraces: trace.subtraces,
+					subcalls_truncated: trace.subcalls_truncated,
+					trace_address: trace.trace_address.clone(),
+					// Can't be known here, must be inserted upstream.
+					transaction_hash: H256::default(),
+					transaction_position: eth_tx_index,
+				}
+			}
+			CallInner::SelfDestruct { balance, to } => TransactionTrace {
+				action: TransactionTraceAction::Suicide {
+					address: trace.from,
+					balance,
+					refund_address: to,
+				},
+				// Can't be known here, must be inserted upstream.
+				block_hash: H256::default(),
+				// Can't be known here, must be inserted upstream.
+				block_number: 0,
+				output: TransactionTraceOutput::Result(TransactionTraceResult::Suicide),
+				subtraces: trace.subtraces,
+				subcalls_truncated: trace.subcalls_truncated,
+				trace_address: trace.trace_address.clone(),
+				// Can't be known here, must be inserted upstream.
+				transaction_hash: H256::default(),
+				transaction_position: eth_tx_index,
+			},
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::formatters::{
+		blockscout::{BlockscoutCall as Call, BlockscoutCallInner as CallInner},
+		ResponseFormatter,
+	};
+	use crate::types::{CallResult, CallType};
+	use ethereum_types::{H160, U256};
+	use serde_json::json;
+	use std::collections::btree_map::BTreeMap;
 
-			traces.append(&mut tx_traces);
+	fn call(from: u8, to: u8, trace_address: Vec<u32>, subtraces: u32) -> Call {
+		Call {
+			from: H160::repeat_byte(from),
+			trace_address,
+			subtraces,
+			value: U256::zero(),
+			gas: 10_000.into(),
+			gas_used: 1_000.into(),
+			subcalls_truncated: false,
+			inner: CallInner::Call {
+				call_type: CallType::Call,
+				to: H160::repeat_byte(to),
+				input: vec![0x12, 0x34],
+				res: CallResult::Output(vec![]),
+			},
 		}
-		Some(traces)
+	}
+
+	#[test]
+	fn nested_calls_are_flattened() {
+		// 0x01 calls 0x02, which calls 0x03 (calling 0x04) and then self destructs.
+		let mut listener = Listener::default();
+		let mut entry = BTreeMap::new();
+		entry.insert(0, call(0x01, 0x02, vec![], 2));
+		entry.insert(1, call(0x02, 0x03, vec![0], 1));
+		entry.insert(2, call(0x03, 0x04, vec![0, 0], 0));
+		entry.insert(
+			3,
+			Call {
+				from: H160::repeat_byte(0x02),
+				trace_address: vec![1],
+				subtraces: 0,
+				value: U256::zero(),
+				gas: U256::zero(),
+				gas_used: U256::zero(),
+				subcalls_truncated: false,
+				inner: CallInner::SelfDestruct {
+					balance: 5.into(),
+					to: H160::repeat_byte(0x01),
+				},
+			},
+		);
+		listener.entries.push(entry);
+
+		let traces = Formatter::format(listener).expect("traces to be formatted");
+		let call_trace = |from: &str, to: &str, trace_address: Vec<u32>, subtraces: u32| {
+			json!({
+				"type": "call",
+				"action": {
+					"callType": "call",
+					"from": from,
+					"gas": "0x2710",
+					"input": "0x1234",
+					"to": to,
+					"value": "0x0",
+				},
+				"result": {
+					"gasUsed": "0x3e8",
+					"output": "0x",
+				},
+				"blockHash": "0x0000000000000000000000000000000000000000000000000000000000000000",
+				"blockNumber": 0,
+				"subtraces": subtraces,
+				"traceAddress": trace_address,
+				"transactionHash": "0x0000000000000000000000000000000000000000000000000000000000000000",
+				"transactionPosition": 0,
+			})
+		};
+		assert_eq!(
+			serde_json::to_value(traces).expect("traces to be serialized"),
+			json!([
+				call_trace(
+					"0x0101010101010101010101010101010101010101",
+					"0x0202020202020202020202020202020202020202",
+					vec![],
+					2
+				),
+				call_trace(
+					"0x0202020202020202020202020202020202020202",
+					"0x0303030303030303030303030303030303030303",
+					vec![0],
+					1
+				),
+				call_trace(
+					"0x0303030303030303030303030303030303030303",
+					"0x0404040404040404040404040404040404040404",
+					vec![0, 0],
+					0
+				),
+				{
+					"type": "suicide",
+					"action": {
+						"address": "0x0202020202020202020202020202020202020202",
+						"balance": "0x5",
+						"refundAddress": "0x0101010101010101010101010101010101010101",
+					},
+					"result": null,
+					"blockHash": "0x0000000000000000000000000000000000000000000000000000000000000000",
+					"blockNumber": 0,
+					"subtraces": 0,
+					"traceAddress": [1],
+					"transactionHash": "0x0000000000000000000000000000000000000000000000000000000000000000",
+					"transactionPosition": 0,
+				},
+			])
+		);
+	}
+
+	#[test]
+	fn calls_not_forming_a_tree_are_listed() {
+		// The parent of the second call, at `[1]`, is missing.
+		let mut listener = Listener::default();
+		let mut entry = BTreeMap::new();
+		entry.insert(0, call(0x01, 0x02, vec![], 2));
+		entry.insert(1, call(0x02, 0x03, vec![1, 0], 0));
+		listener.entries.push(entry);
+
+		let traces = Formatter::format(listener).expect("traces to be formatted");
+		let trace_addresses: Vec<_> = traces
+			.iter()
+			.map(|trace| (trace.trace_address.clone(), trace.subtraces))
+			.collect();
+		assert_eq!(trace_addresses, vec![(vec![], 2), (vec![1, 0], 0)]);
 	}
 }
@@ -79,6 +79,42 @@ pub struct Listener {
 	/// True if only the `GasometerEvent::RecordTransaction` event has been received.
 	/// Allow to correctly handle transactions that cannot pay for the tx data in Legacy mode.
 	record_transaction_event_only: bool,
+
+	/// Maximum amount of nested contexts tracked. Deeper contexts are merged into their parent.
+	max_tracked_frames: Option<usize>,
+	/// Amount of nested contexts currently entered beyond `max_tracked_frames`.
+	untracked_frames: u32,
+	/// True if some contexts have not been tracked due to `max_tracked_frames`.
+	pub frames_truncated: bool,
+	/// The deepest tracked context just entered its first untracked context, whose forwarded
+	/// gas is recorded by the next `GasometerEvent::RecordCost` of the tracked one.
+	untracked_forwarded_gas: bool,
+
+	/// Receives the frames opened and closed while the call list is built, if set.
+	frame_updates: Option<Box<dyn FnMut(FrameUpdate) + Send>>,
+}
+
+/// Update of the call list sent while it is built, see `Listener::with_frame_updates`. Frames
+/// are identified by the index of their entry in the call list.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum FrameUpdate {
+	/// A call or create frame is entered. `parent_id` is `None` for the transaction frame.
+	FrameOpened {
+		frame_id: u32,
+		parent_id: Option<u32>,
+		context_type: ContextType,
+		from: H160,
+		to: H160,
+		value: U256,
+		input: Vec<u8>,
+	},
+	/// The frame exited. `error` is set if it didn't succeed.
+	FrameClosed {
+		frame_id: u32,
+		gas_used: U256,
+		output: Vec<u8>,
+		error: Option<Vec<u8>>,
+	},
 }
 
 struct Context {
@@ -93,6 +129,8 @@ struct Context {
 
 	gas: u64,
 	start_gas: Option<u64>,
+	// Subcalls not tracked due to `max_tracked_frames`.
+	subcalls_truncated: bool,
 
 	// input / data
 	data: Vec<u8>,
@@ -116,11 +154,37 @@ impl Default for Listener {
 			skip_next_context: false,
 			call_list_first_transaction: true,
 			record_transaction_event_only: false,
+
+			max_tracked_frames: None,
+			untracked_frames: 0,
+			frames_truncated: false,
+			untracked_forwarded_gas: false,
+
+			frame_updates: None,
 		}
 	}
 }
 
 impl Listener {
+	/// Bound the amount of nested contexts tracked, which bounds the memory used by pathological
+	/// deep recursions. Contexts nested deeper are not part of the call list: their events are
+	/// merged into the deepest tracked context and `frames_truncated` is set.
+	pub fn with_max_tracked_frames(mut self, max_tracked_frames: usize) -> Self {
+		self.max_tracked_frames = Some(max_tracked_frames);
+		self
+	}
+
+	/// Incremental mode: report each frame to `frame_updates` when it is opened and closed,
+	/// allowing to render the call list while it is built. Frames are opened before their
+	/// subcalls and closed after them.
+	pub fn with_frame_updates<F: FnMut(FrameUpdate) + Send + 'static>(
+		mut self,
+		frame_updates: F,
+	) -> Self {
+		self.frame_updates = Some(Box::new(frame_updates));
+		self
+	}
+
 	pub fn using<R, F: FnOnce() -> R>(&mut self, f: F) -> R {
 		evm_tracing_events::using(self, f)
 	}
@@ -129,12 +193,26 @@ impl Listener {
 	/// Allow to insert the pending entries regardless of which runtime version
 	/// is used (with or without EvmEvent::Exit).
 	pub fn finish_transaction(&mut self) {
+		self.untracked_frames = 0;
+		self.untracked_forwarded_gas = false;
+
 		// remove any leftover context
 		let mut context_stack = vec![];
 		core::mem::swap(&mut self.context_stack, &mut context_stack);
 
 		// if there is a left over there have been an early exit.
 		// we generate an entry from it and discord any inner context.
+		// Their frames are closed without entry in incremental mode.
+		if let Some(frame_updates) = &mut self.frame_updates {
+			for inner in context_stack.iter().skip(1).rev() {
+				frame_updates(FrameUpdate::FrameClosed {
+					frame_id: inner.entries_index,
+					gas_used: 0.into(),
+					output: vec![],
+					error: Some(b"early exit".to_vec()),
+				});
+			}
+		}
 		if let Some(context) = context_stack.into_iter().next() {
 			let mut gas_used = context.start_gas.unwrap_or(0) - context.gas;
 			if context.entries_index == 0 {
@@ -154,6 +232,7 @@ impl Listener {
 						value: context.value,
 						gas: context.gas.into(),
 						gas_used: gas_used.into(),
+						subcalls_truncated: context.subcalls_truncated,
 						inner: CallInner::Call {
 							call_type,
 							to: context.to,
@@ -173,6 +252,7 @@ impl Listener {
 						subtraces: context.subtraces,
 						gas: context.gas.into(),
 						gas_used: gas_used.into(),
+						subcalls_truncated: context.subcalls_truncated,
 						from: context.from,
 						inner: CallInner::Create {
 							init: context.data,
@@ -182,6 +262,7 @@ impl Listener {
 				}
 			};
 
+			self.close_frame(context.entries_index, &entry);
 			self.insert_entry(context.entries_index, entry);
 			// Since only this context/entry is kept, we need update entries_next_index too.
 			self.entries_next_index = context.entries_index + 1;
@@ -202,6 +283,7 @@ impl Listener {
 				value: 0.into(),
 				gas: 0.into(),
 				gas_used: 0.into(),
+				subcalls_truncated: false,
 				inner: CallInner::Call {
 					call_type: CallType::Call,
 					to: H160::repeat_byte(0),
@@ -217,9 +299,20 @@ impl Listener {
 
 	pub fn gasometer_event(&mut self, event: GasometerEvent) {
 		match event {
+			GasometerEvent::RecordCost { cost, snapshot } if self.untracked_forwarded_gas => {
+				// Gas forwarded by the deepest tracked context to its untracked subcall.
+				self.untracked_forwarded_gas = false;
+				if let Some(context) = self.context_stack.last_mut() {
+					context.gas = snapshot.gas().saturating_sub(cost);
+				}
+			}
 			GasometerEvent::RecordCost { snapshot, .. }
 			| GasometerEvent::RecordDynamicCost { snapshot, .. }
 			| GasometerEvent::RecordStipend { snapshot, .. } => {
+				// Gasometers of untracked contexts must not affect the gas of their parent.
+				if self.untracked_frames > 0 {
+					return;
+				}
 				if let Some(context) = self.context_stack.last_mut() {
 					if context.start_gas.is_none() {
 						context.start_gas = Some(snapshot.gas());
@@ -247,6 +340,16 @@ impl Listener {
 					self.call_type = Some(call_type)
 				}
 			}
+			RuntimeEvent::StepResult {
+				result: Err(Capture::Exit(_)),
+				..
+			} if self.untracked_frames > 0 => {
+				// `EvmEvent::Exit` will follow in EarlyTransact mode.
+				if let TracingVersion::Legacy = self.version {
+					self.untracked_frames -= 1;
+					self.untracked_forwarded_gas = false;
+				}
+			}
 			RuntimeEvent::StepResult {
 				result: Err(Capture::Exit(reason)),
 				return_value,
@@ -285,7 +388,7 @@ impl Listener {
 			} => {
 				self.record_transaction_event_only = false;
 				self.version = TracingVersion::EarlyTransact;
-				self.context_stack.push(Context {
+				self.open_context(Context {
 					entries_index: self.entries_next_index,
 
 					context_type: ContextType::Call(CallType::Call),
@@ -297,6 +400,7 @@ impl Listener {
 
 					gas: 0,
 					start_gas: None,
+					subcalls_truncated: false,
 
 					data,
 					to: address,
@@ -315,7 +419,7 @@ impl Listener {
 			} => {
 				self.record_transaction_event_only = false;
 				self.version = TracingVersion::EarlyTransact;
-				self.context_stack.push(Context {
+				self.open_context(Context {
 					entries_index: self.entries_next_index,
 
 					context_type: ContextType::Create,
@@ -327,6 +431,7 @@ impl Listener {
 
 					gas: 0,
 					start_gas: None,
+					subcalls_truncated: false,
 
 					data: init_code,
 					to: address,
@@ -345,7 +450,7 @@ impl Listener {
 			} => {
 				self.record_transaction_event_only = false;
 				self.version = TracingVersion::EarlyTransact;
-				self.context_stack.push(Context {
+				self.open_context(Context {
 					entries_index: self.entries_next_index,
 
 					context_type: ContextType::Create,
@@ -357,6 +462,7 @@ impl Listener {
 
 					gas: 0,
 					start_gas: None,
+					subcalls_truncated: false,
 
 					data: init_code,
 					to: address,
@@ -380,7 +486,11 @@ impl Listener {
 					(Some(call_type), _) => call_type,
 				};
 
-				if !self.skip_next_context {
+				if self.skip_next_context {
+					self.skip_next_context = false;
+				} else if self.enter_untracked_frame() {
+					// Merged into the parent context.
+				} else {
 					let trace_address = if let Some(context) = self.context_stack.last_mut() {
 						let mut trace_address = context.trace_address.clone();
 						trace_address.push(context.subtraces);
@@ -390,7 +500,7 @@ impl Listener {
 						vec![]
 					};
 
-					self.context_stack.push(Context {
+					self.open_context(Context {
 						entries_index: self.entries_next_index,
 
 						context_type: ContextType::Call(call_type),
@@ -402,14 +512,13 @@ impl Listener {
 
 						gas: 0,
 						start_gas: None,
+						subcalls_truncated: false,
 
 						data: input.to_vec(),
 						to: context.address,
 					});
 
 					self.entries_next_index += 1;
-				} else {
-					self.skip_next_context = false;
 				}
 			}
 
@@ -423,7 +532,11 @@ impl Listener {
 			} => {
 				self.record_transaction_event_only = false;
 
-				if !self.skip_next_context {
+				if self.skip_next_context {
+					self.skip_next_context = false;
+				} else if self.enter_untracked_frame() {
+					// Merged into the parent context.
+				} else {
 					let trace_address = if let Some(context) = self.context_stack.last_mut() {
 						let mut trace_address = context.trace_address.clone();
 						trace_address.push(context.subtraces);
@@ -433,7 +546,7 @@ impl Listener {
 						vec![]
 					};
 
-					self.context_stack.push(Context {
+					self.open_context(Context {
 						entries_index: self.entries_next_index,
 
 						context_type: ContextType::Create,
@@ -445,14 +558,13 @@ impl Listener {
 
 						gas: 0,
 						start_gas: None,
+						subcalls_truncated: false,
 
 						data: init_code.to_vec(),
 						to: address,
 					});
 
 					self.entries_next_index += 1;
-				} else {
-					self.skip_next_context = false;
 				}
 			}
 			EvmEvent::Suicide {
@@ -481,6 +593,7 @@ impl Listener {
 						value: 0.into(),
 						gas: 0.into(),
 						gas_used: 0.into(),
+						subcalls_truncated: false,
 						inner: CallInner::SelfDestruct {
 							to: target,
 							balance,
@@ -497,6 +610,12 @@ impl Listener {
 
 				self.record_transaction_event_only = false;
 
+				if self.untracked_frames > 0 {
+					self.untracked_frames -= 1;
+					self.untracked_forwarded_gas = false;
+					return;
+				}
+
 				let entry = self
 					.step_result_entry
 					.take()
@@ -512,6 +631,27 @@ impl Listener {
 		}
 	}
 
+	/// Returns true if a new context must not be tracked due to `max_tracked_frames`, in which
+	/// case it is accounted as untracked.
+	fn enter_untracked_frame(&mut self) -> bool {
+		let at_capacity = self
+			.max_tracked_frames
+			.map_or(false, |max| self.context_stack.len() >= max);
+		if self.untracked_frames > 0 || at_capacity {
+			if self.untracked_frames == 0 {
+				self.untracked_forwarded_gas = true;
+				if let Some(context) = self.context_stack.last_mut() {
+					context.subcalls_truncated = true;
+				}
+			}
+			self.untracked_frames += 1;
+			self.frames_truncated = true;
+			true
+		} else {
+			false
+		}
+	}
+
 	fn insert_entry(&mut self, key: u32, entry: Call) {
 		if self.entries.is_empty() {
 			self.entries.push(BTreeMap::new());
@@ -531,72 +671,123 @@ impl Listener {
 				gas_used += self.transaction_cost;
 			}
 
-			Some((
-				context.entries_index,
-				match context.context_type {
-					ContextType::Call(call_type) => {
-						let res = match &reason {
-							ExitReason::Succeed(ExitSucceed::Returned) => {
-								CallResult::Output(return_value.to_vec())
-							}
-							ExitReason::Succeed(_) => CallResult::Output(vec![]),
-							ExitReason::Error(error) => CallResult::Error(error_message(error)),
-
-							ExitReason::Revert(_) => {
-								CallResult::Error(b"execution reverted".to_vec())
-							}
-							ExitReason::Fatal(_) => CallResult::Error(vec![]),
-						};
-
-						Call {
-							from: context.from,
-							trace_address: context.trace_address,
-							subtraces: context.subtraces,
-							value: context.value,
-							gas: context.gas.into(),
-							gas_used: gas_used.into(),
-							inner: CallInner::Call {
-								call_type,
-								to: context.to,
-								input: context.data,
-								res,
-							},
+			let entry = match context.context_type {
+				ContextType::Call(call_type) => {
+					let res = match &reason {
+						ExitReason::Succeed(ExitSucceed::Returned) => {
+							CallResult::Output(return_value.to_vec())
 						}
+						ExitReason::Succeed(_) => CallResult::Output(vec![]),
+						ExitReason::Error(error) => CallResult::Error(error_message(error)),
+
+						ExitReason::Revert(_) => CallResult::Error(b"execution reverted".to_vec()),
+						ExitReason::Fatal(_) => CallResult::Error(vec![]),
+					};
+
+					Call {
+						from: context.from,
+						trace_address: context.trace_address,
+						subtraces: context.subtraces,
+						value: context.value,
+						gas: context.gas.into(),
+						gas_used: gas_used.into(),
+						subcalls_truncated: context.subcalls_truncated,
+						inner: CallInner::Call {
+							call_type,
+							to: context.to,
+							input: context.data,
+							res,
+						},
 					}
-					ContextType::Create => {
-						let res = match &reason {
-							ExitReason::Succeed(_) => CreateResult::Success {
-								created_contract_address_hash: context.to,
-								created_contract_code: return_value.to_vec(),
-							},
-							ExitReason::Error(error) => CreateResult::Error {
-								error: error_message(error),
-							},
-							ExitReason::Revert(_) => CreateResult::Error {
-								error: b"execution reverted".to_vec(),
-							},
-							ExitReason::Fatal(_) => CreateResult::Error { error: vec![] },
-						};
+				}
+				ContextType::Create => {
+					let res = match &reason {
+						ExitReason::Succeed(_) => CreateResult::Success {
+							created_contract_address_hash: context.to,
+							created_contract_code: return_value.to_vec(),
+						},
+						ExitReason::Error(error) => CreateResult::Error {
+							error: error_message(error),
+						},
+						ExitReason::Revert(_) => CreateResult::Error {
+							error: b"execution reverted".to_vec(),
+						},
+						ExitReason::Fatal(_) => CreateResult::Error { error: vec![] },
+					};
 
-						Call {
-							value: context.value,
-							trace_address: context.trace_address,
-							subtraces: context.subtraces,
-							gas: context.gas.into(),
-							gas_used: gas_used.into(),
-							from: context.from,
-							inner: CallInner::Create {
-								init: context.data,
-								res,
-							},
-						}
+					Call {
+						value: context.value,
+						trace_address: context.trace_address,
+						subtraces: context.subtraces,
+						gas: context.gas.into(),
+						gas_used: gas_used.into(),
+						subcalls_truncated: context.subcalls_truncated,
+						from: context.from,
+						inner: CallInner::Create {
+							init: context.data,
+							res,
+						},
 					}
-				},
-			))
+				}
+			};
+			self.close_frame(context.entries_index, &entry);
+			Some((context.entries_index, entry))
 		} else {
 			None
 		}
 	}
+
+	/// Enters the context, reporting it as opened in incremental mode.
+	fn open_context(&mut self, context: Context) {
+		if let Some(frame_updates) = &mut self.frame_updates {
+			frame_updates(FrameUpdate::FrameOpened {
+				frame_id: context.entries_index,
+				parent_id: self.context_stack.last().map(|parent| parent.entries_index),
+				context_type: context.context_type,
+				from: context.from,
+				to: context.to,
+				value: context.value,
+				input: context.data.clone(),
+			});
+		}
+		self.context_stack.push(context);
+	}
+
+	/// Reports the frame as closed in incremental mode, with the result of its entry.
+	fn close_frame(&mut self, frame_id: u32, entry: &Call) {
+		let frame_updates = match &mut self.frame_updates {
+			Some(frame_updates) => frame_updates,
+			None => return,
+		};
+		let (output, error) = match &entry.inner {
+			CallInner::Call {
+				res: CallResult::Output(output),
+				..
+			} => (output.clone(), None),
+			CallInner::Create {
+				res: CreateResult::Success {
+					created_contract_code,
+					..
+				},
+				..
+			} => (created_contract_code.clone(), None),
+			CallInner::Call {
+				res: CallResult::Error(error),
+				..
+			}
+			| CallInner::Create {
+				res: CreateResult::Error { error },
+				..
+			} => (vec![], Some(error.clone())),
+			CallInner::SelfDestruct { .. } => (vec![], None),
+		};
+		frame_updates(FrameUpdate::FrameClosed {
+			frame_id,
+			gas_used: entry.gas_used,
+			output,
+			error,
+		});
+	}
 }
 
 fn error_message(error: &ExitError) -> Vec<u8> {
@@ -634,6 +825,8 @@ impl ListenerT for Listener {
 					self.call_list_first_transaction = false;
 				}
 			}
+			// Events derived by the runtime tracer are not used to build the call list.
+			Event::Tracer(_) => (),
 		};
 	}
 
@@ -1111,4 +1304,154 @@ mod tests {
 		// There are 5 main nested calls for a total of 56 elements in the callstack: 1 main + 55 nested.
 		assert_eq!(listener.entries[0].len(), (depth * (subdepth + 1)) + 1);
 	}
+
+	#[test]
+	fn max_tracked_frames_truncates_deep_recursion() {
+		let depth = 10;
+		let mut listener = Listener::default().with_max_tracked_frames(3);
+		// Main
+		do_transact_call_event(&mut listener);
+		do_gasometer_event(&mut listener);
+		do_evm_call_event(&mut listener);
+		do_runtime_step_event(&mut listener);
+		do_runtime_step_result_event(&mut listener);
+		// 10 recursive calls
+		for _ in 0..depth {
+			do_evm_call_event(&mut listener);
+			do_runtime_step_event(&mut listener);
+			do_runtime_step_result_event(&mut listener);
+		}
+		for _ in 0..depth {
+			do_exit_event(&mut listener);
+		}
+		// Main exit
+		do_exit_event(&mut listener);
+		listener.finish_transaction();
+		assert!(listener.frames_truncated);
+		assert_eq!(listener.entries.len(), 1);
+		assert_eq!(listener.entries[0].len(), 3);
+		let deepest = listener.entries[0].values().last().unwrap();
+		assert_eq!(deepest.trace_address, vec![0, 0]);
+		assert_eq!(deepest.subtraces, 0);
+	}
+
+	#[test]
+	fn forwarded_gas_of_untracked_subcall_is_charged_to_deepest_tracked_frame() {
+		let record_cost = |cost: u64, gas_limit: u64, used_gas: u64| GasometerEvent::RecordCost {
+			cost,
+			snapshot: Snapshot {
+				gas_limit,
+				memory_gas: 0,
+				used_gas,
+				refunded_gas: 0,
+			},
+		};
+		let mut listener = Listener::default().with_max_tracked_frames(2);
+		// Main
+		do_transact_call_event(&mut listener);
+		do_gasometer_event(&mut listener);
+		do_evm_call_event(&mut listener);
+		listener.gasometer_event(record_cost(100, 100_000, 0));
+		// Tracked subcall, with its forwarded gas and its own first cost.
+		do_evm_call_event(&mut listener);
+		listener.gasometer_event(record_cost(50_000, 100_000, 100));
+		listener.gasometer_event(record_cost(3, 50_000, 0));
+		// Untracked subcall running out of gas, so that no stipend is returned to its caller.
+		do_evm_call_event(&mut listener);
+		listener.gasometer_event(record_cost(40_000, 50_000, 3));
+		listener.gasometer_event(record_cost(5, 40_000, 0));
+		do_exit_event(&mut listener);
+		// Exits of the tracked subcall and main.
+		do_exit_event(&mut listener);
+		do_exit_event(&mut listener);
+		listener.finish_transaction();
+
+		assert!(listener.frames_truncated);
+		let entry = &listener.entries[0];
+		assert_eq!(entry.len(), 2);
+		assert!(!entry[&0].subcalls_truncated);
+		let deepest = &entry[&1];
+		assert!(deepest.subcalls_truncated);
+		assert_eq!(deepest.gas, U256::from(50_000 - 3 - 40_000));
+		assert_eq!(deepest.gas_used, U256::from(99_900 - (50_000 - 3 - 40_000)));
+		let json = serde_json::to_value(deepest).expect("entry to be serialized");
+		assert_eq!(json["subcallsTruncated"], true);
+	}
+
+	#[test]
+	fn frames_are_not_truncated_below_max_tracked_frames() {
+		let mut listener = Listener::default().with_max_tracked_frames(3);
+		do_transact_call_event(&mut listener);
+		do_gasometer_event(&mut listener);
+		do_evm_call_event(&mut listener);
+		do_evm_call_event(&mut listener);
+		do_exit_event(&mut listener);
+		do_exit_event(&mut listener);
+		listener.finish_transaction();
+		assert!(!listener.frames_truncated);
+		assert_eq!(listener.entries[0].len(), 2);
+	}
+
+	#[test]
+	fn frame_updates_follow_the_call_tree() {
+		let updates = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+		let sink = std::sync::Arc::clone(&updates);
+		let mut listener =
+			Listener::default().with_frame_updates(move |update| sink.lock().unwrap().push(update));
+		// Each frame returns a byte of its own.
+		fn do_success_exit_event(listener: &mut Listener, output: u8) {
+			listener.evm_event(EvmEvent::Exit {
+				reason: ExitReason::Succeed(ExitSucceed::Returned),
+				return_value: vec![output],
+			});
+		}
+		// Main
+		do_transact_call_event(&mut listener);
+		do_gasometer_event(&mut listener);
+		do_evm_call_event(&mut listener);
+		// Nested call with a subcall.
+		do_evm_call_event(&mut listener);
+		do_evm_call_event(&mut listener);
+		do_success_exit_event(&mut listener, 2);
+		do_success_exit_event(&mut listener, 1);
+		// Second nested call, failing.
+		do_evm_call_event(&mut listener);
+		do_exit_event(&mut listener);
+		// Main exit
+		do_success_exit_event(&mut listener, 0);
+		listener.finish_transaction();
+
+		let updates: Vec<_> = updates
+			.lock()
+			.unwrap()
+			.iter()
+			.map(|update| match update {
+				FrameUpdate::FrameOpened {
+					frame_id,
+					parent_id,
+					..
+				} => (true, *frame_id, *parent_id, vec![], None),
+				FrameUpdate::FrameClosed {
+					frame_id,
+					output,
+					error,
+					..
+				} => (false, *frame_id, None, output.clone(), error.clone()),
+			})
+			.collect();
+		assert_eq!(
+			updates,
+			vec![
+				(true, 0, None, vec![], None),
+				(true, 1, Some(0), vec![], None),
+				(true, 2, Some(1), vec![], None),
+				(false, 2, None, vec![2], None),
+				(false, 1, None, vec![1], None),
+				(true, 3, Some(0), vec![], None),
+				(false, 3, None, vec![], Some(b"out of gas".to_vec())),
+				(false, 0, None, vec![0], None),
+			]
+		);
+		assert_eq!(listener.entries[0].len(), 4);
+	}
 }
@@ -31,6 +31,9 @@ pub struct Listener {
 
 	new_context: bool,
 	context_stack: Vec<Context>,
+	/// Index in `step_logs` of a call opcode that trapped. The gas forwarded to the callee is
+	/// recorded after the step result and is part of the opcode cost.
+	pending_call_cost: Option<usize>,
 
 	pub step_logs: Vec<RawStepLog>,
 	pub return_value: Vec<u8>,
@@ -51,8 +54,8 @@ struct Step {
 	opcode: Vec<u8>,
 	/// Depth of the context.
 	depth: usize,
-	/// Remaining gas.
-	gas: u64,
+	/// Remaining gas before the opcode is executed.
+	gas: Option<u64>,
 	/// Gas cost of the following opcode.
 	gas_cost: u64,
 	/// Program counter position.
@@ -76,6 +79,7 @@ impl Listener {
 
 			new_context: false,
 			context_stack: vec![],
+			pending_call_cost: None,
 		}
 	}
 
@@ -92,11 +96,19 @@ impl Listener {
 				self.final_gas = cost;
 			}
 			GasometerEvent::RecordCost { cost, snapshot } => {
+				// First cost recorded after a call opcode trapped is the gas forwarded to the
+				// callee, recorded by the caller.
+				if let Some(index) = self.pending_call_cost.take() {
+					if let Some(step_log) = self.step_logs.get_mut(index) {
+						step_log.gas_cost = step_log.gas_cost.saturating_add(cost.into());
+					}
+				}
+
 				if let Some(context) = self.context_stack.last_mut() {
 					// Register opcode cost. (ignore costs not between Step and StepResult)
 					if let Some(step) = &mut context.current_step {
-						step.gas = snapshot.gas();
-						step.gas_cost = cost;
+						step.gas.get_or_insert(snapshot.gas());
+						step.gas_cost += cost;
 					}
 
 					self.final_gas = snapshot.used_gas;
@@ -108,8 +120,8 @@ impl Listener {
 				if let Some(context) = self.context_stack.last_mut() {
 					// Register opcode cost. (ignore costs not between Step and StepResult)
 					if let Some(step) = &mut context.current_step {
-						step.gas = snapshot.gas();
-						step.gas_cost = gas_cost;
+						step.gas.get_or_insert(snapshot.gas());
+						step.gas_cost += gas_cost;
 					}
 
 					self.final_gas = snapshot.used_gas;
@@ -130,6 +142,9 @@ impl Listener {
 				stack,
 				memory,
 			} => {
+				// The call cost is complete once a new step is executed.
+				self.pending_call_cost = None;
+
 				// Create a context if needed.
 				if self.new_context {
 					self.new_context = false;
@@ -149,7 +164,7 @@ impl Listener {
 					context.current_step = Some(Step {
 						opcode,
 						depth,
-						gas: 0,      // 0 for now, will add with gas events
+						gas: None,   // None for now, will add with gas events
 						gas_cost: 0, // 0 for now, will add with gas events
 						position: *position.as_ref().unwrap_or(&0) as usize,
 						memory: if self.disable_memory {
@@ -205,7 +220,7 @@ impl Listener {
 
 						self.step_logs.push(RawStepLog {
 							depth: depth.into(),
-							gas: gas.into(),
+							gas: gas.unwrap_or(0).into(),
 							gas_cost: gas_cost.into(),
 							memory,
 							op: opcode,
@@ -263,6 +278,10 @@ impl Listener {
 						}
 					}
 					Err(Capture::Trap(opcode)) if ContextType::from(opcode.clone()).is_some() => {
+						if let Some(ContextType::Call(_)) = ContextType::from(opcode) {
+							// The step of the call opcode is the last pushed log.
+							self.pending_call_cost = self.step_logs.len().checked_sub(1);
+						}
 						self.new_context = true;
 					}
 					_ => (),
@@ -307,3 +326,81 @@ impl ListenerT for Listener {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use evm_tracing_events::{gasometer::Snapshot, Context as EvmContext};
+
+	fn snapshot(used_gas: u64) -> Snapshot {
+		Snapshot {
+			gas_limit: 100_000,
+			memory_gas: 0,
+			used_gas,
+			refunded_gas: 0,
+		}
+	}
+
+	fn step(listener: &mut Listener, opcode: &[u8], position: u64) {
+		listener.runtime_event(RuntimeEvent::Step {
+			context: EvmContext {
+				address: H160::default(),
+				caller: H160::default(),
+				apparent_value: 0.into(),
+			},
+			opcode: opcode.to_vec(),
+			position: Ok(position),
+			stack: None,
+			memory: None,
+		});
+	}
+
+	#[test]
+	fn call_gas_cost_includes_forwarded_gas() {
+		let mut listener = Listener::new(true, true, true);
+		listener.gasometer_event(GasometerEvent::RecordTransaction {
+			cost: 21_000,
+			snapshot: snapshot(0),
+		});
+
+		step(&mut listener, b"Call", 0);
+		// Cold account access.
+		listener.gasometer_event(GasometerEvent::RecordDynamicCost {
+			gas_cost: 2_600,
+			memory_gas: 0,
+			gas_refund: 0,
+			snapshot: snapshot(1_000),
+		});
+		listener.runtime_event(RuntimeEvent::StepResult {
+			result: Err(Capture::Trap(b"Call".to_vec())),
+			return_value: vec![],
+		});
+		// Gas forwarded to the callee.
+		listener.gasometer_event(GasometerEvent::RecordCost {
+			cost: 50_000,
+			snapshot: snapshot(3_600),
+		});
+		// Callee steps.
+		step(&mut listener, b"Stop", 0);
+		listener.gasometer_event(GasometerEvent::RecordCost {
+			cost: 0,
+			snapshot: Snapshot {
+				gas_limit: 50_000,
+				..snapshot(0)
+			},
+		});
+		listener.runtime_event(RuntimeEvent::StepResult {
+			result: Err(Capture::Exit(ExitReason::Succeed(
+				evm_tracing_events::runtime::ExitSucceed::Stopped,
+			))),
+			return_value: vec![],
+		});
+
+		assert_eq!(listener.step_logs.len(), 2);
+		// `gas` is the gas remaining before the opcode, `gasCost` is its full cost.
+		assert_eq!(listener.step_logs[0].gas, 99_000.into());
+		assert_eq!(listener.step_logs[0].gas_cost, 52_600.into());
+		assert_eq!(listener.step_logs[1].gas, 50_000.into());
+		assert_eq!(listener.step_logs[1].gas_cost, 0.into());
+	}
+}
@@ -0,0 +1,285 @@
+// Copyright 2019-2022 PureStake Inc.
+// This file is part of Moonbeam.
+
+// Moonbeam is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Moonbeam is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Adapter building the responses of the debug RPC from a completed trace, i.e. the events
+//! captured by the runtime tracer, instead of listening to them while the transaction is
+//! replayed.
+
+use crate::formatters::{self, ResponseFormatter};
+use crate::listeners;
+use crate::types::single::TransactionTrace;
+use evm_tracing_events::{Event, Listener as ListenerT, StepEventFilter};
+
+/// Shape of the response, selected by the tracer and the options of the `TraceParams` of the
+/// debug RPC.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum TracerType {
+	/// Flat list of calls, formatted by Blockscout's tracer.
+	CallList,
+	/// Nested calls, formatted by Geth's callTracer.
+	CallTracer,
+	/// Struct logs of Geth's default tracer, one per step.
+	Raw {
+		disable_storage: bool,
+		disable_memory: bool,
+		disable_stack: bool,
+	},
+}
+
+/// Response of the debug RPC to the transaction whose trace is `events`. `None` if the trace
+/// contains no call.
+pub fn response<I: IntoIterator<Item = Event>>(
+	events: I,
+	tracer_type: TracerType,
+) -> Option<TransactionTrace> {
+	match tracer_type {
+		TracerType::CallList => formatters::Blockscout::format(call_list(events)),
+		TracerType::CallTracer => formatters::CallTracer::format(call_list(events))?.pop(),
+		TracerType::Raw {
+			disable_storage,
+			disable_memory,
+			disable_stack,
+		} => {
+			let mut listener = listeners::Raw::new(disable_storage, disable_memory, disable_stack);
+			for event in events {
+				listener.event(event);
+			}
+			formatters::Raw::format(listener)
+		}
+	}
+}
+
+/// Responses of the debug RPC to the transactions of the block whose trace is `events`,
+/// formatted by Geth's callTracer, the only tracer supported for blocks. `None` if the trace
+/// contains no call.
+pub fn block_response<I: IntoIterator<Item = Event>>(events: I) -> Option<Vec<TransactionTrace>> {
+	formatters::CallTracer::format(call_list(events))
+}
+
+/// Listener capturing the events of a trace, with the step details needed by the response.
+pub struct Capture {
+	events: Vec<Event>,
+	step_event_filter: StepEventFilter,
+}
+
+impl Capture {
+	pub fn new(tracer_type: TracerType) -> Self {
+		let step_event_filter = match tracer_type {
+			TracerType::Raw {
+				disable_memory,
+				disable_stack,
+				..
+			} => StepEventFilter {
+				enable_memory: !disable_memory,
+				enable_stack: !disable_stack,
+			},
+			TracerType::CallList | TracerType::CallTracer => StepEventFilter {
+				enable_memory: false,
+				enable_stack: false,
+			},
+		};
+		Self {
+			events: Vec::new(),
+			step_event_filter,
+		}
+	}
+
+	pub fn using<R, F: FnOnce() -> R>(&mut self, f: F) -> R {
+		evm_tracing_events::using(self, f)
+	}
+
+	/// Events captured so far, in the order they were received.
+	pub fn into_events(self) -> Vec<Event> {
+		self.events
+	}
+}
+
+impl ListenerT for Capture {
+	fn event(&mut self, event: Event) {
+		self.events.push(event);
+	}
+
+	fn step_event_filter(&self) -> StepEventFilter {
+		self.step_event_filter
+	}
+}
+
+fn call_list<I: IntoIterator<Item = Event>>(events: I) -> listeners::CallList {
+	let mut listener = listeners::CallList::default();
+	for event in events {
+		listener.event(event);
+	}
+	listener.finish_transaction();
+	listener
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::types::single::Call;
+	use ethereum_types::H160;
+	use evm_tracing_events::{
+		gasometer::Snapshot,
+		runtime::{Capture, ExitReason, ExitSucceed},
+		Context as EvmContext, EvmEvent, GasometerEvent, RuntimeEvent,
+	};
+
+	fn snapshot(gas_limit: u64, used_gas: u64) -> Snapshot {
+		Snapshot {
+			gas_limit,
+			memory_gas: 0,
+			used_gas,
+			refunded_gas: 0,
+		}
+	}
+
+	/// Events of a call to a contract executing a single `STOP`.
+	fn captured_trace() -> Vec<Event> {
+		let caller = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let stopped = ExitReason::Succeed(ExitSucceed::Stopped);
+		vec![
+			Event::Evm(EvmEvent::TransactCall {
+				caller,
+				address: contract,
+				value: 0.into(),
+				data: vec![],
+				gas_limit: 100_000,
+			}),
+			Event::Gasometer(GasometerEvent::RecordTransaction {
+				cost: 21_000,
+				snapshot: snapshot(100_000, 0),
+			}),
+			Event::Evm(EvmEvent::Call {
+				code_address: contract,
+				transfer: None,
+				input: vec![],
+				target_gas: None,
+				is_static: false,
+				context: EvmContext {
+					address: contract,
+					caller,
+					apparent_value: 0.into(),
+				},
+			}),
+			Event::Runtime(RuntimeEvent::Step {
+				context: EvmContext {
+					address: contract,
+					caller,
+					apparent_value: 0.into(),
+				},
+				opcode: b"Stop".to_vec(),
+				position: Ok(0),
+				stack: None,
+				memory: None,
+			}),
+			Event::Gasometer(GasometerEvent::RecordCost {
+				cost: 0,
+				snapshot: snapshot(100_000, 21_000),
+			}),
+			Event::Runtime(RuntimeEvent::StepResult {
+				result: Err(Capture::Exit(stopped.clone())),
+				return_value: vec![],
+			}),
+			Event::Evm(EvmEvent::Exit {
+				reason: stopped,
+				return_value: vec![],
+			}),
+		]
+	}
+
+	#[test]
+	fn call_list_response() {
+		match response(captured_trace(), TracerType::CallList) {
+			Some(TransactionTrace::CallList(calls)) => {
+				assert_eq!(calls.len(), 1);
+				assert!(matches!(calls[0], Call::Blockscout(_)));
+			}
+			other => panic!("unexpected response {:?}", other),
+		}
+	}
+
+	#[test]
+	fn call_tracer_response() {
+		match response(captured_trace(), TracerType::CallTracer) {
+			Some(TransactionTrace::CallListNested(Call::CallTracer(call))) => {
+				assert_eq!(call.from, H160::repeat_byte(0x01));
+				assert!(call.calls.is_empty());
+			}
+			other => panic!("unexpected response {:?}", other),
+		}
+	}
+
+	#[test]
+	fn raw_response() {
+		let tracer_type = TracerType::Raw {
+			disable_storage: true,
+			disable_memory: true,
+			disable_stack: true,
+		};
+
+		match response(captured_trace(), tracer_type) {
+			Some(TransactionTrace::Raw { step_logs, .. }) => {
+				assert_eq!(step_logs.len(), 1);
+				assert_eq!(step_logs[0].op, b"Stop".to_vec());
+			}
+			other => panic!("unexpected response {:?}", other),
+		}
+	}
+
+	#[test]
+	fn block_response_has_a_trace_per_transaction() {
+		let mut events = vec![Event::CallListNew()];
+		events.extend(captured_trace());
+		events.push(Event::CallListNew());
+		events.extend(captured_trace());
+
+		let traces = block_response(events).expect("traces to be formatted");
+		assert_eq!(traces.len(), 2);
+		assert!(traces
+			.iter()
+			.all(|trace| matches!(trace, TransactionTrace::CallListNested(Call::CallTracer(_)))));
+	}
+
+	#[test]
+	fn capture_requests_the_step_details_of_the_response() {
+		let raw = Capture::new(TracerType::Raw {
+			disable_storage: false,
+			disable_memory: true,
+			disable_stack: false,
+		});
+		assert_eq!(
+			raw.step_event_filter(),
+			StepEventFilter {
+				enable_memory: false,
+				enable_stack: true,
+			}
+		);
+		assert_eq!(
+			Capture::new(TracerType::CallTracer).step_event_filter(),
+			StepEventFilter {
+				enable_memory: false,
+				enable_stack: false,
+			}
+		);
+
+		let mut capture = Capture::new(TracerType::CallList);
+		for event in captured_trace() {
+			capture.event(event);
+		}
+		assert_eq!(capture.into_events(), captured_trace());
+	}
+}
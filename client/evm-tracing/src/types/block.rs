@@ -34,6 +34,10 @@ pub struct TransactionTrace {
 	#[serde(flatten)]
 	pub output: TransactionTraceOutput,
 	pub subtraces: u32,
+	/// Subcalls not tracked due to `Listener::with_max_tracked_frames`, whose gas is
+	/// accounted in this trace.
+	#[serde(skip_serializing_if = "core::ops::Not::not")]
+	pub subcalls_truncated: bool,
 	pub trace_address: Vec<u32>,
 	#[serde(serialize_with = "h256_0x_serialize")]
 	pub transaction_hash: H256,
@@ -68,6 +72,19 @@ pub enum TransactionTraceAction {
 		balance: U256,
 		refund_address: H160,
 	},
+	#[serde(rename_all = "camelCase")]
+	Reward {
+		author: H160,
+		reward_type: RewardType,
+		value: U256,
+	},
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Encode, Decode, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RewardType {
+	Block,
+	Uncle,
 }
 
 #[derive(Clone, Eq, PartialEq, Debug, Encode, Decode, Serialize)]
@@ -94,4 +111,5 @@ pub enum TransactionTraceResult {
 		gas_used: U256,
 	},
 	Suicide,
+	Reward,
 }
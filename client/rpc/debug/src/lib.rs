@@ -25,9 +25,12 @@ use tokio::{
 use ethereum_types::H256;
 use fc_rpc::{frontier_backend_client, internal_err, OverrideHandle};
 use fp_rpc::EthereumRuntimeRPCApi;
-use moonbeam_client_evm_tracing::{formatters::ResponseFormatter, types::single};
+use moonbeam_client_evm_tracing::{
+	rpc::{self, TracerType},
+	types::single,
+};
 use moonbeam_rpc_core_types::{RequestBlockId, RequestBlockTag};
-use moonbeam_rpc_primitives_debug::{DebugRuntimeApi, TracerInput};
+use moonbeam_rpc_primitives_debug::DebugRuntimeApi;
 use sc_client_api::backend::{Backend, StateBackend, StorageProvider};
 use sc_utils::mpsc::TracingUnboundedSender;
 use sp_api::{ApiExt, BlockId, Core, HeaderT, ProvideRuntimeApi};
@@ -240,7 +243,7 @@ where
 		(fut, tx)
 	}
 
-	fn handle_params(params: Option<TraceParams>) -> RpcResult<(TracerInput, single::TraceType)> {
+	fn handle_params(params: Option<TraceParams>) -> RpcResult<TracerType> {
 		// Set trace input and type
 		match params {
 			Some(TraceParams {
@@ -254,14 +257,14 @@ where
 				let hash = sp_io::hashing::twox_128(&tracer.as_bytes());
 				let tracer =
 					if hash == BLOCKSCOUT_JS_CODE_HASH || hash == BLOCKSCOUT_JS_CODE_HASH_V2 {
-						Some(TracerInput::Blockscout)
+						Some(TracerType::CallList)
 					} else if tracer == "callTracer" {
-						Some(TracerInput::CallTracer)
+						Some(TracerType::CallTracer)
 					} else {
 						None
 					};
 				if let Some(tracer) = tracer {
-					Ok((tracer, single::TraceType::CallList))
+					Ok(tracer)
 				} else {
 					return Err(internal_err(format!(
 						"javascript based tracing is not available (hash :{:?})",
@@ -269,22 +272,16 @@ where
 					)));
 				}
 			}
-			Some(params) => Ok((
-				TracerInput::None,
-				single::TraceType::Raw {
-					disable_storage: params.disable_storage.unwrap_or(false),
-					disable_memory: params.disable_memory.unwrap_or(false),
-					disable_stack: params.disable_stack.unwrap_or(false),
-				},
-			)),
-			_ => Ok((
-				TracerInput::None,
-				single::TraceType::Raw {
-					disable_storage: false,
-					disable_memory: false,
-					disable_stack: false,
-				},
-			)),
+			Some(params) => Ok(TracerType::Raw {
+				disable_storage: params.disable_storage.unwrap_or(false),
+				disable_memory: params.disable_memory.unwrap_or(false),
+				disable_stack: params.disable_stack.unwrap_or(false),
+			}),
+			_ => Ok(TracerType::Raw {
+				disable_storage: false,
+				disable_memory: false,
+				disable_stack: false,
+			}),
 		}
 	}
 
@@ -296,7 +293,7 @@ where
 		params: Option<TraceParams>,
 		overrides: Arc<OverrideHandle<B>>,
 	) -> RpcResult<Response> {
-		let (tracer_input, trace_type) = Self::handle_params(params)?;
+		let tracer_type = Self::handle_params(params)?;
 
 		let reference_id: BlockId<B> = match request_block_id {
 			RequestBlockId::Number(n) => Ok(BlockId::Number(n.unique_saturated_into())),
@@ -381,21 +378,13 @@ where
 			Ok(moonbeam_rpc_primitives_debug::Response::Block)
 		};
 
-		return match trace_type {
-			single::TraceType::CallList => {
-				let mut proxy = moonbeam_client_evm_tracing::listeners::CallList::default();
-				proxy.using(f)?;
-				proxy.finish_transaction();
-				let response = match tracer_input {
-					TracerInput::CallTracer => {
-						moonbeam_client_evm_tracing::formatters::CallTracer::format(proxy)
-							.ok_or("Trace result is empty.")
-							.map_err(|e| internal_err(format!("{:?}", e)))
-					}
-					_ => Err(internal_err(format!(
-						"Bug: failed to resolve the tracer format."
-					))),
-				}?;
+		return match tracer_type {
+			TracerType::CallTracer => {
+				let mut capture = rpc::Capture::new(tracer_type);
+				capture.using(f)?;
+				let response = rpc::block_response(capture.into_events())
+					.ok_or("Trace result is empty.")
+					.map_err(|e| internal_err(format!("{:?}", e)))?;
 
 				Ok(Response::Block(response))
 			}
@@ -421,7 +410,7 @@ where
 		params: Option<TraceParams>,
 		overrides: Arc<OverrideHandle<B>>,
 	) -> RpcResult<Response> {
-		let (tracer_input, trace_type) = Self::handle_params(params)?;
+		let tracer_type = Self::handle_params(params)?;
 
 		let (hash, index) = match frontier_backend_client::load_transactions::<B, C>(
 			client.as_ref(),
@@ -525,52 +514,12 @@ where
 					Ok(moonbeam_rpc_primitives_debug::Response::Single)
 				};
 
-				return match trace_type {
-					single::TraceType::Raw {
-						disable_storage,
-						disable_memory,
-						disable_stack,
-					} => {
-						let mut proxy = moonbeam_client_evm_tracing::listeners::Raw::new(
-							disable_storage,
-							disable_memory,
-							disable_stack,
-						);
-						proxy.using(f)?;
-						Ok(Response::Single(
-							moonbeam_client_evm_tracing::formatters::Raw::format(proxy).unwrap(),
-						))
-					}
-					single::TraceType::CallList => {
-						let mut proxy = moonbeam_client_evm_tracing::listeners::CallList::default();
-						proxy.using(f)?;
-						proxy.finish_transaction();
-						let response = match tracer_input {
-							TracerInput::Blockscout => {
-								moonbeam_client_evm_tracing::formatters::Blockscout::format(proxy)
-									.ok_or("Trace result is empty.")
-									.map_err(|e| internal_err(format!("{:?}", e)))
-							}
-							TracerInput::CallTracer => {
-								let mut res =
-									moonbeam_client_evm_tracing::formatters::CallTracer::format(
-										proxy,
-									)
-									.ok_or("Trace result is empty.")
-									.map_err(|e| internal_err(format!("{:?}", e)))?;
-								Ok(res.pop().unwrap())
-							}
-							_ => Err(internal_err(format!(
-								"Bug: failed to resolve the tracer format."
-							))),
-						}?;
-						Ok(Response::Single(response))
-					}
-					not_supported => Err(internal_err(format!(
-						"Bug: `handle_transaction_request` does not support {:?}.",
-						not_supported
-					))),
-				};
+				let mut capture = rpc::Capture::new(tracer_type);
+				capture.using(f)?;
+				return rpc::response(capture.into_events(), tracer_type)
+					.ok_or("Trace result is empty.")
+					.map(Response::Single)
+					.map_err(|e| internal_err(format!("{:?}", e)));
 			}
 		}
 		Err(internal_err("Runtime block call failed".to_string()))
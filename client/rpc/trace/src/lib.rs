@@ -192,6 +192,11 @@ where
 						(from_address.is_empty() || from_address.contains(&address))
 							&& to_address.is_empty()
 					}
+					// Like Parity, the author of a reward is matched as its receiver.
+					block::TransactionTraceAction::Reward { author, .. } => {
+						from_address.is_empty()
+							&& (to_address.is_empty() || to_address.contains(&author))
+					}
 				})
 				.cloned()
 				.collect();
@@ -28,7 +28,9 @@ use sp_runtime_interface::runtime_interface;
 use codec::Decode;
 use sp_std::vec::Vec;
 
-use evm_tracing_events::{Event, EvmEvent, GasometerEvent, RuntimeEvent, StepEventFilter};
+use evm_tracing_events::{
+	Event, EvmEvent, GasometerEvent, RuntimeEvent, StepEventFilter, TracerEvent,
+};
 
 #[runtime_interface]
 pub trait MoonbeamExt {
@@ -67,6 +69,45 @@ pub trait MoonbeamExt {
 		}
 	}
 
+	/// A `Tracer` event produced by the Moonbeam runtime tracer and sent to this host function.
+	/// moonbeam_runtime -> host.
+	fn tracer_event(&mut self, event: Vec<u8>) {
+		if let Ok(event) = TracerEvent::decode(&mut &event[..]) {
+			Event::Tracer(event).emit();
+		}
+	}
+
+	/// An event of any type encoded as CBOR by the Moonbeam runtime tracer, see
+	/// `Encoding::Cbor`. Ignored by hosts built without the `cbor` feature.
+	/// moonbeam_runtime -> host.
+	fn cbor_event(&mut self, event: Vec<u8>) {
+		#[cfg(feature = "cbor")]
+		{
+			if let Some(event) = Event::from_cbor(&event) {
+				event.emit();
+			}
+		}
+		#[cfg(not(feature = "cbor"))]
+		let _ = event;
+	}
+
+	/// An event of a stream multiplexed on the host, see `EvmTracer::with_stream_id`. Only the
+	/// events of the EVM stream are routed to the tracing listener, other streams are dropped.
+	/// moonbeam_runtime -> host.
+	fn stream_event(&mut self, message: Vec<u8>) {
+		match evm_tracing_events::decode_stream_message(&message) {
+			Some((evm_tracing_events::EVM_STREAM_ID, event)) => event.emit(),
+			_ => (),
+		}
+	}
+
+	/// A marker emitted by runtime code at a chosen point of a trace, see
+	/// `EvmTracer::emit_marker`.
+	/// moonbeam_runtime -> host.
+	fn trace_marker(&mut self, label: Vec<u8>) {
+		Event::Tracer(TracerEvent::Marker { label }).emit();
+	}
+
 	/// Allow the tracing module in the runtime to know how to filter Step event
 	/// content, as cloning the entire data is expensive and most of the time
 	/// not necessary.
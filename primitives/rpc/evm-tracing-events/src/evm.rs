@@ -22,6 +22,7 @@ use ethereum_types::{H160, H256, U256};
 use evm::ExitReason;
 
 #[derive(Clone, Debug, Encode, Decode, PartialEq, Eq)]
+#[cfg_attr(feature = "cbor", derive(serde::Serialize, serde::Deserialize))]
 pub struct Transfer {
 	/// Source address.
 	pub source: H160,
@@ -42,6 +43,7 @@ impl From<evm_runtime::Transfer> for Transfer {
 }
 
 #[derive(Clone, Copy, Eq, PartialEq, Debug, Encode, Decode)]
+#[cfg_attr(feature = "cbor", derive(serde::Serialize, serde::Deserialize))]
 pub enum CreateScheme {
 	/// Legacy create scheme of `CREATE`.
 	Legacy {
@@ -80,10 +82,13 @@ impl From<evm_runtime::CreateScheme> for CreateScheme {
 }
 
 #[derive(Debug, Clone, Encode, Decode, PartialEq, Eq)]
+#[cfg_attr(feature = "cbor", derive(serde::Serialize, serde::Deserialize))]
 pub enum EvmEvent {
 	Call {
 		code_address: H160,
 		transfer: Option<Transfer>,
+		/// Exact bytes passed to the callee: the slice of the caller memory at call time, which
+		/// the EVM pads with zeros if it extends beyond the memory.
 		input: Vec<u8>,
 		target_gas: Option<u64>,
 		is_static: bool,
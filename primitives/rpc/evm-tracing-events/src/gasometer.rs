@@ -17,6 +17,7 @@
 use codec::{Decode, Encode};
 
 #[derive(Debug, Default, Copy, Clone, Encode, Decode, PartialEq, Eq)]
+#[cfg_attr(feature = "cbor", derive(serde::Serialize, serde::Deserialize))]
 pub struct Snapshot {
 	pub gas_limit: u64,
 	pub memory_gas: u64,
@@ -46,6 +47,7 @@ impl From<Option<evm_gasometer::Snapshot>> for Snapshot {
 }
 
 #[derive(Debug, Copy, Clone, Encode, Decode, PartialEq, Eq)]
+#[cfg_attr(feature = "cbor", derive(serde::Serialize, serde::Deserialize))]
 pub enum GasometerEvent {
 	RecordCost {
 		cost: u64,
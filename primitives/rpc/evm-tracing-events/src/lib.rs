@@ -34,10 +34,12 @@ extern crate alloc;
 pub mod evm;
 pub mod gasometer;
 pub mod runtime;
+pub mod tracer;
 
 pub use self::evm::EvmEvent;
 pub use gasometer::GasometerEvent;
 pub use runtime::RuntimeEvent;
+pub use tracer::TracerEvent;
 
 use ::evm::Opcode;
 use alloc::vec::Vec;
@@ -61,11 +63,30 @@ pub struct StepEventFilter {
 }
 
 #[derive(Clone, Eq, PartialEq, Debug, Encode, Decode)]
+#[cfg_attr(feature = "cbor", derive(serde::Serialize, serde::Deserialize))]
 pub enum Event {
 	Evm(evm::EvmEvent),
 	Gasometer(gasometer::GasometerEvent),
 	Runtime(runtime::RuntimeEvent),
 	CallListNew(),
+	Tracer(tracer::TracerEvent),
+}
+
+/// Encoding of the events sent by the runtime to the host.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Encoding {
+	/// SCALE, through one host function per type of event.
+	Scale,
+	/// CBOR, through the `cbor_event` host function, for hosts decoding the events in other
+	/// languages.
+	#[cfg(feature = "cbor")]
+	Cbor,
+}
+
+impl Default for Encoding {
+	fn default() -> Self {
+		Self::Scale
+	}
 }
 
 impl Event {
@@ -76,6 +97,28 @@ impl Event {
 	pub fn emit(self) {
 		listener::with(|listener| listener.event(self));
 	}
+
+	/// CBOR encoding of the event.
+	#[cfg(feature = "cbor")]
+	pub fn to_cbor(&self) -> Vec<u8> {
+		self.try_to_cbor().unwrap_or_default()
+	}
+
+	/// CBOR encoding of the event. Writing into a `Vec` doesn't fail, but the serialization of
+	/// the event may.
+	#[cfg(feature = "cbor")]
+	pub fn try_to_cbor(&self) -> Result<Vec<u8>, tracer::TracerError> {
+		let mut data = Vec::new();
+		ciborium::ser::into_writer(self, &mut data)
+			.map_err(|_| tracer::TracerError::SerializationFailed)?;
+		Ok(data)
+	}
+
+	/// Decode a CBOR-encoded event.
+	#[cfg(feature = "cbor")]
+	pub fn from_cbor(data: &[u8]) -> Option<Self> {
+		ciborium::de::from_reader(data).ok()
+	}
 }
 
 /// Main trait to proxy emitted messages.
@@ -97,7 +140,22 @@ pub fn step_event_filter() -> Option<StepEventFilter> {
 	filter
 }
 
+/// Stream of the EVM tracer, whose events are sent to the host unprefixed.
+pub const EVM_STREAM_ID: u16 = 0;
+
+/// Message of a stream multiplexed on the host: the stream id as a little endian `u16`,
+/// followed by the SCALE-encoded event.
+pub fn encode_stream_message(stream_id: u16, event: &Event) -> Vec<u8> {
+	(stream_id, event).encode()
+}
+
+/// Decodes a message built by `encode_stream_message`.
+pub fn decode_stream_message(message: &[u8]) -> Option<(u16, Event)> {
+	Decode::decode(&mut &message[..]).ok()
+}
+
 #[derive(Clone, Debug, Encode, Decode, PartialEq, Eq)]
+#[cfg_attr(feature = "cbor", derive(serde::Serialize, serde::Deserialize))]
 pub struct Context {
 	/// Execution address.
 	pub address: H160,
@@ -119,8 +177,15 @@ impl From<evm_runtime::Context> for Context {
 
 /// Converts an Opcode into its name, stored in a `Vec<u8>`.
 pub fn opcodes_string(opcode: Opcode) -> Vec<u8> {
-	let tmp;
-	let out = match opcode {
+	match opcode_name(opcode) {
+		Some(name) => name.as_bytes().to_vec(),
+		None => alloc::format!("Unknown({})", opcode.0).into_bytes(),
+	}
+}
+
+/// Name of the opcode, `None` if it is unknown to the tracer.
+pub fn opcode_name(opcode: Opcode) -> Option<&'static str> {
+	let name = match opcode {
 		Opcode(0) => "Stop",
 		Opcode(1) => "Add",
 		Opcode(2) => "Mul",
@@ -171,6 +236,9 @@ pub fn opcodes_string(opcode: Opcode) -> Vec<u8> {
 		Opcode(68) => "Difficulty",
 		Opcode(69) => "GasLimit",
 		Opcode(70) => "ChainId",
+		// EIP-4844 opcodes, not supported by the EVM yet but named to not be reported as unknown.
+		Opcode(73) => "BlobHash",
+		Opcode(74) => "BlobBaseFee",
 		Opcode(80) => "Pop",
 		Opcode(81) => "MLoad",
 		Opcode(82) => "MStore",
@@ -275,10 +343,377 @@ pub fn opcodes_string(opcode: Opcode) -> Vec<u8> {
 		Opcode(253) => "Revert",
 		Opcode(254) => "Invalid",
 		Opcode(255) => "SelfDestruct",
-		Opcode(n) => {
-			tmp = alloc::format!("Unknown({})", n);
-			&tmp
-		}
+		_ => return None,
 	};
-	out.as_bytes().to_vec()
+	Some(name)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn blob_opcodes_are_named() {
+		assert_eq!(opcodes_string(Opcode(0x49)), b"BlobHash".to_vec());
+		assert_eq!(opcodes_string(Opcode(0x4a)), b"BlobBaseFee".to_vec());
+		assert_eq!(opcodes_string(Opcode(0x4b)), b"Unknown(75)".to_vec());
+	}
+
+	#[test]
+	fn stream_messages_are_prefixed_with_stream_id() {
+		let event = Event::Tracer(TracerEvent::FrameStart {
+			gas_at_frame_start: 21_000,
+		});
+
+		let message = encode_stream_message(0x0102, &event);
+
+		assert_eq!(&message[..2], &[0x02, 0x01]);
+		assert_eq!(&message[2..], &event.encode()[..]);
+		assert_eq!(decode_stream_message(&message), Some((0x0102, event)));
+	}
+
+	/// Index of the variant of the event, among all the variants of `Event` and of the event
+	/// types it wraps. Not having a wildcard arm, the match stops compiling when a variant is
+	/// added, which must then be sampled by `events_round_trip_as_cbor`.
+	#[cfg(feature = "cbor")]
+	fn variant_index(event: &Event) -> usize {
+		match event {
+			Event::Evm(EvmEvent::Call { .. }) => 0,
+			Event::Evm(EvmEvent::Create { .. }) => 1,
+			Event::Evm(EvmEvent::Suicide { .. }) => 2,
+			Event::Evm(EvmEvent::Exit { .. }) => 3,
+			Event::Evm(EvmEvent::TransactCall { .. }) => 4,
+			Event::Evm(EvmEvent::TransactCreate { .. }) => 5,
+			Event::Evm(EvmEvent::TransactCreate2 { .. }) => 6,
+			Event::Gasometer(GasometerEvent::RecordCost { .. }) => 7,
+			Event::Gasometer(GasometerEvent::RecordRefund { .. }) => 8,
+			Event::Gasometer(GasometerEvent::RecordStipend { .. }) => 9,
+			Event::Gasometer(GasometerEvent::RecordDynamicCost { .. }) => 10,
+			Event::Gasometer(GasometerEvent::RecordTransaction { .. }) => 11,
+			Event::Runtime(RuntimeEvent::Step { .. }) => 12,
+			Event::Runtime(RuntimeEvent::StepResult { .. }) => 13,
+			Event::Runtime(RuntimeEvent::SLoad { .. }) => 14,
+			Event::Runtime(RuntimeEvent::SStore { .. }) => 15,
+			Event::CallListNew() => 16,
+			Event::Tracer(event) => match event {
+				TracerEvent::Begin(_) => 17,
+				TracerEvent::Hardfork(_) => 18,
+				TracerEvent::Call { .. } => 19,
+				TracerEvent::FrameStart { .. } => 20,
+				TracerEvent::MemoryGrowth { .. } => 21,
+				TracerEvent::Selfdestruct { .. } => 22,
+				TracerEvent::Keccak { .. } => 23,
+				TracerEvent::ReturnData { .. } => 24,
+				TracerEvent::GasSample { .. } => 25,
+				TracerEvent::Jumpdest { .. } => 26,
+				TracerEvent::Reentrancy { .. } => 27,
+				TracerEvent::PrecompileGas { .. } => 28,
+				TracerEvent::DepthLimit { .. } => 29,
+				TracerEvent::CreateCollision { .. } => 30,
+				TracerEvent::FrameId { .. } => 31,
+				TracerEvent::AccountAccess { .. } => 32,
+				TracerEvent::Error(_) => 33,
+				TracerEvent::CreatorNonce { .. } => 34,
+				TracerEvent::GasExplanation { .. } => 35,
+				TracerEvent::Replay(_) => 36,
+				TracerEvent::UnknownOpcode { .. } => 37,
+				TracerEvent::CalldataRead { .. } => 38,
+				TracerEvent::Sstore { .. } => 39,
+				TracerEvent::TraceEnd { .. } => 40,
+				TracerEvent::Timeout { .. } => 41,
+				TracerEvent::Summary { .. } => 42,
+				TracerEvent::FrameSummary { .. } => 43,
+				TracerEvent::RolledBack(_) => 44,
+				TracerEvent::Marker { .. } => 45,
+				TracerEvent::StorageAccess { .. } => 46,
+			},
+		}
+	}
+
+	/// Amount of variants indexed by `variant_index`.
+	#[cfg(feature = "cbor")]
+	const VARIANTS: usize = 47;
+
+	#[cfg(feature = "cbor")]
+	#[test]
+	fn events_round_trip_as_cbor() {
+		use ethereum_types::{H256, U256};
+
+		let address = H160::repeat_byte(0x01);
+		let caller = H160::repeat_byte(0x02);
+		let hash = H256::repeat_byte(0x03);
+		let context = Context {
+			address,
+			caller,
+			apparent_value: 3.into(),
+		};
+		let snapshot = gasometer::Snapshot {
+			gas_limit: 100,
+			memory_gas: 6,
+			used_gas: 9,
+			refunded_gas: -1,
+		};
+		let events = vec![
+			Event::Evm(EvmEvent::Call {
+				code_address: address,
+				transfer: Some(evm::Transfer {
+					source: caller,
+					target: address,
+					value: 3.into(),
+				}),
+				input: vec![0xde, 0xad],
+				target_gas: Some(21_000),
+				is_static: false,
+				context: context.clone(),
+			}),
+			Event::Evm(EvmEvent::Create {
+				caller,
+				address,
+				scheme: evm::CreateScheme::Create2 {
+					caller,
+					code_hash: hash,
+					salt: H256::repeat_byte(0x04),
+				},
+				value: 0.into(),
+				init_code: vec![0x00],
+				target_gas: None,
+			}),
+			Event::Evm(EvmEvent::Create {
+				caller,
+				address,
+				scheme: evm::CreateScheme::Fixed(address),
+				value: 1.into(),
+				init_code: vec![],
+				target_gas: Some(5),
+			}),
+			Event::Evm(EvmEvent::Suicide {
+				address,
+				target: caller,
+				balance: U256::MAX,
+			}),
+			Event::Evm(EvmEvent::Exit {
+				reason: runtime::ExitReason::Succeed(runtime::ExitSucceed::Returned),
+				return_value: vec![0x01],
+			}),
+			Event::Evm(EvmEvent::Exit {
+				reason: runtime::ExitReason::Error(runtime::ExitError::OutOfGas),
+				return_value: vec![],
+			}),
+			Event::Evm(EvmEvent::TransactCall {
+				caller,
+				address,
+				value: 0.into(),
+				data: vec![0xa9, 0x05, 0x9c, 0xbb],
+				gas_limit: 21_000,
+			}),
+			Event::Evm(EvmEvent::TransactCreate {
+				caller,
+				value: 0.into(),
+				init_code: vec![0x60, 0x00],
+				gas_limit: 53_000,
+				address,
+			}),
+			Event::Evm(EvmEvent::TransactCreate2 {
+				caller,
+				value: 0.into(),
+				init_code: vec![0x60, 0x00],
+				salt: hash,
+				gas_limit: 53_000,
+				address,
+			}),
+			Event::Gasometer(GasometerEvent::RecordCost { cost: 3, snapshot }),
+			Event::Gasometer(GasometerEvent::RecordRefund {
+				refund: -4_800,
+				snapshot,
+			}),
+			Event::Gasometer(GasometerEvent::RecordStipend {
+				stipend: 2_300,
+				snapshot,
+			}),
+			Event::Gasometer(GasometerEvent::RecordDynamicCost {
+				gas_cost: 2_100,
+				memory_gas: 6,
+				gas_refund: 0,
+				snapshot,
+			}),
+			Event::Gasometer(GasometerEvent::RecordTransaction {
+				cost: 21_000,
+				snapshot,
+			}),
+			Event::Runtime(RuntimeEvent::Step {
+				context: context.clone(),
+				opcode: opcodes_string(Opcode::ADD),
+				position: Ok(5),
+				stack: Some(runtime::Stack {
+					data: vec![H256::repeat_byte(0x04)],
+					limit: 1024,
+				}),
+				memory: Some(runtime::Memory {
+					data: vec![0xff; 32],
+					effective_len: 32.into(),
+					limit: u64::MAX,
+				}),
+			}),
+			Event::Runtime(RuntimeEvent::Step {
+				context,
+				opcode: opcodes_string(Opcode::STOP),
+				position: Err(runtime::ExitReason::Succeed(runtime::ExitSucceed::Stopped)),
+				stack: None,
+				memory: None,
+			}),
+			Event::Runtime(RuntimeEvent::StepResult {
+				result: Ok(()),
+				return_value: vec![],
+			}),
+			Event::Runtime(RuntimeEvent::StepResult {
+				result: Err(runtime::Capture::Trap(opcodes_string(Opcode::CALL))),
+				return_value: vec![0x02],
+			}),
+			Event::Runtime(RuntimeEvent::SLoad {
+				address,
+				index: hash,
+				value: H256::zero(),
+			}),
+			Event::Runtime(RuntimeEvent::SStore {
+				address,
+				index: hash,
+				value: H256::repeat_byte(0x05),
+			}),
+			Event::CallListNew(),
+			Event::Tracer(TracerEvent::Begin(tracer::TxContext {
+				caller,
+				to: None,
+				value: 0.into(),
+				gas_limit: 100_000,
+				nonce: 4.into(),
+			})),
+			Event::Tracer(TracerEvent::Hardfork(tracer::Hardfork::Cancun)),
+			Event::Tracer(TracerEvent::Call {
+				stipend: Some(tracer::CALL_STIPEND),
+				selector: Some([0xa9, 0x05, 0x9c, 0xbb]),
+				originating_precompile: None,
+				msg_sender: caller,
+				immediate_caller: caller,
+				target_existed: Some(false),
+				new_account_cost_charged: true,
+			}),
+			Event::Tracer(TracerEvent::FrameStart {
+				gas_at_frame_start: 21_000,
+			}),
+			Event::Tracer(TracerEvent::MemoryGrowth {
+				pc: 5,
+				old_size: 0,
+				new_size: 32,
+				expansion_cost: 3,
+			}),
+			Event::Tracer(TracerEvent::Selfdestruct {
+				contract: address,
+				beneficiary: caller,
+				balance: 7.into(),
+				deleted: false,
+			}),
+			Event::Tracer(TracerEvent::Keccak {
+				input: vec![0x01; 64],
+				output: hash,
+			}),
+			Event::Tracer(TracerEvent::ReturnData {
+				opcode: Opcode::RETURNDATACOPY.0,
+				offset: 0.into(),
+				size: 64.into(),
+				total_available: 32,
+				out_of_bounds: true,
+			}),
+			Event::Tracer(TracerEvent::GasSample {
+				step_index: 10,
+				gas_remaining: 90_000,
+			}),
+			Event::Tracer(TracerEvent::Jumpdest {
+				pc: 7,
+				gas_used_so_far: 24,
+			}),
+			Event::Tracer(TracerEvent::Reentrancy {
+				address,
+				original_depth: 0,
+				reentry_depth: 2,
+			}),
+			Event::Tracer(TracerEvent::PrecompileGas {
+				address: H160::from_low_u64_be(2),
+				gas_used: 84,
+			}),
+			Event::Tracer(TracerEvent::DepthLimit {
+				attempted_depth: 1025,
+			}),
+			Event::Tracer(TracerEvent::CreateCollision { address }),
+			Event::Tracer(TracerEvent::FrameId { frame_id: 3 }),
+			Event::Tracer(TracerEvent::AccountAccess {
+				address,
+				cold: true,
+			}),
+			Event::Tracer(TracerEvent::Error(tracer::TracerError::MalformedStep {
+				pc: 1,
+				opcode: Opcode::SHA3.0,
+			})),
+			Event::Tracer(TracerEvent::Error(tracer::TracerError::BudgetExceeded)),
+			Event::Tracer(TracerEvent::CreatorNonce { creator_nonce: 1 }),
+			Event::Tracer(TracerEvent::GasExplanation {
+				rule: tracer::GasRule::ColdSload,
+			}),
+			Event::Tracer(TracerEvent::Replay(tracer::ReplayRecord {
+				block_hashes: vec![(1.into(), hash)],
+				precompile_outputs: vec![(H160::from_low_u64_be(2), vec![0x01], vec![0x02])],
+				external_code: vec![(address, 0.into(), vec![0x00])],
+				storage: vec![(address, hash, H256::zero())],
+			})),
+			Event::Tracer(TracerEvent::UnknownOpcode {
+				pc: 0,
+				opcode: 0x0c,
+			}),
+			Event::Tracer(TracerEvent::CalldataRead {
+				offset: 4.into(),
+				size: 32.into(),
+			}),
+			Event::Tracer(TracerEvent::Sstore {
+				address,
+				slot: hash,
+				original: H256::zero(),
+				new: H256::zero(),
+				no_op: true,
+			}),
+			Event::Tracer(TracerEvent::TraceEnd { commitment: hash }),
+			Event::Tracer(TracerEvent::Timeout { elapsed: 5 }),
+			Event::Tracer(TracerEvent::Summary {
+				gas_used: 21_000,
+				touched: vec![address],
+				gas_breakdown: tracer::GasBreakdown {
+					intrinsic: 21_000,
+					..Default::default()
+				},
+				hardfork: Some(tracer::Hardfork::London),
+			}),
+			Event::Tracer(TracerEvent::FrameSummary { max_stack_depth: 8 }),
+			Event::Tracer(TracerEvent::RolledBack(Box::new(Event::Runtime(
+				RuntimeEvent::SStore {
+					address,
+					index: hash,
+					value: H256::zero(),
+				},
+			)))),
+			Event::Tracer(TracerEvent::Marker {
+				label: b"before".to_vec(),
+			}),
+			Event::Tracer(TracerEvent::StorageAccess {
+				address,
+				slot: hash,
+				cold: true,
+			}),
+		];
+
+		let mut sampled = [false; VARIANTS];
+		for event in events {
+			sampled[variant_index(&event)] = true;
+			assert_eq!(Event::from_cbor(&event.to_cbor()), Some(event));
+		}
+		let missing: Vec<_> = (0..VARIANTS).filter(|index| !sampled[*index]).collect();
+		assert_eq!(missing, Vec::<usize>::new());
+	}
 }
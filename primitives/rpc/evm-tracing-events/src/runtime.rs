@@ -22,9 +22,10 @@ use super::{opcodes_string, Context};
 use alloc::vec::Vec;
 use codec::{Decode, Encode};
 use ethereum_types::{H160, H256, U256};
-pub use evm::{ExitError, ExitReason, ExitSucceed, Opcode};
+pub use evm::{ExitError, ExitReason, ExitRevert, ExitSucceed, Opcode};
 
 #[derive(Clone, Debug, Encode, Decode, PartialEq, Eq)]
+#[cfg_attr(feature = "cbor", derive(serde::Serialize, serde::Deserialize))]
 pub struct Stack {
 	pub data: Vec<H256>,
 	pub limit: u64,
@@ -40,6 +41,7 @@ impl From<&evm::Stack> for Stack {
 }
 
 #[derive(Clone, Debug, Encode, Decode, PartialEq, Eq)]
+#[cfg_attr(feature = "cbor", derive(serde::Serialize, serde::Deserialize))]
 pub struct Memory {
 	pub data: Vec<u8>,
 	pub effective_len: U256,
@@ -57,6 +59,7 @@ impl From<&evm::Memory> for Memory {
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Encode, Decode)]
+#[cfg_attr(feature = "cbor", derive(serde::Serialize, serde::Deserialize))]
 pub enum Capture<E, T> {
 	/// The machine has exited. It cannot be executed again.
 	Exit(E),
@@ -68,6 +71,7 @@ pub enum Capture<E, T> {
 pub type Trap = Vec<u8>; // Should hold the marshalled Opcode.
 
 #[derive(Debug, Clone, Encode, Decode, PartialEq, Eq)]
+#[cfg_attr(feature = "cbor", derive(serde::Serialize, serde::Deserialize))]
 pub enum RuntimeEvent {
 	Step {
 		context: Context,
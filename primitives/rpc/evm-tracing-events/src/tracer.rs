@@ -0,0 +1,831 @@
+// Copyright 2019-2022 PureStake Inc.
+// This file is part of Moonbeam.
+
+// Moonbeam is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Moonbeam is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Events produced by the runtime tracer itself.
+//!
+//! Unlike the other event types of this crate, those are not mirrors of events emitted by the
+//! `evm` crates but data derived by the tracer from them. They are sent through their own host
+//! function so that the encoding of the mirror types stays stable across runtime versions.
+
+extern crate alloc;
+
+use crate::{Event, EvmEvent, RuntimeEvent};
+use alloc::{boxed::Box, collections::BTreeSet, vec::Vec};
+use codec::{Decode, Encode};
+use ethereum_types::{H160, H256, U256};
+
+/// Gas stipend the EVM adds to the gas forwarded to the callee of a value-bearing call.
+pub const CALL_STIPEND: u64 = 2300;
+
+/// Gas charged to a `CALL` transferring value to an account that doesn't exist (EIP-161: that is
+/// empty), which the transfer creates.
+pub const NEW_ACCOUNT_COST: u64 = 25_000;
+
+/// Envelope of the traced transaction, passed by the node to the tracer.
+#[derive(Debug, Clone, Encode, Decode, PartialEq, Eq)]
+#[cfg_attr(feature = "cbor", derive(serde::Serialize, serde::Deserialize))]
+pub struct TxContext {
+	pub caller: H160,
+	/// `None` for a contract creation.
+	pub to: Option<H160>,
+	pub value: U256,
+	pub gas_limit: u64,
+	pub nonce: U256,
+}
+
+/// Hardforks changing the gas schedule, in activation order.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Encode, Decode)]
+#[cfg_attr(feature = "cbor", derive(serde::Serialize, serde::Deserialize))]
+pub enum Hardfork {
+	Istanbul,
+	/// EIP-2929: state accesses are priced depending on whether they are warm or cold.
+	Berlin,
+	/// EIP-3198: `BASEFEE`.
+	London,
+	/// EIP-3855: `PUSH0`.
+	Shanghai,
+	/// EIP-1153, EIP-4844, EIP-5656 and EIP-7516: transient storage, blobs and `MCOPY`.
+	Cancun,
+}
+
+/// Split of the gas used by a transaction, before refunds, between `intrinsic`, `calldata` and
+/// `execution`.
+#[derive(Debug, Clone, Copy, Default, Encode, Decode, PartialEq, Eq)]
+#[cfg_attr(feature = "cbor", derive(serde::Serialize, serde::Deserialize))]
+pub struct GasBreakdown {
+	/// Intrinsic cost of the transaction other than its input.
+	pub intrinsic: u64,
+	/// Cost of the input of the transaction, 4 per zero byte and 16 per other byte (EIP-2028).
+	pub calldata: u64,
+	/// Gas used executing the transaction.
+	pub execution: u64,
+	/// Refund counter at the end of the transaction, before the EVM caps it.
+	pub refund: u64,
+}
+
+/// Recoverable issue met by the tracer, which keeps tracing without the data concerned.
+#[derive(Debug, Clone, Copy, Encode, Decode, PartialEq, Eq)]
+#[cfg_attr(feature = "cbor", derive(serde::Serialize, serde::Deserialize))]
+pub enum TracerError {
+	/// The host hook needed by the operation is not configured.
+	HostUnavailable,
+	/// A value doesn't fit the field of its event, e.g. a nonce above `u64::MAX`.
+	ConversionFailed,
+	/// The stack of a step holds less operands than its opcode takes. The step traps.
+	MalformedStep { pc: u64, opcode: u8 },
+	/// An event or a trace session couldn't be encoded or decoded.
+	SerializationFailed,
+	/// The trace exceeded its time limit.
+	BudgetExceeded,
+}
+
+/// Pricing rule accounting for most of a gas charge.
+#[derive(Debug, Clone, Copy, Encode, Decode, PartialEq, Eq)]
+#[cfg_attr(feature = "cbor", derive(serde::Serialize, serde::Deserialize))]
+pub enum GasRule {
+	/// Intrinsic cost of the transaction, including its input (EIP-2028).
+	Intrinsic,
+	/// Static cost of the opcode in the gas schedule.
+	Base,
+	/// Cost depending on the operands of the opcode, e.g. the words hashed or copied.
+	Operands,
+	/// Expansion of the memory, quadratic in its size.
+	MemoryExpansion,
+	/// EIP-2929 cold account access.
+	ColdAccountAccess,
+	/// EIP-2929 cold `SLOAD`.
+	ColdSload,
+	/// EIP-2929 access to a warm account or storage slot.
+	WarmAccess,
+	/// EIP-2200 `SSTORE` setting a zero slot.
+	SstoreSet,
+	/// EIP-2200 `SSTORE` of a slot already set, or written again in the transaction.
+	SstoreUpdate,
+	/// EIP-3529 `SSTORE` refund, capped at the end of the transaction.
+	SstoreRefund,
+	/// Value transfer of a call.
+	ValueTransfer,
+	/// EIP-161 creation of the account receiving the value of a call.
+	NewAccount,
+	/// EIP-150 gas forwarded to a subcall, at most 63/64 of the gas available.
+	ForwardedGas,
+	/// Stipend added to the gas forwarded to a value-bearing subcall.
+	CallStipend,
+	/// Cost charged by a precompile.
+	Precompile,
+}
+
+/// Inputs of a transaction the EVM can't recompute from the bytecode alone, which are enough to
+/// replay it offline without the state of the chain.
+#[derive(Debug, Clone, Default, Encode, Decode, PartialEq, Eq)]
+#[cfg_attr(feature = "cbor", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReplayRecord {
+	/// Results of the `BLOCKHASH`es: block number and hash.
+	pub block_hashes: Vec<(U256, H256)>,
+	/// Outputs of the frames executing no bytecode, i.e. the precompiles: address, input and
+	/// output.
+	pub precompile_outputs: Vec<(H160, Vec<u8>, Vec<u8>)>,
+	/// Code copied by the `EXTCODECOPY`s: address, offset in the code and copied bytes.
+	pub external_code: Vec<(H160, U256, Vec<u8>)>,
+	/// Storage slots read before any write of the transaction: address, slot and value.
+	pub storage: Vec<(H160, H256, H256)>,
+}
+
+#[derive(Debug, Clone, Encode, Decode, PartialEq, Eq)]
+#[cfg_attr(feature = "cbor", derive(serde::Serialize, serde::Deserialize))]
+pub enum TracerEvent {
+	/// Transaction traced, emitted right after the `Event::CallListNew` marker if the node
+	/// provided it.
+	Begin(TxContext),
+	/// Hardfork the EVM of the runtime is configured with, emitted at the start of the trace
+	/// after the `Begin` if the node provided it. Gas costs must be interpreted in its schedule.
+	Hardfork(Hardfork),
+	/// Data about the call frame opened by the preceding `EvmEvent::Call`.
+	Call {
+		/// Stipend added to the gas forwarded to the callee, if any.
+		/// Only value-bearing subcalls receive it, the transaction call never does.
+		stipend: Option<u64>,
+		/// 4-byte function selector of the input, `None` if the input is shorter than 4 bytes
+		/// (i.e. a plain transfer).
+		selector: Option<[u8; 4]>,
+		/// Precompile that initiated the call, directly or through one of the parent calls
+		/// (e.g. the batch precompile). Events of the frame can be attributed to it.
+		originating_precompile: Option<H160>,
+		/// `msg.sender` of the frame, preserved through a `DELEGATECALL`.
+		msg_sender: H160,
+		/// Address of the code that made the call, which differs from `msg_sender` when it
+		/// runs in the context of another contract. The caller of the transaction for the
+		/// transaction frame.
+		immediate_caller: H160,
+		/// Whether the callee existed before the call. Only known for the `CALL`s transferring
+		/// value, whose cost depends on it, `None` otherwise.
+		target_existed: Option<bool>,
+		/// Whether the call was charged `NEW_ACCOUNT_COST` for creating the callee.
+		new_account_cost_charged: bool,
+	},
+	/// Gas available to the frame opened by the last `EvmEvent::Call/Create`, after the 63/64
+	/// rule and the stipend are applied. Emitted once the caller recorded the gas forwarded to
+	/// the frame, before any event of the frame itself.
+	FrameStart { gas_at_frame_start: u64 },
+	/// An opcode expanded the memory of the current frame.
+	MemoryGrowth {
+		/// Program counter of the opcode.
+		pc: u64,
+		/// Memory size in bytes before the opcode.
+		old_size: u64,
+		/// Memory size in bytes after the opcode.
+		new_size: u64,
+		/// Gas charged for the expansion, on top of the opcode cost.
+		expansion_cost: u64,
+	},
+	/// A contract executed `SELFDESTRUCT`.
+	Selfdestruct {
+		/// Self destructing contract.
+		contract: H160,
+		/// Account receiving the balance of the contract.
+		beneficiary: H160,
+		/// Balance transferred to the beneficiary.
+		balance: U256,
+		/// Whether the contract is deleted. Since EIP-6780 it is only the case if the contract was
+		/// created in the same transaction, otherwise only its balance is transferred.
+		deleted: bool,
+	},
+	/// A `SHA3` opcode hashed `input`, pushing `output` on the stack.
+	/// Allows to find the keys of accessed mapping storage slots (`keccak(key . slot)`).
+	Keccak { input: Vec<u8>, output: H256 },
+	/// A `RETURNDATASIZE` or `RETURNDATACOPY` opcode accessed the return data of the last
+	/// subcall.
+	ReturnData {
+		/// Opcode accessing the return data.
+		opcode: u8,
+		/// Offset in the return data copied by `RETURNDATACOPY`, 0 for `RETURNDATASIZE`.
+		offset: U256,
+		/// Amount of bytes copied by `RETURNDATACOPY`, 0 for `RETURNDATASIZE`.
+		size: U256,
+		/// Size of the return data of the last subcall.
+		total_available: u64,
+		/// `RETURNDATACOPY` reads beyond `total_available`, which makes the opcode fail.
+		out_of_bounds: bool,
+	},
+	/// Sample of the gas remaining in the current frame, emitted every N steps.
+	GasSample {
+		/// Index of the step in the transaction, starting at 0.
+		step_index: u64,
+		/// Gas remaining in the current frame before the step.
+		gas_remaining: u64,
+	},
+	/// `JUMPDEST` reached, to attribute the gas used between jump destinations to the functions
+	/// they are the entry of.
+	Jumpdest {
+		/// Program counter of the `JUMPDEST`.
+		pc: u64,
+		/// Gas used in the current frame before the step, including the gas forwarded to its
+		/// subcalls.
+		gas_used_so_far: u64,
+	},
+	/// Call into an address already executing in one of the frames of the stack, emitted after
+	/// its `EvmEvent::Call`. Reentrancy is not necessarily malicious.
+	Reentrancy {
+		/// Address executing in both frames.
+		address: H160,
+		/// Depth of the outermost frame executing the address, 0 for the transaction frame.
+		original_depth: u64,
+		/// Depth of the frame opened by the call.
+		reentry_depth: u64,
+	},
+	/// Gas charged by a precompile, which executes no step, emitted after the
+	/// `EvmEvent::Exit` of its frame.
+	PrecompileGas {
+		/// Address of the precompile.
+		address: H160,
+		/// Gas used by the frame of the precompile, including the gas used by its subcalls.
+		gas_used: u64,
+	},
+	/// Call or create failing because the maximum call depth is reached, emitted after the
+	/// `EvmEvent::Exit` of its frame. The caller is not trapped and only sees a failure.
+	DepthLimit {
+		/// Depth of the frame that could not be entered, 0 being the transaction frame.
+		attempted_depth: u64,
+	},
+	/// Creation failing because an account with code or a non-zero nonce already exists at the
+	/// created address (EIP-684), emitted after the `EvmEvent::Exit` of its frame. Typically a
+	/// `CREATE2` deploying twice the same code with the same salt.
+	CreateCollision { address: H160 },
+	/// Frame of the preceding `RuntimeEvent::Step` or `EvmEvent::Exit`, unambiguous unlike the
+	/// depth, which is shared by sibling frames.
+	FrameId {
+		/// Unique in the transaction, increasing in the order frames are entered from 0 for the
+		/// transaction frame.
+		frame_id: u64,
+	},
+	/// Access to an account by the preceding `RuntimeEvent::Step`, priced depending on whether
+	/// the account is warm or cold since EIP-2929.
+	AccountAccess {
+		address: H160,
+		/// Whether it is the first access to the account in the transaction. The sender,
+		/// recipient and precompiles, as well as the coinbase since Shanghai, are warm from the
+		/// start of the transaction, and the contract itself always is.
+		cold: bool,
+	},
+	/// Recoverable issue met by the tracer when processing the preceding event.
+	Error(TracerError),
+	/// Nonce of the creator of the preceding `EvmEvent::Create`, if it is a `CREATE`, whose
+	/// address is `keccak(rlp(creator, creator_nonce))[12..]`.
+	CreatorNonce { creator_nonce: u64 },
+	/// Pricing rule of the preceding `GasometerEvent`.
+	GasExplanation { rule: GasRule },
+	/// Inputs of the transaction needed to replay it, emitted when the transaction frame exits.
+	/// The only event emitted in replay mode.
+	Replay(ReplayRecord),
+	/// Step of an opcode unknown to the tracer, e.g. added by a newer version of the EVM.
+	/// Emitted after the step event, the first time the opcode is executed in the trace.
+	UnknownOpcode {
+		/// Program counter of the step.
+		pc: u64,
+		opcode: u8,
+	},
+	/// Read of the input of the current frame by a `CALLDATALOAD` or `CALLDATACOPY`, emitted
+	/// after the step event.
+	CalldataRead {
+		offset: U256,
+		/// 32 for a `CALLDATALOAD`.
+		size: U256,
+	},
+	/// Values of the slot written by the preceding `RuntimeEvent::SStore`.
+	Sstore {
+		address: H160,
+		slot: H256,
+		/// Value of the slot before the write.
+		original: H256,
+		new: H256,
+		/// The write doesn't change the slot, although it still costs gas.
+		no_op: bool,
+	},
+	/// Last event of the trace, emitted if the events are committed to.
+	TraceEnd {
+		/// Root of the hash chain over the SCALE encoding of the events emitted before this one,
+		/// starting from zero: `root = keccak(root . event)` for each event.
+		commitment: H256,
+	},
+	/// The trace ran for longer than its time limit, no event is emitted after this one.
+	Timeout {
+		/// Time elapsed since the first step, in milliseconds.
+		elapsed: u64,
+	},
+	/// Summary of a transaction, the only event emitted in summary mode.
+	Summary {
+		/// Gas used by the transaction, before refunds.
+		gas_used: u64,
+		/// Addresses touched by the transaction: sender, callees, created contracts, value
+		/// recipients and self destruct beneficiaries.
+		touched: Vec<H160>,
+		gas_breakdown: GasBreakdown,
+		/// Hardfork the transaction ran under, if the node provided it.
+		hardfork: Option<Hardfork>,
+	},
+	/// Summary of the frame exited by the preceding `EvmEvent::Exit`.
+	FrameSummary {
+		/// Maximum size of the EVM stack observed before a step of the frame.
+		max_stack_depth: u64,
+	},
+	/// Event of a frame whose changes were rolled back, because the frame or one of its callers
+	/// reverted or errored. Only emitted with the `RevertPolicy::Flag` of the runtime tracer.
+	RolledBack(Box<Event>),
+	/// Marker emitted by the runtime at a chosen point of the execution, see
+	/// `EvmTracer::emit_marker`.
+	Marker {
+		/// UTF-8 label of the marker.
+		label: Vec<u8>,
+	},
+	/// Access to a storage slot of the current contract by the preceding `RuntimeEvent::Step`
+	/// (`SLOAD` or `SSTORE`).
+	StorageAccess {
+		address: H160,
+		slot: H256,
+		/// Whether it is the first access to the slot in the transaction. Accesses of a frame
+		/// that reverted or errored are forgotten, as in `AccountAccess`.
+		cold: bool,
+	},
+}
+
+/// Structural inconsistency found in a stream of events by `validate_stream`. `index` is the
+/// position of the faulty event in the stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamError {
+	/// An `EvmEvent::Exit` was received while no frame was entered.
+	OrphanExit { index: usize },
+	/// An `EvmEvent::TransactX` was received while frames of the previous transaction were not
+	/// exited.
+	NestedTransaction { index: usize, open_frames: usize },
+	/// A `TracerEvent` describing the opened frame doesn't follow the `EvmEvent` opening it.
+	UnexpectedFrameData { index: usize },
+	/// The stream, or the transaction ended by the `Event::CallListNew` at `index`, ended with
+	/// frames not exited.
+	UnclosedFrames { index: usize, open_frames: usize },
+	/// A `TracerEvent::Reentrancy` or `TracerEvent::DepthLimit` reports a depth other than the
+	/// one of the frames entered by the stream.
+	DepthMismatch {
+		index: usize,
+		reported: u64,
+		expected: u64,
+	},
+	/// A `TracerEvent::FrameId` reports an id differing from the one already reported for its
+	/// frame, already reported for another frame of the transaction, or not greater than the id
+	/// of a frame enclosing it.
+	InconsistentFrameId { index: usize, frame_id: u64 },
+}
+
+impl core::fmt::Display for StreamError {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		match self {
+			StreamError::OrphanExit { index } => {
+				write!(f, "event {}: exit without any entered frame", index)
+			}
+			StreamError::NestedTransaction { index, open_frames } => write!(
+				f,
+				"event {}: transaction started with {} frames not exited",
+				index, open_frames
+			),
+			StreamError::UnexpectedFrameData { index } => write!(
+				f,
+				"event {}: frame data not following the event opening the frame",
+				index
+			),
+			StreamError::UnclosedFrames { index, open_frames } => write!(
+				f,
+				"event {}: transaction ended with {} frames not exited",
+				index, open_frames
+			),
+			StreamError::DepthMismatch {
+				index,
+				reported,
+				expected,
+			} => write!(
+				f,
+				"event {}: depth {} reported instead of {}",
+				index, reported, expected
+			),
+			StreamError::InconsistentFrameId { index, frame_id } => write!(
+				f,
+				"event {}: frame id {} inconsistent with the frames entered",
+				index, frame_id
+			),
+		}
+	}
+}
+
+/// Checks that a stream of events forms a well-formed call tree: each exit matches a
+/// previously entered frame, all frames are exited when a transaction ends, and the depths and
+/// frame ids reported by the tracer events agree with the frames entered. Returns the first
+/// inconsistency found.
+pub fn validate_stream(events: &[Event]) -> Result<(), StreamError> {
+	// Frames entered and not exited, with the id reported for each of them if any.
+	let mut frames: Vec<Option<u64>> = Vec::new();
+	// Ids reported for the frames of the current transaction.
+	let mut frame_ids = BTreeSet::new();
+	// Like in the EVM, the call or create following a `TransactX` is the transaction frame.
+	let mut skip_next_frame = false;
+	// Whether the previous event opened a frame.
+	let mut frame_opened = false;
+	// Position in `frames` of the frame a `TracerEvent::FrameId` refers to, and the frame
+	// exited by the last `EvmEvent::Exit`, which is no longer in `frames`.
+	let mut current_frame: Option<usize> = None;
+	let mut exited_frame: Option<Option<u64>> = None;
+
+	for (index, event) in events.iter().enumerate() {
+		let opens_frame = match event {
+			Event::Evm(EvmEvent::TransactCall { .. })
+			| Event::Evm(EvmEvent::TransactCreate { .. })
+			| Event::Evm(EvmEvent::TransactCreate2 { .. }) => {
+				if !frames.is_empty() {
+					return Err(StreamError::NestedTransaction {
+						index,
+						open_frames: frames.len(),
+					});
+				}
+				frames.push(None);
+				frame_ids.clear();
+				skip_next_frame = true;
+				true
+			}
+			Event::Evm(EvmEvent::Call { .. }) | Event::Evm(EvmEvent::Create { .. }) => {
+				if skip_next_frame {
+					skip_next_frame = false;
+				} else {
+					frames.push(None);
+				}
+				true
+			}
+			Event::Evm(EvmEvent::Exit { .. }) => {
+				match frames.pop() {
+					Some(frame_id) => exited_frame = Some(frame_id),
+					None => return Err(StreamError::OrphanExit { index }),
+				}
+				false
+			}
+			Event::Tracer(TracerEvent::Call { .. }) => {
+				if !frame_opened {
+					return Err(StreamError::UnexpectedFrameData { index });
+				}
+				false
+			}
+			Event::Tracer(TracerEvent::Reentrancy {
+				original_depth,
+				reentry_depth,
+				..
+			}) => {
+				let expected = frames.len().saturating_sub(1) as u64;
+				if *reentry_depth != expected {
+					return Err(StreamError::DepthMismatch {
+						index,
+						reported: *reentry_depth,
+						expected,
+					});
+				}
+				if original_depth >= reentry_depth {
+					return Err(StreamError::DepthMismatch {
+						index,
+						reported: *original_depth,
+						expected: reentry_depth.saturating_sub(1),
+					});
+				}
+				false
+			}
+			Event::Tracer(TracerEvent::DepthLimit { attempted_depth }) => {
+				let expected = frames.len() as u64;
+				if *attempted_depth != expected {
+					return Err(StreamError::DepthMismatch {
+						index,
+						reported: *attempted_depth,
+						expected,
+					});
+				}
+				false
+			}
+			Event::Tracer(TracerEvent::FrameId { frame_id }) => {
+				let (reported, ancestors) = match (&mut exited_frame, current_frame) {
+					(Some(exited), _) => (exited, &frames[..]),
+					(None, Some(position)) => {
+						let (ancestors, current) = frames.split_at_mut(position);
+						(&mut current[0], &*ancestors)
+					}
+					(None, None) => return Err(StreamError::UnexpectedFrameData { index }),
+				};
+				let consistent = match *reported {
+					Some(reported) => reported == *frame_id,
+					None => {
+						!frame_ids.contains(frame_id)
+							&& ancestors.iter().flatten().all(|id| id < frame_id)
+					}
+				};
+				if !consistent {
+					return Err(StreamError::InconsistentFrameId {
+						index,
+						frame_id: *frame_id,
+					});
+				}
+				*reported = Some(*frame_id);
+				frame_ids.insert(*frame_id);
+				false
+			}
+			Event::CallListNew() => {
+				if !frames.is_empty() {
+					return Err(StreamError::UnclosedFrames {
+						index,
+						open_frames: frames.len(),
+					});
+				}
+				skip_next_frame = false;
+				false
+			}
+			_ => false,
+		};
+		frame_opened = opens_frame;
+		// A `TracerEvent::FrameId` refers to the preceding step or exit.
+		match event {
+			Event::Runtime(RuntimeEvent::Step { .. }) => {
+				current_frame = frames.len().checked_sub(1);
+				exited_frame = None;
+			}
+			Event::Evm(EvmEvent::Exit { .. }) => current_frame = None,
+			Event::Evm(_) | Event::CallListNew() => {
+				current_frame = None;
+				exited_frame = None;
+			}
+			_ => (),
+		}
+	}
+
+	if !frames.is_empty() {
+		return Err(StreamError::UnclosedFrames {
+			index: events.len(),
+			open_frames: frames.len(),
+		});
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		runtime::{ExitReason, ExitSucceed},
+		Context,
+	};
+
+	fn transact_call() -> Event {
+		Event::Evm(EvmEvent::TransactCall {
+			caller: H160::repeat_byte(0x01),
+			address: H160::repeat_byte(0x02),
+			value: U256::zero(),
+			data: Vec::new(),
+			gas_limit: 1_000_000,
+		})
+	}
+
+	fn call() -> Event {
+		Event::Evm(EvmEvent::Call {
+			code_address: H160::repeat_byte(0x02),
+			transfer: None,
+			input: Vec::new(),
+			target_gas: None,
+			is_static: false,
+			context: Context {
+				address: H160::repeat_byte(0x02),
+				caller: H160::repeat_byte(0x01),
+				apparent_value: U256::zero(),
+			},
+		})
+	}
+
+	fn call_data() -> Event {
+		Event::Tracer(TracerEvent::Call {
+			stipend: None,
+			selector: None,
+			originating_precompile: None,
+			msg_sender: H160::repeat_byte(0x01),
+			immediate_caller: H160::repeat_byte(0x01),
+			target_existed: None,
+			new_account_cost_charged: false,
+		})
+	}
+
+	fn exit() -> Event {
+		Event::Evm(EvmEvent::Exit {
+			reason: ExitReason::Succeed(ExitSucceed::Stopped),
+			return_value: Vec::new(),
+		})
+	}
+
+	fn step() -> Event {
+		Event::Runtime(RuntimeEvent::Step {
+			context: Context {
+				address: H160::repeat_byte(0x02),
+				caller: H160::repeat_byte(0x01),
+				apparent_value: U256::zero(),
+			},
+			opcode: b"Call".to_vec(),
+			position: Ok(0),
+			stack: None,
+			memory: None,
+		})
+	}
+
+	fn frame_id(frame_id: u64) -> Event {
+		Event::Tracer(TracerEvent::FrameId { frame_id })
+	}
+
+	fn reentrancy(original_depth: u64, reentry_depth: u64) -> Event {
+		Event::Tracer(TracerEvent::Reentrancy {
+			address: H160::repeat_byte(0x02),
+			original_depth,
+			reentry_depth,
+		})
+	}
+
+	#[test]
+	fn well_formed_stream_is_valid() {
+		let events = vec![
+			Event::CallListNew(),
+			transact_call(),
+			call(),
+			call_data(),
+			call(),
+			call_data(),
+			exit(),
+			exit(),
+			Event::CallListNew(),
+			transact_call(),
+			call(),
+			exit(),
+		];
+		assert_eq!(validate_stream(&events), Ok(()));
+	}
+
+	#[test]
+	fn extra_exit_is_reported() {
+		let events = vec![transact_call(), call(), call(), exit(), exit(), exit()];
+		assert_eq!(
+			validate_stream(&events),
+			Err(StreamError::OrphanExit { index: 5 })
+		);
+	}
+
+	#[test]
+	fn missing_exit_is_reported() {
+		let events = vec![
+			transact_call(),
+			call(),
+			call(),
+			exit(),
+			Event::CallListNew(),
+		];
+		assert_eq!(
+			validate_stream(&events),
+			Err(StreamError::UnclosedFrames {
+				index: 4,
+				open_frames: 1
+			})
+		);
+		assert_eq!(
+			validate_stream(&events[..4]),
+			Err(StreamError::UnclosedFrames {
+				index: 4,
+				open_frames: 1
+			})
+		);
+	}
+
+	#[test]
+	fn reported_depths_and_frame_ids_are_accepted() {
+		let events = vec![
+			transact_call(),
+			call(),
+			step(),
+			frame_id(0),
+			call(),
+			call_data(),
+			reentrancy(0, 1),
+			step(),
+			frame_id(1),
+			exit(),
+			frame_id(1),
+			Event::Tracer(TracerEvent::DepthLimit { attempted_depth: 1 }),
+			step(),
+			frame_id(0),
+			exit(),
+			frame_id(0),
+		];
+		assert_eq!(validate_stream(&events), Ok(()));
+	}
+
+	#[test]
+	fn wrong_depths_are_reported() {
+		// Balanced calls and exits, but the reentered frame is at depth 1.
+		let events = vec![
+			transact_call(),
+			call(),
+			call(),
+			reentrancy(0, 2),
+			exit(),
+			exit(),
+		];
+		assert_eq!(
+			validate_stream(&events),
+			Err(StreamError::DepthMismatch {
+				index: 3,
+				reported: 2,
+				expected: 1
+			})
+		);
+
+		let events = vec![
+			transact_call(),
+			call(),
+			call(),
+			exit(),
+			Event::Tracer(TracerEvent::DepthLimit { attempted_depth: 2 }),
+			exit(),
+		];
+		assert_eq!(
+			validate_stream(&events),
+			Err(StreamError::DepthMismatch {
+				index: 4,
+				reported: 2,
+				expected: 1
+			})
+		);
+	}
+
+	#[test]
+	fn inconsistent_frame_ids_are_reported() {
+		// The transaction frame exits with the id of its subcall.
+		let events = vec![
+			transact_call(),
+			call(),
+			call(),
+			exit(),
+			frame_id(1),
+			exit(),
+			frame_id(1),
+		];
+		assert_eq!(
+			validate_stream(&events),
+			Err(StreamError::InconsistentFrameId {
+				index: 6,
+				frame_id: 1
+			})
+		);
+
+		// A frame changes id between its steps.
+		let events = vec![
+			transact_call(),
+			call(),
+			step(),
+			frame_id(0),
+			step(),
+			frame_id(1),
+			exit(),
+		];
+		assert_eq!(
+			validate_stream(&events),
+			Err(StreamError::InconsistentFrameId {
+				index: 5,
+				frame_id: 1
+			})
+		);
+
+		// A subcall has a lower id than its caller.
+		let events = vec![
+			transact_call(),
+			call(),
+			step(),
+			frame_id(1),
+			call(),
+			step(),
+			frame_id(0),
+			exit(),
+			exit(),
+		];
+		assert_eq!(
+			validate_stream(&events),
+			Err(StreamError::InconsistentFrameId {
+				index: 6,
+				frame_id: 0
+			})
+		);
+	}
+}
@@ -104,8 +104,16 @@ macro_rules! impl_runtime_apis_plus_common {
 							let _ = match &ext.0.function {
 								Call::Ethereum(transact { transaction }) => {
 									if transaction == traced_transaction {
-										EvmTracer::new().trace(|| Executive::apply_extrinsic(ext));
-										return Ok(());
+										// The trace is incomplete if the transaction could not
+										// be applied.
+										return match EvmTracer::new()
+											.trace(|| Executive::apply_extrinsic(ext))
+										{
+											Ok(_) => Ok(()),
+											Err(_) => Err(sp_runtime::DispatchError::Other(
+												"Failed to apply the traced Ethereum transaction.",
+											)),
+										};
 									} else {
 										Executive::apply_extrinsic(ext)
 									}
@@ -143,9 +151,16 @@ macro_rules! impl_runtime_apis_plus_common {
 							match &ext.0.function {
 								Call::Ethereum(transact { transaction }) => {
 									if known_transactions.contains(&transaction.hash()) {
-										// Each known extrinsic is a new call stack.
-										EvmTracer::emit_new();
-										EvmTracer::new().trace(|| Executive::apply_extrinsic(ext));
+										// Each known extrinsic is a new call stack. The trace is
+										// incomplete if the transaction could not be applied.
+										if EvmTracer::new()
+											.trace(|| Executive::apply_extrinsic(ext))
+											.is_err()
+										{
+											return Err(sp_runtime::DispatchError::Other(
+												"Failed to apply a traced Ethereum transaction.",
+											));
+										}
 									} else {
 										let _ = Executive::apply_extrinsic(ext);
 									}
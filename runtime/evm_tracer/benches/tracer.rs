@@ -0,0 +1,169 @@
+// Copyright 2019-2022 PureStake Inc.
+// This file is part of Moonbeam.
+
+// Moonbeam is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Moonbeam is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Benchmarks of the tracer on a step-heavy transaction, run with
+//! `cargo bench -p moonbeam-evm-tracer`.
+
+#![feature(test)]
+
+extern crate test;
+
+use ethereum_types::{H160, H256, U256};
+use evm::backend::{MemoryAccount, MemoryBackend, MemoryVicinity};
+use evm::executor::stack::{MemoryStackState, StackExecutor, StackSubstateMetadata};
+use evm_tracing_events::{Event, Listener, StepEventFilter};
+use moonbeam_evm_tracer::tracer::{EvmTracer, OpcodeFilter};
+use std::collections::BTreeSet;
+use test::Bencher;
+
+/// Host listener dropping the events, requesting the stack and memory of the steps.
+struct Sink;
+
+impl Listener for Sink {
+	fn event(&mut self, event: Event) {
+		test::black_box(event);
+	}
+
+	fn step_event_filter(&self) -> StepEventFilter {
+		StepEventFilter {
+			enable_stack: true,
+			enable_memory: true,
+		}
+	}
+}
+
+/// Code looping 1024 times over 7 steps, i.e. about 7000 steps:
+/// PUSH2 0x0400 JUMPDEST PUSH1 1 SWAP1 SUB DUP1 PUSH1 3 JUMPI POP STOP
+fn loop_code() -> Vec<u8> {
+	vec![
+		0x61, 0x04, 0x00, 0x5b, 0x60, 0x01, 0x90, 0x03, 0x80, 0x60, 0x03, 0x57, 0x50, 0x00,
+	]
+}
+
+/// Execute `code` in a transaction calling the contract deploying it.
+fn execute(code: Vec<u8>) -> evm::ExitReason {
+	let eoa = H160::repeat_byte(0x01);
+	let contract = H160::repeat_byte(0x02);
+	let config = evm::Config::london();
+	let vicinity = MemoryVicinity {
+		gas_price: 1.into(),
+		origin: eoa,
+		chain_id: 1.into(),
+		block_hashes: vec![H256::repeat_byte(0xbb)],
+		block_number: 1.into(),
+		block_coinbase: H160::zero(),
+		block_timestamp: 1.into(),
+		block_difficulty: 0.into(),
+		block_gas_limit: 15_000_000.into(),
+		block_base_fee_per_gas: 0.into(),
+	};
+	let mut accounts = std::collections::BTreeMap::new();
+	accounts.insert(
+		contract,
+		MemoryAccount {
+			nonce: 1.into(),
+			balance: U256::zero(),
+			storage: Default::default(),
+			code,
+		},
+	);
+	let backend = MemoryBackend::new(&vicinity, accounts);
+	let metadata = StackSubstateMetadata::new(1_000_000, &config);
+	let state = MemoryStackState::new(metadata, &backend);
+	let precompiles = ();
+	let mut executor = StackExecutor::new_with_precompiles(state, &config, &precompiles);
+	let (reason, _) =
+		executor.transact_call(eoa, contract, 0.into(), Vec::new(), 1_000_000, Vec::new());
+	reason
+}
+
+/// Benchmark `f` in an environment with host functions, whose listener drops the events.
+fn bench_with_host<F: FnMut()>(b: &mut Bencher, mut f: F) {
+	sp_io::TestExternalities::default()
+		.execute_with(|| evm_tracing_events::using(&mut Sink, || b.iter(&mut f)));
+}
+
+#[bench]
+fn untraced(b: &mut Bencher) {
+	bench_with_host(b, || {
+		execute(loop_code());
+	});
+}
+
+#[bench]
+fn full_tracing(b: &mut Bencher) {
+	bench_with_host(b, || {
+		EvmTracer::new().trace(|| execute(loop_code()));
+	});
+}
+
+/// Summary mode, which skips the step events, to compare with `full_tracing`.
+#[bench]
+fn summary_only(b: &mut Bencher) {
+	bench_with_host(b, || {
+		let (_, result) = EvmTracer::new()
+			.summary_only()
+			.trace_with_result(|| execute(loop_code()));
+		test::black_box(result);
+	});
+}
+
+/// Cost of the tracer apart from the host calls, to compare with `full_tracing`.
+#[bench]
+fn bench_mode(b: &mut Bencher) {
+	bench_with_host(b, || {
+		let (tracer, _) = EvmTracer::new()
+			.bench_mode()
+			.trace_with_tracer(|| execute(loop_code()));
+		test::black_box(tracer.blackhole().map(|sink| sink.encoded_bytes));
+	});
+}
+
+/// Opcodes kept by the filters: the storage and call opcodes.
+const FILTERED: [u8; 6] = [0x54, 0x55, 0xf1, 0xf2, 0xf4, 0xfa];
+
+/// Check of every opcode against the bitset of `OpcodeFilter`.
+#[bench]
+fn opcode_filter_bitset(b: &mut Bencher) {
+	let filter = OpcodeFilter::from(&FILTERED[..]);
+	b.iter(|| {
+		(0..=u8::MAX)
+			.filter(|opcode| filter.contains(test::black_box(*opcode)))
+			.count()
+	});
+}
+
+/// Check of every opcode against the `BTreeSet` the filter was previously stored in, to
+/// compare with `opcode_filter_bitset`.
+#[bench]
+fn opcode_filter_btree_set(b: &mut Bencher) {
+	let filter: BTreeSet<u8> = FILTERED.iter().copied().collect();
+	b.iter(|| {
+		(0..=u8::MAX)
+			.filter(|opcode| filter.contains(test::black_box(opcode)))
+			.count()
+	});
+}
+
+/// Full tracing keeping only the steps of the filtered opcodes, none in the loop.
+#[bench]
+fn opcode_filtered_tracing(b: &mut Bencher) {
+	bench_with_host(b, || {
+		EvmTracer::new()
+			.with_opcode_filter(&FILTERED[..])
+			.trace(|| execute(loop_code()));
+	});
+}
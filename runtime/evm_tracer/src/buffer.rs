@@ -0,0 +1,279 @@
+// Copyright 2019-2022 PureStake Inc.
+// This file is part of Moonbeam.
+
+// Moonbeam is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Moonbeam is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Buffering of the events of the frames and transactions, until they exit and it is known
+//! whether their events are emitted.
+
+use crate::tracer::EvmTracer;
+use evm_tracing_events::{Event, TracerEvent};
+use sp_std::boxed::Box;
+
+/// Handling of the events of the frames whose changes are rolled back, because the frame or
+/// one of its callers reverted or errored.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RevertPolicy {
+	/// The events are not emitted.
+	Drop,
+	/// Each event is emitted wrapped in a `TracerEvent::RolledBack`, including the events
+	/// opening and exiting the frame.
+	Flag,
+	/// The events are emitted unchanged.
+	Keep,
+}
+
+impl EvmTracer {
+	/// Whether the events of each frame are buffered until it exits.
+	pub(crate) fn buffers_frames(&self) -> bool {
+		self.config.min_frame_gas.is_some() || self.config.revert_policy != RevertPolicy::Keep
+	}
+
+	/// Outputs the buffered events of the exited frame if it used at least the minimum frame
+	/// gas, and discards them otherwise. The revert policy is then applied if the frame
+	/// didn't succeed. The events of a frame are outputted with the ones of its parent, which
+	/// used at least as much gas and is rolled back as well if it fails.
+	pub(crate) fn close_frame_buffer(&mut self, gas_used: u64, succeeded: bool) {
+		if !self.buffers_frames() {
+			return;
+		}
+		let mut events = match self.frame_buffers.pop() {
+			Some(events)
+				if self
+					.config
+					.min_frame_gas
+					.map_or(true, |min| gas_used >= min) =>
+			{
+				events
+			}
+			_ => return,
+		};
+		if !succeeded {
+			match self.config.revert_policy {
+				RevertPolicy::Drop => return,
+				RevertPolicy::Flag => {
+					events = events
+						.into_iter()
+						.map(|event| match event {
+							// Events of a subcall already rolled back.
+							Event::Tracer(TracerEvent::RolledBack(_)) => event,
+							event => Event::Tracer(TracerEvent::RolledBack(Box::new(event))),
+						})
+						.collect();
+				}
+				RevertPolicy::Keep => (),
+			}
+		}
+		match self.frame_buffers.last_mut() {
+			Some(parent) => parent.extend(events),
+			None => {
+				for event in events {
+					self.output(event);
+				}
+			}
+		}
+	}
+
+	/// Sends the buffered events to the host if the transaction frame failed, and discards
+	/// them otherwise.
+	pub(crate) fn flush_failed_only_buffer(&mut self, failed: bool) {
+		if let Some(buffer) = &mut self.failed_only_buffer {
+			let events = sp_std::mem::take(buffer);
+			if failed {
+				for event in events {
+					self.deliver(event);
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::*;
+	use evm_tracing_events::tracer::validate_stream;
+
+	#[test]
+	fn only_if_failed_discards_succeeding_transactions() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+
+		let succeeding = trace_events_with(
+			|tracer| tracer.only_if_failed(),
+			|tracer| {
+				transact_call(tracer, eoa, contract, 0.into());
+				call(tracer, eoa, contract, 0.into(), &[], None);
+				record_cost(tracer, 3);
+				exit(tracer);
+			},
+		);
+		assert!(succeeding.is_empty());
+
+		let reverting = trace_events_with(
+			|tracer| tracer.only_if_failed(),
+			|tracer| {
+				transact_call(tracer, eoa, contract, 0.into());
+				call(tracer, eoa, contract, 0.into(), &[], None);
+				record_cost(tracer, 3);
+				exit_with(
+					tracer,
+					evm::ExitReason::Revert(evm::ExitRevert::Reverted),
+					&[],
+				);
+			},
+		);
+		assert_eq!(
+			reverting
+				.iter()
+				.filter(|event| matches!(event, Event::Evm(_)))
+				.count(),
+			3
+		);
+		assert!(reverting
+			.iter()
+			.any(|event| matches!(event, Event::Gasometer(_))));
+	}
+
+	#[test]
+	fn min_frame_gas_drops_cheap_frames() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let cheap = H160::repeat_byte(0x03);
+		let expensive = H160::repeat_byte(0x04);
+
+		let events = trace_events_with(
+			|tracer| tracer.with_min_frame_gas(1_000),
+			|tracer| {
+				transact_call(tracer, eoa, contract, 0.into());
+				call(tracer, eoa, contract, 0.into(), &[], None);
+				record_cost(tracer, 100_000);
+				call(tracer, contract, cheap, 0.into(), &[], None);
+				record_cost_with_snapshot(tracer, 10_000, 100_000, 0);
+				record_cost_with_snapshot(tracer, 100, 10_000, 0);
+				exit(tracer);
+				call(tracer, contract, expensive, 0.into(), &[], None);
+				record_cost_with_snapshot(tracer, 50_000, 100_000, 0);
+				record_cost_with_snapshot(tracer, 30_000, 50_000, 0);
+				exit(tracer);
+				record_cost_with_snapshot(tracer, 3, 100_000, 40_000);
+				exit(tracer);
+			},
+		);
+
+		let calls: Vec<_> = events
+			.iter()
+			.filter_map(|event| match event {
+				Event::Evm(EvmEvent::Call { code_address, .. }) => Some(*code_address),
+				_ => None,
+			})
+			.collect();
+		let exits = events
+			.iter()
+			.filter(|event| matches!(event, Event::Evm(EvmEvent::Exit { .. })))
+			.count();
+		assert_eq!(calls, vec![contract, expensive]);
+		assert_eq!(exits, 2);
+	}
+
+	fn reverting_subcall_events(policy: RevertPolicy) -> Vec<Event> {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let callee = H160::repeat_byte(0x03);
+		let revert = evm::ExitReason::Revert(evm::ExitRevert::Reverted);
+
+		trace_events_with(
+			|tracer| tracer.with_revert_policy(policy),
+			|tracer| {
+				transact_call(tracer, eoa, contract, 0.into());
+				call(tracer, eoa, contract, 0.into(), &[], None);
+				call(tracer, contract, callee, 0.into(), &[], None);
+				sstore(
+					tracer,
+					callee,
+					H256::repeat_byte(0x01),
+					H256::repeat_byte(0x02),
+				);
+				exit_with(tracer, revert, &[]);
+				sstore(
+					tracer,
+					contract,
+					H256::repeat_byte(0x03),
+					H256::repeat_byte(0x04),
+				);
+				exit(tracer);
+			},
+		)
+	}
+
+	fn stored_addresses(events: &[Event]) -> Vec<H160> {
+		events
+			.iter()
+			.filter_map(|event| match event {
+				Event::Runtime(RuntimeEvent::SStore { address, .. }) => Some(*address),
+				_ => None,
+			})
+			.collect()
+	}
+
+	#[test]
+	fn revert_policy_keep_emits_rolled_back_events() {
+		let events = reverting_subcall_events(RevertPolicy::Keep);
+
+		assert_eq!(
+			stored_addresses(&events),
+			vec![H160::repeat_byte(0x03), H160::repeat_byte(0x02)]
+		);
+		assert!(!events
+			.iter()
+			.any(|event| matches!(event, Event::Tracer(TracerEvent::RolledBack(_)))));
+	}
+
+	#[test]
+	fn revert_policy_drop_removes_rolled_back_events() {
+		let events = reverting_subcall_events(RevertPolicy::Drop);
+
+		assert_eq!(stored_addresses(&events), vec![H160::repeat_byte(0x02)]);
+		let calls = events
+			.iter()
+			.filter(|event| matches!(event, Event::Evm(EvmEvent::Call { .. })))
+			.count();
+		assert_eq!(calls, 1);
+		assert_eq!(validate_stream(&events), Ok(()));
+	}
+
+	#[test]
+	fn revert_policy_flag_wraps_rolled_back_events() {
+		let events = reverting_subcall_events(RevertPolicy::Flag);
+
+		let (rolled_back, committed): (Vec<_>, Vec<_>) = events
+			.iter()
+			.cloned()
+			.partition(|event| matches!(event, Event::Tracer(TracerEvent::RolledBack(_))));
+		assert_eq!(committed, reverting_subcall_events(RevertPolicy::Drop));
+		let unwrapped: Vec<_> = events
+			.into_iter()
+			.map(|event| match event {
+				Event::Tracer(TracerEvent::RolledBack(event)) => *event,
+				event => event,
+			})
+			.collect();
+		assert_eq!(unwrapped, reverting_subcall_events(RevertPolicy::Keep));
+		assert!(matches!(
+			rolled_back.last(),
+			Some(Event::Tracer(TracerEvent::RolledBack(event)))
+				if matches!(**event, Event::Evm(EvmEvent::Exit { .. }))
+		));
+	}
+}
@@ -0,0 +1,127 @@
+// Copyright 2019-2022 PureStake Inc.
+// This file is part of Moonbeam.
+
+// Moonbeam is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Moonbeam is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Options of the tracer, set by the `with_*` builders of `EvmTracer`.
+
+use crate::buffer::RevertPolicy;
+use crate::listener::Subsystem;
+use crate::opcode_filter::OpcodeFilter;
+use crate::schedule::Hardfork;
+use ethereum_types::H160;
+use evm_tracing_events::{Encoding, EVM_STREAM_ID};
+use sp_std::collections::btree_set::BTreeSet;
+
+/// Options of the tracer, see the `with_*` builders of `EvmTracer`.
+pub(crate) struct Config {
+	pub(crate) encoding: Encoding,
+	/// Order in which the listeners of each subsystem are registered, innermost first.
+	pub(crate) listener_order: [Subsystem; 3],
+	/// Stream of the events on the host, see `with_stream_id`.
+	pub(crate) stream_id: u16,
+	/// Hardfork of the EVM configuration of the runtime, see `with_hardfork`.
+	pub(crate) hardfork: Option<Hardfork>,
+	/// Coinbase of the block, warm since Shanghai.
+	pub(crate) coinbase: Option<H160>,
+	/// Emit `TracerEvent::MemoryGrowth` events.
+	pub(crate) memory_growth: bool,
+	/// `SELFDESTRUCT` follows EIP-6780 semantics.
+	pub(crate) eip_6780: bool,
+	/// If set, step events are only emitted while executing the code of those addresses.
+	pub(crate) deep_trace_addresses: Option<BTreeSet<H160>>,
+	/// If set, step events are only emitted for those opcodes.
+	pub(crate) opcode_filter: Option<OpcodeFilter>,
+	/// Emit `TracerEvent::Keccak` events.
+	pub(crate) keccak_preimages: bool,
+	/// Emit `TracerEvent::ReturnData` events.
+	pub(crate) return_data_accesses: bool,
+	/// Don't emit `SLOAD`s reading the value already emitted for the slot in the frame.
+	pub(crate) sload_dedup: bool,
+	/// Emit `TracerEvent::FrameSummary` events.
+	pub(crate) frame_summaries: bool,
+	/// Only emit a `TracerEvent::Summary` when the transaction frame exits.
+	pub(crate) summary_only: bool,
+	/// Only emit a `TracerEvent::Replay` when the transaction frame exits, after the start
+	/// of the trace.
+	pub(crate) replay_capture: bool,
+	/// If set, a `TracerEvent::GasSample` is emitted every N steps.
+	pub(crate) gas_sampling: Option<u64>,
+	/// Whether to emit a `TracerEvent::Jumpdest` at each `JUMPDEST`.
+	pub(crate) jumpdest_gas_tracking: bool,
+	/// Emit `TracerEvent::Reentrancy` events.
+	pub(crate) reentrancy_detection: bool,
+	/// Emit `TracerEvent::PrecompileGas` events.
+	pub(crate) precompile_gas: bool,
+	/// Emit `TracerEvent::CalldataRead` events.
+	pub(crate) calldata_reads: bool,
+	/// Emit `TracerEvent::AccountAccess` and `TracerEvent::StorageAccess` events.
+	pub(crate) account_accesses: bool,
+	/// Emit `TracerEvent::GasExplanation` events.
+	pub(crate) gas_explanations: bool,
+	/// If set, emission stops once the trace ran for longer than this.
+	#[cfg(feature = "std")]
+	pub(crate) time_limit: Option<std::time::Duration>,
+	/// Amount of steps between two checks of the time limit.
+	#[cfg(feature = "std")]
+	pub(crate) time_check_interval: u64,
+	/// If set, only the events of the frames using at least this gas are emitted.
+	pub(crate) min_frame_gas: Option<u64>,
+	/// Handling of the events of the frames rolled back.
+	pub(crate) revert_policy: RevertPolicy,
+	/// Emit `TracerEvent::Call` and `TracerEvent::FrameStart` events.
+	pub(crate) call_details: bool,
+	/// Emit a `TracerEvent::FrameId` after each step and exit event.
+	pub(crate) frame_ids: bool,
+	/// Zero the data of the events, see `with_data_redaction`.
+	pub(crate) data_redaction: bool,
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Self {
+			encoding: Encoding::default(),
+			listener_order: [Subsystem::Runtime, Subsystem::Gasometer, Subsystem::Evm],
+			stream_id: EVM_STREAM_ID,
+			hardfork: None,
+			coinbase: None,
+			memory_growth: false,
+			eip_6780: false,
+			deep_trace_addresses: None,
+			opcode_filter: None,
+			keccak_preimages: false,
+			return_data_accesses: false,
+			sload_dedup: false,
+			frame_summaries: false,
+			summary_only: false,
+			replay_capture: false,
+			gas_sampling: None,
+			jumpdest_gas_tracking: false,
+			reentrancy_detection: false,
+			precompile_gas: false,
+			calldata_reads: false,
+			account_accesses: false,
+			gas_explanations: false,
+			#[cfg(feature = "std")]
+			time_limit: None,
+			#[cfg(feature = "std")]
+			time_check_interval: 1_000,
+			min_frame_gas: None,
+			revert_policy: RevertPolicy::Keep,
+			call_details: false,
+			frame_ids: false,
+			data_redaction: false,
+		}
+	}
+}
@@ -0,0 +1,592 @@
+// Copyright 2019-2022 PureStake Inc.
+// This file is part of Moonbeam.
+
+// Moonbeam is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Moonbeam is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Gas accounting of the tracer: the gas of the frames, the expansions of the memory and the
+//! pricing rules of the costs.
+
+use crate::schedule::Hardfork;
+use crate::tracer::EvmTracer;
+use evm::Opcode;
+use evm_tracing_events::{
+	tracer::{GasRule, NEW_ACCOUNT_COST},
+	GasometerEvent, TracerEvent,
+};
+use sp_std::vec::Vec;
+
+/// EIP-2929 surcharge of the first access to a storage slot in the transaction.
+const COLD_SLOAD_COST: u64 = 2_100;
+/// EIP-2200 cost of an `SSTORE` setting a zero slot.
+const SSTORE_SET_COST: u64 = 20_000;
+/// Cost of a value transfer by a call.
+const CALL_VALUE_COST: u64 = 9_000;
+
+impl EvmTracer {
+	/// Records the gas of the current frame after the gas event, returning the
+	/// `TracerEvent::FrameStart` if the event records the gas forwarded to it.
+	pub(crate) fn record_frame_gas(&mut self, event: &GasometerEvent) -> Option<TracerEvent> {
+		// The first cost recorded after a frame is opened is the gas forwarded to it, recorded by
+		// the gasometer of its parent.
+		let frame_start = match *event {
+			GasometerEvent::RecordCost { cost, .. } => {
+				self.state
+					.pending_frame_start
+					.take()
+					.map(|stipend| TracerEvent::FrameStart {
+						gas_at_frame_start: cost.saturating_add(stipend),
+					})
+			}
+			_ => {
+				self.state.pending_frame_start = None;
+				None
+			}
+		};
+
+		// The forwarded gas is recorded by the gasometer of the parent, but is the gas of the
+		// frame just opened.
+		match &frame_start {
+			Some(TracerEvent::FrameStart { gas_at_frame_start }) => {
+				if let Some(frame) = self.state.frames.last_mut() {
+					frame.gas_at_start = *gas_at_frame_start;
+					frame.gas_remaining = *gas_at_frame_start;
+				}
+			}
+			_ => {
+				if let Some(frame) = self.state.frames.last_mut() {
+					frame.gas_remaining = gas_remaining_after(event);
+				}
+			}
+		}
+
+		// The dynamic cost of a `CALL` transferring value only reaches the new account cost if it
+		// is charged, the other costs of the call being lower.
+		if let GasometerEvent::RecordDynamicCost { gas_cost, .. } = *event {
+			if sp_std::mem::take(&mut self.state.pending_value_call) {
+				self.state.pending_target_existed = Some(gas_cost < NEW_ACCOUNT_COST);
+			}
+		}
+		frame_start
+	}
+
+	/// `TracerEvent::MemoryGrowth` of the gas event, if it expands the memory.
+	pub(crate) fn memory_growth(&self, event: &GasometerEvent) -> Option<TracerEvent> {
+		match *event {
+			// `memory_gas` is the memory cost after the opcode while the snapshot is taken before
+			// the cost is recorded.
+			GasometerEvent::RecordDynamicCost {
+				memory_gas,
+				snapshot,
+				..
+			} if memory_gas > snapshot.memory_gas => Some(TracerEvent::MemoryGrowth {
+				pc: self.state.pc,
+				old_size: memory_words(snapshot.memory_gas) * 32,
+				new_size: memory_words(memory_gas) * 32,
+				expansion_cost: memory_gas - snapshot.memory_gas,
+			}),
+			_ => None,
+		}
+	}
+
+	/// Gas events of the step of the opcode, whose index in the transaction is `step_index`.
+	pub(crate) fn step_gas_events(&self, opcode: Opcode, step_index: u64) -> Vec<TracerEvent> {
+		let frame = match self.state.frames.last() {
+			Some(frame) => frame,
+			None => return Vec::new(),
+		};
+		let mut events = Vec::new();
+		match self.config.gas_sampling {
+			Some(n) if step_index % n == 0 => {
+				events.push(TracerEvent::GasSample {
+					step_index,
+					gas_remaining: frame.gas_remaining,
+				});
+			}
+			_ => (),
+		}
+		if self.config.jumpdest_gas_tracking && opcode == Opcode::JUMPDEST {
+			events.push(TracerEvent::Jumpdest {
+				pc: self.state.pc,
+				gas_used_so_far: frame.gas_at_start.saturating_sub(frame.gas_remaining),
+			});
+		}
+		events
+	}
+
+	/// Pricing rule accounting for most of the gas event, given whether it records the gas
+	/// forwarded to a frame.
+	pub(crate) fn gas_rule(&self, event: &GasometerEvent, forwarded: bool) -> GasRule {
+		let (gas_cost, memory_expansion) = match *event {
+			GasometerEvent::RecordTransaction { .. } => return GasRule::Intrinsic,
+			GasometerEvent::RecordRefund { .. } => return GasRule::SstoreRefund,
+			GasometerEvent::RecordStipend { .. } => return GasRule::CallStipend,
+			GasometerEvent::RecordCost { .. } if forwarded => return GasRule::ForwardedGas,
+			GasometerEvent::RecordCost { .. } => {
+				// Precompiles charge their cost without executing any step.
+				return match self.state.frames.last() {
+					Some(frame) if !frame.executed => GasRule::Precompile,
+					_ => GasRule::Base,
+				};
+			}
+			GasometerEvent::RecordDynamicCost {
+				gas_cost,
+				memory_gas,
+				snapshot,
+				..
+			} => (gas_cost, memory_gas.saturating_sub(snapshot.memory_gas)),
+		};
+		if memory_expansion > gas_cost {
+			return GasRule::MemoryExpansion;
+		}
+
+		// The rule is decided from the opcode and the access it makes, the cost being the sum of
+		// the costs of several rules.
+		let eip_2929 = self
+			.config
+			.hardfork
+			.map_or(true, |hardfork| hardfork >= Hardfork::Berlin);
+		let cold = eip_2929 && self.state.step_cold_access.unwrap_or(false);
+		let access = if !eip_2929 {
+			GasRule::Base
+		} else if cold {
+			GasRule::ColdAccountAccess
+		} else {
+			GasRule::WarmAccess
+		};
+		match self.state.opcode {
+			Some(Opcode::SLOAD) if cold => GasRule::ColdSload,
+			Some(Opcode::SLOAD) => access,
+			Some(Opcode::SSTORE) => {
+				// Without the EIP-2929 surcharge of a cold slot, only setting a zero slot
+				// costs the EIP-2200 set cost.
+				let surcharge = if cold { COLD_SLOAD_COST } else { 0 };
+				if gas_cost.saturating_sub(surcharge) >= SSTORE_SET_COST {
+					GasRule::SstoreSet
+				} else {
+					GasRule::SstoreUpdate
+				}
+			}
+			Some(
+				Opcode::BALANCE | Opcode::EXTCODESIZE | Opcode::EXTCODECOPY | Opcode::EXTCODEHASH,
+			) => access,
+			Some(Opcode::CALL) if self.state.step_transfers_value => {
+				// The access and the transfer cost less than the new account cost, charged on
+				// top of them when the callee is empty.
+				if gas_cost >= CALL_VALUE_COST + NEW_ACCOUNT_COST {
+					GasRule::NewAccount
+				} else {
+					GasRule::ValueTransfer
+				}
+			}
+			Some(Opcode::CALLCODE) if self.state.step_transfers_value => GasRule::ValueTransfer,
+			Some(Opcode::CALL | Opcode::CALLCODE | Opcode::DELEGATECALL | Opcode::STATICCALL) => {
+				access
+			}
+			_ => GasRule::Operands,
+		}
+	}
+}
+
+/// Gas remaining in the gasometer after the event is recorded. The snapshot is taken before
+/// it is recorded.
+fn gas_remaining_after(event: &GasometerEvent) -> u64 {
+	match *event {
+		GasometerEvent::RecordCost { cost, snapshot }
+		| GasometerEvent::RecordTransaction { cost, snapshot } => snapshot.gas().saturating_sub(cost),
+		GasometerEvent::RecordDynamicCost {
+			gas_cost,
+			memory_gas,
+			snapshot,
+			..
+		} => snapshot
+			.gas_limit
+			.saturating_sub(snapshot.used_gas)
+			.saturating_sub(gas_cost)
+			.saturating_sub(memory_gas),
+		GasometerEvent::RecordStipend { stipend, snapshot } => {
+			snapshot.gas().saturating_add(stipend)
+		}
+		GasometerEvent::RecordRefund { snapshot, .. } => snapshot.gas(),
+	}
+}
+
+/// Gas cost of a memory of `words` 32-bytes words (yellow paper `C_mem`).
+fn memory_cost(words: u64) -> u64 {
+	words
+		.saturating_mul(3)
+		.saturating_add(words.saturating_mul(words) / 512)
+}
+
+/// Size in words of the memory whose cost is `memory_gas`, which is the smallest amount of
+/// words costing at least `memory_gas`.
+fn memory_words(memory_gas: u64) -> u64 {
+	let (mut low, mut high) = (0, memory_gas / 3);
+	while low < high {
+		let mid = low + (high - low) / 2;
+		if memory_cost(mid) < memory_gas {
+			low = mid + 1;
+		} else {
+			high = mid;
+		}
+	}
+	low
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::*;
+
+	#[test]
+	fn value_bearing_subcall_reports_stipend() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let callee = H160::repeat_byte(0x03);
+
+		let events = trace_events_with(
+			|tracer| tracer.with_call_details(),
+			|tracer| {
+				transact_call(tracer, eoa, contract, 0.into());
+				call(tracer, eoa, contract, 10.into(), &[], None);
+				call(tracer, contract, callee, 10.into(), &[], Some(0));
+				call(tracer, callee, contract, 0.into(), &[], Some(0));
+				exit(tracer);
+				exit(tracer);
+				exit(tracer);
+			},
+		);
+
+		// The tracer event directly follows the call event it refers to.
+		assert!(matches!(
+			events[1],
+			Event::Evm(EvmEvent::Call {
+				target_gas: None,
+				..
+			})
+		));
+		assert!(matches!(
+			events[2],
+			Event::Tracer(TracerEvent::Call { stipend: None, .. })
+		));
+
+		let stipends: Vec<_> = tracer_events(&events)
+			.into_iter()
+			.filter_map(|event| match event {
+				TracerEvent::Call { stipend, .. } => Some(stipend),
+				_ => None,
+			})
+			.collect();
+		// The transaction call never receives the stipend, even with a value.
+		assert_eq!(stipends, vec![None, Some(2300), None]);
+	}
+
+	#[test]
+	fn frame_start_reports_forwarded_gas() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let callee = H160::repeat_byte(0x03);
+
+		let events = trace_events_with(
+			|tracer| tracer.with_call_details(),
+			|tracer| {
+				transact_call(tracer, eoa, contract, 0.into());
+				call(tracer, eoa, contract, 0.into(), &[], None);
+				record_cost(tracer, 100_000);
+				record_cost(tracer, 3);
+				call(tracer, contract, callee, 0.into(), &[], None);
+				record_cost(tracer, 60_000);
+				record_cost(tracer, 3);
+				// Value-bearing call, receiving the stipend.
+				call(tracer, callee, contract, 1.into(), &[], None);
+				record_cost(tracer, 30_000);
+				exit(tracer);
+				exit(tracer);
+				exit(tracer);
+			},
+		);
+
+		let gas: Vec<_> = tracer_events(&events)
+			.into_iter()
+			.filter_map(|event| match event {
+				TracerEvent::FrameStart { gas_at_frame_start } => Some(gas_at_frame_start),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(gas, vec![100_000, 60_000, 32_300]);
+	}
+
+	#[test]
+	fn value_transfer_to_fresh_account_charges_new_account_cost() {
+		let fresh = H160::repeat_byte(0x03);
+		// PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 1 PUSH20 fresh GAS CALL STOP
+		let mut code = vec![
+			0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x01, 0x73,
+		];
+		code.extend_from_slice(fresh.as_bytes());
+		code.extend_from_slice(&[0x5a, 0xf1, 0x00]);
+
+		let events = host_events(|| {
+			EvmTracer::new()
+				.with_call_details()
+				.trace(|| execute_with_balance(code, 1.into()));
+		});
+
+		let calls: Vec<_> = tracer_events(&events)
+			.into_iter()
+			.filter_map(|event| match event {
+				TracerEvent::Call {
+					target_existed,
+					new_account_cost_charged,
+					..
+				} => Some((target_existed, new_account_cost_charged)),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(calls, vec![(None, false), (Some(false), true)]);
+		// Cold account access, value transfer and account creation.
+		assert!(events.iter().any(|event| matches!(
+			event,
+			Event::Gasometer(GasometerEvent::RecordDynamicCost {
+				gas_cost: 36_600,
+				..
+			})
+		)));
+	}
+
+	#[test]
+	fn memory_growth_reports_expansion() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let trace = |tracer: &mut EvmTracer| {
+			transact_call(tracer, eoa, contract, 0.into());
+			call(tracer, eoa, contract, 0.into(), &[], None);
+			// MSTORE at offset 0x1000 grows the memory to 0x1020 bytes (129 words).
+			step(tracer, evm::Opcode::MSTORE, 5, &[]);
+			record_dynamic_cost(tracer, 3, 3 * 129 + 129 * 129 / 512, 0);
+			// MSTORE at offset 0 doesn't expand the memory.
+			step(tracer, evm::Opcode::MSTORE, 9, &[]);
+			record_dynamic_cost(
+				tracer,
+				3,
+				3 * 129 + 129 * 129 / 512,
+				3 * 129 + 129 * 129 / 512,
+			);
+			exit(tracer);
+		};
+
+		let memory_growths = |events: Vec<Event>| -> Vec<TracerEvent> {
+			tracer_events(&events)
+				.into_iter()
+				.filter(|event| matches!(event, TracerEvent::MemoryGrowth { .. }))
+				.collect()
+		};
+
+		assert_eq!(memory_growths(trace_events(trace)), vec![]);
+		assert_eq!(
+			memory_growths(trace_events_with(
+				|tracer| tracer.with_memory_growth(),
+				trace
+			)),
+			vec![TracerEvent::MemoryGrowth {
+				pc: 5,
+				old_size: 0,
+				new_size: 0x1020,
+				expansion_cost: 419,
+			}]
+		);
+	}
+
+	#[test]
+	fn cold_sload_is_explained() {
+		// PUSH1 0 SLOAD PUSH1 0 SLOAD STOP
+		let code = vec![0x60, 0x00, 0x54, 0x60, 0x00, 0x54, 0x00];
+
+		let events = host_events(|| {
+			EvmTracer::new()
+				.with_gas_explanations()
+				.trace(|| execute(code));
+		});
+
+		let explained_sloads: Vec<_> = events
+			.windows(2)
+			.filter_map(|pair| match (&pair[0], &pair[1]) {
+				(
+					Event::Gasometer(GasometerEvent::RecordDynamicCost { gas_cost, .. }),
+					Event::Tracer(TracerEvent::GasExplanation { rule }),
+				) => Some((*gas_cost, *rule)),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(
+			explained_sloads,
+			vec![(2_100, GasRule::ColdSload), (100, GasRule::WarmAccess)]
+		);
+	}
+
+	#[test]
+	fn call_gas_is_explained_from_the_call() {
+		let fresh = H160::repeat_byte(0x03);
+		let mut code = Vec::new();
+		// Cold call expanding the memory for its input, then value transfers creating the
+		// account and to the created account:
+		// PUSH1 0 PUSH1 0 PUSH1 0x20 PUSH1 0 PUSH1 value PUSH20 fresh GAS CALL POP
+		for (args_size, value) in [(0x20, 0), (0, 1), (0, 1)] {
+			code.extend_from_slice(&[0x60, 0x00, 0x60, 0x00, 0x60, args_size, 0x60, 0x00]);
+			code.extend_from_slice(&[0x60, value, 0x73]);
+			code.extend_from_slice(fresh.as_bytes());
+			code.extend_from_slice(&[0x5a, 0xf1, 0x50]);
+		}
+		// PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH20 fresh GAS STATICCALL POP STOP
+		code.extend_from_slice(&[0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x73]);
+		code.extend_from_slice(fresh.as_bytes());
+		code.extend_from_slice(&[0x5a, 0xfa, 0x50, 0x00]);
+
+		let events = host_events(|| {
+			EvmTracer::new()
+				.with_gas_explanations()
+				.trace(|| execute_with_balance(code, 2.into()));
+		});
+
+		let explained_calls: Vec<_> = events
+			.windows(2)
+			.filter_map(|pair| match (&pair[0], &pair[1]) {
+				(
+					Event::Gasometer(GasometerEvent::RecordDynamicCost { gas_cost, .. }),
+					Event::Tracer(TracerEvent::GasExplanation { rule }),
+				) => Some((*gas_cost, *rule)),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(
+			explained_calls,
+			vec![
+				(2_600, GasRule::ColdAccountAccess),
+				(34_100, GasRule::NewAccount),
+				(9_100, GasRule::ValueTransfer),
+				(100, GasRule::WarmAccess),
+			]
+		);
+	}
+
+	#[test]
+	fn gas_sampling_emits_every_n_steps() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+
+		let events = trace_events_with(
+			|tracer| tracer.with_gas_sampling(4),
+			|tracer| {
+				transact_call(tracer, eoa, contract, 0.into());
+				call(tracer, eoa, contract, 0.into(), &[], None);
+				record_cost(tracer, 100_000);
+				// Loop of 10 steps costing 3 gas each.
+				for i in 0..10 {
+					step(tracer, evm::Opcode::ADD, i as usize, &[]);
+					record_cost_with_snapshot(tracer, 3, 100_000, 3 * i);
+				}
+				exit(tracer);
+			},
+		);
+
+		let samples: Vec<_> = tracer_events(&events)
+			.into_iter()
+			.filter_map(|event| match event {
+				TracerEvent::GasSample {
+					step_index,
+					gas_remaining,
+				} => Some((step_index, gas_remaining)),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(samples, vec![(0, 100_000), (4, 99_988), (8, 99_976)]);
+	}
+
+	#[test]
+	fn jumpdest_gas_tracking_reports_cumulative_gas() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+
+		let events = trace_events_with(
+			|tracer| tracer.with_jumpdest_gas_tracking(),
+			|tracer| {
+				transact_call(tracer, eoa, contract, 0.into());
+				call(tracer, eoa, contract, 0.into(), &[], None);
+				record_cost(tracer, 100_000);
+				step(tracer, evm::Opcode::PUSH1, 0, &[]);
+				record_cost_with_snapshot(tracer, 3, 100_000, 0);
+				step(tracer, evm::Opcode::JUMP, 2, &[]);
+				record_cost_with_snapshot(tracer, 8, 100_000, 3);
+				step(tracer, evm::Opcode::JUMPDEST, 10, &[]);
+				record_cost_with_snapshot(tracer, 1, 100_000, 11);
+				step(tracer, evm::Opcode::ADD, 11, &[]);
+				record_cost_with_snapshot(tracer, 3, 100_000, 12);
+				step(tracer, evm::Opcode::JUMPDEST, 12, &[]);
+				record_cost_with_snapshot(tracer, 1, 100_000, 15);
+				exit(tracer);
+			},
+		);
+
+		let jumpdests: Vec<_> = tracer_events(&events)
+			.into_iter()
+			.filter_map(|event| match event {
+				TracerEvent::Jumpdest {
+					pc,
+					gas_used_so_far,
+				} => Some((pc, gas_used_so_far)),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(jumpdests, vec![(10, 11), (12, 15)]);
+	}
+
+	#[test]
+	fn precompile_gas_is_reported() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let sha256 = H160::from_low_u64_be(2);
+		let input = [0xab; 40];
+		// Cost of the SHA256 precompile.
+		let words = ((input.len() + 31) / 32) as u64;
+		let cost = 60 + 12 * words;
+
+		let events = trace_events_with(
+			|tracer| tracer.with_precompile_gas(),
+			|tracer| {
+				transact_call(tracer, eoa, contract, 0.into());
+				call(tracer, eoa, contract, 0.into(), &[], None);
+				record_cost(tracer, 100_000);
+				step(tracer, evm::Opcode::STATICCALL, 0, &[]);
+				call(tracer, contract, sha256, 0.into(), &input, None);
+				// Gas forwarded by the contract.
+				record_cost_with_snapshot(tracer, 50_000, 100_000, 0);
+				// Gas charged by the precompile.
+				record_cost_with_snapshot(tracer, cost, 50_000, 0);
+				exit(tracer);
+				exit(tracer);
+			},
+		);
+
+		let precompile_gas: Vec<_> = tracer_events(&events)
+			.into_iter()
+			.filter(|event| matches!(event, TracerEvent::PrecompileGas { .. }))
+			.collect();
+		assert_eq!(
+			precompile_gas,
+			vec![TracerEvent::PrecompileGas {
+				address: sha256,
+				gas_used: 84,
+			}]
+		);
+	}
+}
@@ -0,0 +1,193 @@
+// Copyright 2019-2022 PureStake Inc.
+// This file is part of Moonbeam.
+
+// Moonbeam is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Moonbeam is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Hooks reading the state of the chain, for the events reporting values the `evm` crates don't
+//! include in their events.
+
+use crate::tracer::EvmTracer;
+use ethereum_types::{H160, H256, U256};
+use evm_tracing_events::{tracer::TracerError, TracerEvent};
+
+impl EvmTracer {
+	/// Current nonce of the account, read by the hook of `with_creator_nonces`.
+	pub(crate) fn creator_nonce(&self, creator: H160) -> Result<u64, TracerError> {
+		let read_nonce = self
+			.read_nonce
+			.as_ref()
+			.ok_or(TracerError::HostUnavailable)?;
+		let nonce = read_nonce(creator);
+		if nonce > U256::from(u64::MAX) {
+			return Err(TracerError::ConversionFailed);
+		}
+		Ok(nonce.low_u64())
+	}
+
+	/// Current value of the storage slot, read by the hook of `with_sstore_values`.
+	pub(crate) fn original_value(&self, address: H160, slot: H256) -> Result<H256, TracerError> {
+		let read_storage = self
+			.read_storage
+			.as_ref()
+			.ok_or(TracerError::HostUnavailable)?;
+		Ok(read_storage(address, slot))
+	}
+
+	/// `TracerEvent::Sstore` of the runtime event, if it is an `SSTORE`.
+	pub(crate) fn sstore_values(&self, event: &evm_runtime::tracing::Event) -> Option<TracerEvent> {
+		match *event {
+			evm_runtime::tracing::Event::SStore {
+				address,
+				index,
+				value,
+			} => Some(match self.original_value(address, index) {
+				Ok(original) => TracerEvent::Sstore {
+					address,
+					slot: index,
+					original,
+					new: value,
+					no_op: original == value,
+				},
+				Err(error) => TracerEvent::Error(error),
+			}),
+			_ => None,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::*;
+	use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
+
+	#[test]
+	fn creator_nonce_derives_created_address() {
+		let contract = H160::repeat_byte(0x02);
+		// PUSH1 0 PUSH1 0 PUSH1 0 CREATE POP STOP
+		let code = vec![0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0xf0, 0x50, 0x00];
+
+		let events = host_events(|| {
+			EvmTracer::new()
+				.with_creator_nonces(move |address| {
+					assert_eq!(address, contract);
+					1.into()
+				})
+				.trace(|| execute(code));
+		});
+
+		let create = events
+			.iter()
+			.position(|event| matches!(event, Event::Evm(EvmEvent::Create { .. })))
+			.unwrap();
+		let created = match &events[create] {
+			Event::Evm(EvmEvent::Create { address, .. }) => *address,
+			_ => unreachable!(),
+		};
+		let creator_nonce = match &events[create + 1] {
+			Event::Tracer(TracerEvent::CreatorNonce { creator_nonce }) => *creator_nonce,
+			event => panic!("unexpected event {:?}", event),
+		};
+		assert_eq!(creator_nonce, 1);
+
+		// RLP list of the 20-byte address and the single-byte nonce.
+		let mut rlp = vec![0xd6, 0x94];
+		rlp.extend_from_slice(contract.as_bytes());
+		rlp.push(creator_nonce as u8);
+		assert_eq!(
+			created,
+			H160::from_slice(&sp_io::hashing::keccak_256(&rlp)[12..])
+		);
+	}
+
+	#[test]
+	fn missing_host_hooks_are_unavailable() {
+		let tracer = EvmTracer::new();
+
+		assert_eq!(
+			tracer.creator_nonce(H160::repeat_byte(0x02)),
+			Err(TracerError::HostUnavailable)
+		);
+		assert_eq!(
+			tracer.original_value(H160::repeat_byte(0x02), H256::zero()),
+			Err(TracerError::HostUnavailable)
+		);
+		let tracer = tracer
+			.with_creator_nonces(|_| 3.into())
+			.with_sstore_values(|_, _| H256::repeat_byte(0x04));
+		assert_eq!(tracer.creator_nonce(H160::repeat_byte(0x02)), Ok(3));
+		assert_eq!(
+			tracer.original_value(H160::repeat_byte(0x02), H256::zero()),
+			Ok(H256::repeat_byte(0x04))
+		);
+	}
+
+	#[test]
+	fn creator_nonce_overflow_fails_conversion() {
+		// PUSH1 0 PUSH1 0 PUSH1 0 CREATE POP STOP
+		let code = vec![0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0xf0, 0x50, 0x00];
+
+		let events = host_events(|| {
+			EvmTracer::new()
+				.with_creator_nonces(|_| U256::MAX)
+				.trace(|| execute(code));
+		});
+
+		let create = events
+			.iter()
+			.position(|event| matches!(event, Event::Evm(EvmEvent::Create { .. })))
+			.unwrap();
+		assert_eq!(
+			events[create + 1],
+			Event::Tracer(TracerEvent::Error(TracerError::ConversionFailed))
+		);
+	}
+
+	#[test]
+	fn sstore_values_flag_no_op_writes() {
+		let contract = H160::repeat_byte(0x02);
+		let slot = H256::repeat_byte(0x01);
+		let value = H256::repeat_byte(0x02);
+		let storage = Rc::new(RefCell::new(BTreeMap::new()));
+
+		let events = trace_events_with(
+			|tracer| {
+				let storage = Rc::clone(&storage);
+				tracer.with_sstore_values(move |address, index| {
+					storage
+						.borrow()
+						.get(&(address, index))
+						.copied()
+						.unwrap_or_default()
+				})
+			},
+			|tracer| {
+				sstore(tracer, contract, slot, value);
+				storage.borrow_mut().insert((contract, slot), value);
+				sstore(tracer, contract, slot, value);
+			},
+		);
+
+		let writes: Vec<_> = tracer_events(&events)
+			.into_iter()
+			.filter_map(|event| match event {
+				TracerEvent::Sstore {
+					original, no_op, ..
+				} => Some((original, no_op)),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(writes, vec![(H256::zero(), false), (value, true)]);
+	}
+}
@@ -0,0 +1,189 @@
+// Copyright 2019-2022 PureStake Inc.
+// This file is part of Moonbeam.
+
+// Moonbeam is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Moonbeam is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Proxies registering the tracer as listener of the events of the `evm` crates, and the events
+//! they forward to it.
+
+use evm::tracing::EventListener as EvmListener;
+use evm_gasometer::tracing::EventListener as GasometerListener;
+use evm_runtime::tracing::EventListener as RuntimeListener;
+use sp_std::{cell::RefCell, rc::Rc};
+
+pub(crate) struct ListenerProxy<T>(pub Rc<RefCell<T>>);
+impl<T: GasometerListener> GasometerListener for ListenerProxy<T> {
+	fn event(&mut self, event: evm_gasometer::tracing::Event) {
+		self.0.borrow_mut().event(event);
+	}
+}
+
+impl<T: RuntimeListener> RuntimeListener for ListenerProxy<T> {
+	fn event(&mut self, event: evm_runtime::tracing::Event) {
+		self.0.borrow_mut().event(event);
+	}
+}
+
+impl<T: EvmListener> EvmListener for ListenerProxy<T> {
+	fn event(&mut self, event: evm::tracing::Event) {
+		self.0.borrow_mut().event(event);
+	}
+}
+
+/// Event received by the tracer from the `evm` crates, before any conversion.
+#[derive(Debug, Clone, Copy)]
+pub enum RawEvent<'a> {
+	Evm(evm::tracing::Event<'a>),
+	Gasometer(evm_gasometer::tracing::Event),
+	Runtime(evm_runtime::tracing::Event<'a>),
+}
+
+/// Subsystem of the EVM emitting tracing events.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Subsystem {
+	Evm,
+	Gasometer,
+	Runtime,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::*;
+
+	/// Counts the events it receives.
+	#[derive(Clone, Default)]
+	struct Counter(Rc<RefCell<usize>>);
+
+	impl Counter {
+		fn count(&self) -> usize {
+			*self.0.borrow()
+		}
+	}
+
+	impl EvmListener for Counter {
+		fn event(&mut self, _event: evm::tracing::Event) {
+			*self.0.borrow_mut() += 1;
+		}
+	}
+
+	impl GasometerListener for Counter {
+		fn event(&mut self, _event: evm_gasometer::tracing::Event) {
+			*self.0.borrow_mut() += 1;
+		}
+	}
+
+	impl RuntimeListener for Counter {
+		fn event(&mut self, _event: evm_runtime::tracing::Event) {
+			*self.0.borrow_mut() += 1;
+		}
+	}
+
+	#[test]
+	fn boxed_listeners_receive_events() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let (evm_a, evm_b, gasometer, runtime) = (
+			Counter::default(),
+			Counter::default(),
+			Counter::default(),
+			Counter::default(),
+		);
+
+		let events = trace_events_with(
+			|tracer| {
+				tracer
+					.with_evm_listener(Box::new(evm_a.clone()))
+					.with_evm_listener(Box::new(evm_b.clone()))
+					.with_gasometer_listener(Box::new(gasometer.clone()))
+					.with_runtime_listener(Box::new(runtime.clone()))
+			},
+			|tracer| {
+				transact_call(tracer, eoa, contract, 0.into());
+				record_cost(tracer, 21_000);
+				call(tracer, eoa, contract, 0.into(), &[], None);
+				step(tracer, evm::Opcode::STOP, 0, &[]);
+				step_result(tracer);
+				exit(tracer);
+			},
+		);
+
+		assert_eq!(evm_a.count(), 3);
+		assert_eq!(evm_b.count(), 3);
+		assert_eq!(gasometer.count(), 1);
+		assert_eq!(runtime.count(), 2);
+		// The host still receives the events.
+		assert_eq!(
+			events
+				.iter()
+				.filter(|event| matches!(event, Event::Evm(_)))
+				.count(),
+			3
+		);
+	}
+
+	#[test]
+	fn raw_listener_receives_unconverted_events() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let stacks = Rc::new(RefCell::new(Vec::new()));
+
+		let listener_stacks = Rc::clone(&stacks);
+		let events = trace_events_with(
+			|tracer| {
+				tracer.with_raw_listener(move |event| {
+					if let RawEvent::Runtime(evm_runtime::tracing::Event::Step { stack, .. }) =
+						event
+					{
+						listener_stacks.borrow_mut().push(stack.data().clone());
+					}
+				})
+			},
+			|tracer| {
+				transact_call(tracer, eoa, contract, 0.into());
+				call(tracer, eoa, contract, 0.into(), &[], None);
+				step(tracer, evm::Opcode::ADD, 0, &[H256::repeat_byte(0x11)]);
+				exit(tracer);
+			},
+		);
+
+		// The stack is filtered out of the event sent to the host.
+		assert!(events.iter().any(|event| matches!(
+			event,
+			Event::Runtime(RuntimeEvent::Step { stack: None, .. })
+		)));
+		assert_eq!(*stacks.borrow(), vec![vec![H256::repeat_byte(0x11)]]);
+	}
+
+	#[test]
+	fn events_dont_depend_on_listener_order() {
+		// PUSH1 1, PUSH1 2, ADD, PUSH1 0, SSTORE, STOP
+		let code = vec![0x60, 0x01, 0x60, 0x02, 0x01, 0x60, 0x00, 0x55, 0x00];
+		let trace = |order| {
+			host_events(|| {
+				let reason = EvmTracer::new()
+					.with_listener_order(order)
+					.trace(|| execute(code.clone()));
+				assert!(matches!(reason, evm::ExitReason::Succeed(_)));
+			})
+		};
+
+		let events = trace([Subsystem::Runtime, Subsystem::Gasometer, Subsystem::Evm]);
+		assert!(events.len() > 1);
+		assert_eq!(
+			trace([Subsystem::Evm, Subsystem::Runtime, Subsystem::Gasometer]),
+			events
+		);
+	}
+}
@@ -0,0 +1,109 @@
+// Copyright 2019-2022 PureStake Inc.
+// This file is part of Moonbeam.
+
+// Moonbeam is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Moonbeam is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Set of opcodes checked at each step.
+
+use evm::Opcode;
+use evm_tracing_events::opcodes_string;
+use sp_std::iter::FromIterator;
+
+/// Set of opcodes, stored as a 256-bit bitset so that checking an opcode is a single bit test.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct OpcodeFilter([u64; 4]);
+
+impl OpcodeFilter {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Build the set from opcode names as given by `opcodes_string`, case insensitive (e.g.
+	/// `SStore` or `SSTORE`). Returns the first unknown name as error.
+	pub fn from_mnemonics<'a, I: IntoIterator<Item = &'a str>>(
+		mnemonics: I,
+	) -> Result<Self, &'a str> {
+		let mut filter = Self::new();
+		for mnemonic in mnemonics {
+			let opcode = (0..=u8::MAX)
+				.find(|opcode| {
+					opcodes_string(Opcode(*opcode)).eq_ignore_ascii_case(mnemonic.as_bytes())
+				})
+				.ok_or(mnemonic)?;
+			filter.insert(opcode);
+		}
+		Ok(filter)
+	}
+
+	pub fn insert(&mut self, opcode: u8) {
+		self.0[(opcode / 64) as usize] |= 1 << (opcode % 64);
+	}
+
+	pub fn remove(&mut self, opcode: u8) {
+		self.0[(opcode / 64) as usize] &= !(1 << (opcode % 64));
+	}
+
+	pub fn contains(&self, opcode: u8) -> bool {
+		self.0[(opcode / 64) as usize] & (1 << (opcode % 64)) != 0
+	}
+}
+
+impl FromIterator<u8> for OpcodeFilter {
+	fn from_iter<I: IntoIterator<Item = u8>>(opcodes: I) -> Self {
+		let mut filter = Self::new();
+		for opcode in opcodes {
+			filter.insert(opcode);
+		}
+		filter
+	}
+}
+
+impl From<&[u8]> for OpcodeFilter {
+	fn from(opcodes: &[u8]) -> Self {
+		opcodes.iter().copied().collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::collections::BTreeSet;
+
+	#[test]
+	fn membership_matches_btree_set() {
+		let opcodes = [
+			0x00, 0x01, 0x3f, 0x40, 0x54, 0x7f, 0x80, 0xbf, 0xc0, 0xf1, 0xff,
+		];
+		let set: BTreeSet<u8> = opcodes.iter().copied().collect();
+		let mut filter = OpcodeFilter::from(&opcodes[..]);
+
+		for opcode in 0..=u8::MAX {
+			assert_eq!(
+				filter.contains(opcode),
+				set.contains(&opcode),
+				"{:#x}",
+				opcode
+			);
+		}
+
+		filter.remove(0x54);
+		assert!(!filter.contains(0x54));
+
+		assert_eq!(
+			OpcodeFilter::from_mnemonics(["SLOAD", "SStore", "call"]),
+			Ok([0x54, 0x55, 0xf1].iter().copied().collect())
+		);
+		assert_eq!(OpcodeFilter::from_mnemonics(["Sload", "Nope"]), Err("Nope"));
+	}
+}
@@ -0,0 +1,556 @@
+// Copyright 2019-2022 PureStake Inc.
+// This file is part of Moonbeam.
+
+// Moonbeam is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Moonbeam is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Output of the events of the tracer: the transforms and interceptors applied to them, and the
+//! sinks receiving them, which are the host functions unless the events are captured or dropped
+//! in benchmark mode.
+
+use crate::tracer::EvmTracer;
+use codec::Encode;
+use ethereum_types::H256;
+use evm_tracing_events::{Encoding, Event, EvmEvent, RuntimeEvent, TracerEvent, EVM_STREAM_ID};
+use sp_std::vec::Vec;
+
+/// Post-processing applied by the tracer to each event before it is sent to the host.
+///
+/// Transforms registered with `EvmTracer::with_transform` form a pipeline: they are applied
+/// in registration order, each one receiving the output of the previous one.
+pub trait TraceTransform {
+	/// Returns the event to emit in place of `event`, or `None` to drop it.
+	fn transform(&self, event: Event) -> Option<Event>;
+}
+
+/// Type of an event sent to the host.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EventKind {
+	Evm,
+	Gasometer,
+	Runtime,
+	CallListNew,
+	Tracer,
+}
+
+impl EventKind {
+	pub(crate) fn of(event: &Event) -> Self {
+		match event {
+			Event::Evm(_) => EventKind::Evm,
+			Event::Gasometer(_) => EventKind::Gasometer,
+			Event::Runtime(_) => EventKind::Runtime,
+			Event::CallListNew() => EventKind::CallListNew,
+			Event::Tracer(_) => EventKind::Tracer,
+		}
+	}
+}
+
+/// Hook applied to the encoded bytes of each event right before they are passed to the
+/// host, e.g. to sign or checksum them. Unlike a `TraceTransform`, it sees the bytes in the
+/// encoding of the tracer, and the host must be able to decode its output.
+pub trait ByteInterceptor {
+	/// Returns the bytes to pass to the host in place of `bytes`, the encoding of an event of
+	/// type `kind`. An `Event::CallListNew` sent to its own host function has no bytes and
+	/// is not intercepted.
+	fn intercept(&self, kind: EventKind, bytes: Vec<u8>) -> Vec<u8>;
+}
+
+/// Sink of the events in benchmark mode, encoding the events and dropping the bytes.
+#[derive(Clone, Debug, Default)]
+pub struct BlackholeSink {
+	/// Amount of events received.
+	pub events: u64,
+	/// Total size of the encoded events, which keeps the encoding from being optimized out.
+	pub encoded_bytes: u64,
+}
+
+impl BlackholeSink {
+	pub(crate) fn consume(&mut self, event: &Event, encoding: Encoding) {
+		let encoded_len = match encoding {
+			Encoding::Scale => event.encode().len(),
+			#[cfg(feature = "cbor")]
+			Encoding::Cbor => event.to_cbor().len(),
+		};
+		self.events += 1;
+		self.encoded_bytes += encoded_len as u64;
+	}
+}
+
+impl EvmTracer {
+	/// Applies the transform pipeline to the event and proxies the result to the host, or
+	/// buffers it if the events of the current frame or transaction may be discarded.
+	pub(crate) fn emit(&mut self, event: Event) {
+		let mut event = match self
+			.transforms
+			.iter()
+			.try_fold(event, |event, transform| transform.transform(event))
+		{
+			Some(event) => event,
+			None => return,
+		};
+		if self.config.data_redaction {
+			redact_data(&mut event);
+		}
+		// The markers of the traced transactions are kept to tell their records apart.
+		if self.config.replay_capture
+			&& !matches!(
+				event,
+				Event::CallListNew()
+					| Event::Tracer(TracerEvent::Begin(_))
+					| Event::Tracer(TracerEvent::Replay(_))
+			) {
+			return;
+		}
+
+		match self.frame_buffers.last_mut() {
+			Some(buffer) if self.buffers_frames() => buffer.push(event),
+			_ => self.output(event),
+		}
+	}
+
+	/// Proxies the event to the host, or buffers it if only failed transactions are traced.
+	pub(crate) fn output(&mut self, event: Event) {
+		match &mut self.failed_only_buffer {
+			Some(buffer) => buffer.push(event),
+			None => self.deliver(event),
+		}
+	}
+
+	/// Sends the event to the host, or keeps it if the events are captured.
+	pub(crate) fn deliver(&mut self, event: Event) {
+		if self.timed_out {
+			return;
+		}
+		if let Some(commitment) = &mut self.event_commitment {
+			*commitment = chain_commitment(*commitment, &event);
+		}
+		for callback in &mut self.event_callbacks {
+			callback(&event);
+		}
+		if let Some(blackhole) = &mut self.blackhole {
+			blackhole.consume(&event, self.config.encoding);
+			return;
+		}
+		match &mut self.captured_events {
+			Some(events) => events.push(event),
+			None => send(
+				event,
+				self.config.encoding,
+				self.config.stream_id,
+				self.byte_interceptor.as_deref(),
+			),
+		}
+	}
+}
+
+/// Root of the hash chain of `with_event_commitment` once `event` is appended to the chain
+/// whose root is `commitment`.
+pub fn chain_commitment(commitment: H256, event: &Event) -> H256 {
+	let mut preimage = commitment.as_bytes().to_vec();
+	event.encode_to(&mut preimage);
+	H256::from(sp_io::hashing::keccak_256(&preimage))
+}
+
+/// Proxies the event of the stream to the host with the given encoding. The encoded bytes
+/// go through the interceptor, if any.
+fn send(
+	event: Event,
+	encoding: Encoding,
+	stream_id: u16,
+	interceptor: Option<&dyn ByteInterceptor>,
+) {
+	let intercept = |kind, bytes| match interceptor {
+		Some(interceptor) => interceptor.intercept(kind, bytes),
+		None => bytes,
+	};
+	if stream_id != EVM_STREAM_ID {
+		let kind = EventKind::of(&event);
+		let message = evm_tracing_events::encode_stream_message(stream_id, &event);
+		moonbeam_primitives_ext::moonbeam_ext::stream_event(intercept(kind, message));
+		return;
+	}
+	match encoding {
+		Encoding::Scale => send_scale(event, intercept),
+		#[cfg(feature = "cbor")]
+		Encoding::Cbor => match event.try_to_cbor() {
+			Ok(bytes) => moonbeam_primitives_ext::moonbeam_ext::cbor_event(intercept(
+				EventKind::of(&event),
+				bytes,
+			)),
+			// Reported in SCALE, which can't fail.
+			Err(error) => send_scale(Event::Tracer(TracerEvent::Error(error)), intercept),
+		},
+	}
+}
+
+/// Proxies the SCALE-encoded event to the host function of its type.
+fn send_scale<I: Fn(EventKind, Vec<u8>) -> Vec<u8>>(event: Event, intercept: I) {
+	use moonbeam_primitives_ext::moonbeam_ext;
+
+	match event {
+		Event::Evm(event) => moonbeam_ext::evm_event(intercept(EventKind::Evm, event.encode())),
+		Event::Gasometer(event) => {
+			moonbeam_ext::gasometer_event(intercept(EventKind::Gasometer, event.encode()))
+		}
+		Event::Runtime(event) => {
+			moonbeam_ext::runtime_event(intercept(EventKind::Runtime, event.encode()))
+		}
+		Event::Tracer(event) => {
+			moonbeam_ext::tracer_event(intercept(EventKind::Tracer, event.encode()))
+		}
+		// Sent without any content.
+		Event::CallListNew() => moonbeam_ext::call_list_new(),
+	}
+}
+
+/// Zeroes the inputs, outputs, memory and copied code of the event, keeping their length.
+fn redact_data(event: &mut Event) {
+	let data = match event {
+		Event::Evm(EvmEvent::Call { input: data, .. })
+		| Event::Evm(EvmEvent::Create {
+			init_code: data, ..
+		})
+		| Event::Evm(EvmEvent::Exit {
+			return_value: data, ..
+		})
+		| Event::Evm(EvmEvent::TransactCall { data, .. })
+		| Event::Evm(EvmEvent::TransactCreate {
+			init_code: data, ..
+		})
+		| Event::Evm(EvmEvent::TransactCreate2 {
+			init_code: data, ..
+		})
+		| Event::Runtime(RuntimeEvent::StepResult {
+			return_value: data, ..
+		})
+		| Event::Tracer(TracerEvent::Keccak { input: data, .. }) => data,
+		Event::Runtime(RuntimeEvent::Step {
+			memory: Some(memory),
+			..
+		}) => &mut memory.data,
+		Event::Tracer(TracerEvent::Call {
+			selector: Some(selector),
+			..
+		}) => {
+			*selector = [0; 4];
+			return;
+		}
+		Event::Tracer(TracerEvent::RolledBack(event)) => return redact_data(event),
+		Event::Tracer(TracerEvent::Replay(record)) => {
+			for (_, input, output) in &mut record.precompile_outputs {
+				input.iter_mut().chain(output).for_each(|byte| *byte = 0);
+			}
+			for (_, _, code) in &mut record.external_code {
+				code.iter_mut().for_each(|byte| *byte = 0);
+			}
+			return;
+		}
+		_ => return,
+	};
+	data.iter_mut().for_each(|byte| *byte = 0);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::*;
+	use std::{cell::RefCell, rc::Rc};
+
+	struct DropGasometer;
+
+	impl TraceTransform for DropGasometer {
+		fn transform(&self, event: Event) -> Option<Event> {
+			match event {
+				Event::Gasometer(_) => None,
+				event => Some(event),
+			}
+		}
+	}
+
+	#[test]
+	fn transform_drops_events() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let trace = |tracer: &mut EvmTracer| {
+			transact_call(tracer, eoa, contract, 0.into());
+			record_cost(tracer, 21_000);
+			call(tracer, eoa, contract, 0.into(), &[], None);
+			record_cost(tracer, 3);
+			exit(tracer);
+		};
+
+		let events = trace_events(trace);
+		let transformed = trace_events_with(|tracer| tracer.with_transform(DropGasometer), trace);
+
+		assert_eq!(events.len(), 5);
+		let expected: Vec<_> = events
+			.into_iter()
+			.filter(|event| !matches!(event, Event::Gasometer(_)))
+			.collect();
+		assert_eq!(expected.len(), 3);
+		assert_eq!(transformed, expected);
+	}
+
+	/// Appends to the bytes their sum, keeping the intercepted bytes.
+	#[derive(Default)]
+	struct Checksum {
+		intercepted: Rc<RefCell<Vec<(EventKind, Vec<u8>)>>>,
+	}
+
+	impl ByteInterceptor for Checksum {
+		fn intercept(&self, kind: EventKind, mut bytes: Vec<u8>) -> Vec<u8> {
+			let checksum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+			bytes.push(checksum);
+			self.intercepted.borrow_mut().push((kind, bytes.clone()));
+			bytes
+		}
+	}
+
+	/// Replaces the bytes of the tracer events, which the host can't decode anymore.
+	struct Garble;
+
+	impl ByteInterceptor for Garble {
+		fn intercept(&self, kind: EventKind, bytes: Vec<u8>) -> Vec<u8> {
+			match kind {
+				EventKind::Tracer => vec![0xff],
+				_ => bytes,
+			}
+		}
+	}
+
+	#[test]
+	fn byte_interceptor_modifies_host_bytes() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let events = |tracer: &mut EvmTracer| {
+			transact_call(tracer, eoa, contract, 0.into());
+			call(tracer, eoa, contract, 0.into(), &[], None);
+			exit(tracer);
+		};
+		let checksum = Checksum::default();
+		let intercepted = Rc::clone(&checksum.intercepted);
+
+		let checksummed = trace_events_with(
+			|tracer| tracer.with_call_details().with_byte_interceptor(checksum),
+			events,
+		);
+		let garbled = trace_events_with(
+			|tracer| tracer.with_call_details().with_byte_interceptor(Garble),
+			events,
+		);
+
+		// The host ignores the trailing checksum.
+		assert_eq!(
+			checksummed,
+			trace_events_with(|tracer| tracer.with_call_details(), events)
+		);
+		let intercepted = intercepted.borrow();
+		assert_eq!(intercepted.len(), checksummed.len());
+		assert_eq!(intercepted[0].0, EventKind::Evm);
+		for (_, bytes) in intercepted.iter() {
+			let (checksum, encoded) = bytes.split_last().unwrap();
+			assert_eq!(
+				encoded
+					.iter()
+					.fold(0u8, |sum, byte| sum.wrapping_add(*byte)),
+				*checksum
+			);
+		}
+		assert!(tracer_events(&garbled).is_empty());
+		assert!(!tracer_events(&checksummed).is_empty());
+	}
+
+	#[test]
+	fn data_redaction_preserves_lengths() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let input = [0xa9, 0x05, 0x9c, 0xbb, 0x01, 0x02, 0x03];
+		let events = |tracer: &mut EvmTracer| {
+			transact_call(tracer, eoa, contract, 0.into());
+			call(tracer, eoa, contract, 0.into(), &input, None);
+			step_with_memory(tracer, evm::Opcode::MSTORE, 0, &[], &[0xff; 32]);
+			exit_with(
+				tracer,
+				evm::ExitReason::Succeed(evm::ExitSucceed::Returned),
+				&[0xee; 5],
+			);
+		};
+
+		let redacted = trace_events_with(
+			|tracer| tracer.with_call_details().with_data_redaction(),
+			events,
+		);
+
+		let mut plain = trace_events_with(|tracer| tracer.with_call_details(), events);
+		assert_eq!(redacted.len(), plain.len());
+		plain.iter_mut().for_each(|event| match event {
+			Event::Evm(EvmEvent::Call { input, .. }) => *input = vec![0; input.len()],
+			Event::Evm(EvmEvent::Exit { return_value, .. }) => {
+				*return_value = vec![0; return_value.len()]
+			}
+			Event::Runtime(RuntimeEvent::Step {
+				memory: Some(memory),
+				..
+			}) => memory.data = vec![0; memory.data.len()],
+			Event::Tracer(TracerEvent::Call { selector, .. }) => *selector = Some([0; 4]),
+			_ => (),
+		});
+		assert_eq!(redacted, plain);
+		assert!(redacted.iter().any(|event| matches!(
+			event,
+			Event::Evm(EvmEvent::Call { input, .. }) if input.len() == 7
+		)));
+
+		let precompile = H160::from_low_u64_be(2);
+		let replayed = |tracer: &mut EvmTracer| {
+			transact_call(tracer, eoa, contract, 0.into());
+			call(tracer, eoa, contract, 0.into(), &[], None);
+			// EXTCODECOPY of 3 bytes of the code of `eoa`, written at the start of the memory.
+			let stack = [
+				H256::from_low_u64_be(3),
+				H256::zero(),
+				H256::zero(),
+				H256::from(eoa),
+			];
+			step(tracer, evm::Opcode::EXTCODECOPY, 0, &stack);
+			step_with_memory(tracer, evm::Opcode::STOP, 1, &[], &[0xcc; 3]);
+			call(tracer, contract, precompile, 0.into(), &input, None);
+			exit_with(
+				tracer,
+				evm::ExitReason::Succeed(evm::ExitSucceed::Returned),
+				&[0xee; 5],
+			);
+			exit(tracer);
+		};
+
+		let redacted = trace_events_with(
+			|tracer| tracer.replay_capture().with_data_redaction(),
+			replayed,
+		);
+
+		let plain = trace_events_with(|tracer| tracer.replay_capture(), replayed);
+		let mut record = match tracer_events(&plain).pop() {
+			Some(TracerEvent::Replay(record)) => record,
+			other => panic!("unexpected last tracer event {:?}", other),
+		};
+		assert_eq!(
+			record.precompile_outputs,
+			vec![(precompile, input.to_vec(), vec![0xee; 5])]
+		);
+		assert_eq!(
+			record.external_code,
+			vec![(eoa, U256::zero(), vec![0xcc; 3])]
+		);
+		record.precompile_outputs = vec![(precompile, vec![0; 7], vec![0; 5])];
+		record.external_code = vec![(eoa, U256::zero(), vec![0; 3])];
+		assert_eq!(
+			tracer_events(&redacted).pop(),
+			Some(TracerEvent::Replay(record))
+		);
+	}
+
+	#[test]
+	fn bench_mode_drops_encoded_events() {
+		// PUSH1 0x01 PUSH1 0x00 SSTORE STOP
+		let code = vec![0x60, 0x01, 0x60, 0x00, 0x55, 0x00];
+
+		let mut blackhole = None;
+		let events = host_events(|| {
+			let (tracer, _) = EvmTracer::new()
+				.bench_mode()
+				.trace_with_tracer(|| execute(code.clone()));
+			blackhole = tracer.blackhole().cloned();
+		});
+
+		assert_eq!(events, vec![]);
+		let blackhole = blackhole.unwrap();
+		let expected = host_events(|| {
+			EvmTracer::new().trace(|| execute(code));
+		});
+		assert_eq!(blackhole.events, expected.len() as u64);
+		assert_eq!(
+			blackhole.encoded_bytes,
+			expected
+				.iter()
+				.map(|event| event.encode().len() as u64)
+				.sum()
+		);
+	}
+
+	#[test]
+	fn event_callback_receives_each_event() {
+		// PUSH1 0x01 PUSH1 0x00 SSTORE STOP
+		let code = vec![0x60, 0x01, 0x60, 0x00, 0x55, 0x00];
+		let received = Rc::new(RefCell::new(Vec::new()));
+
+		let callback_received = Rc::clone(&received);
+		let events = host_events(|| {
+			EvmTracer::new()
+				.with_event_callback(move |event| {
+					callback_received.borrow_mut().push(event.clone())
+				})
+				.trace(|| execute(code));
+		});
+
+		assert!(events.len() > 2);
+		assert_eq!(received.borrow().len(), events.len());
+		assert_eq!(*received.borrow(), events);
+	}
+
+	#[test]
+	fn event_commitment_covers_the_stream() {
+		// PUSH1 0x01 PUSH1 0x00 SSTORE STOP
+		let code = vec![0x60, 0x01, 0x60, 0x00, 0x55, 0x00];
+		let recompute = |events: &[Event]| {
+			events.iter().fold(H256::zero(), |root, event| {
+				let mut preimage = root.as_bytes().to_vec();
+				preimage.extend(event.encode());
+				H256::from(sp_core::keccak_256(&preimage))
+			})
+		};
+
+		let mut events = host_events(|| {
+			EvmTracer::new()
+				.with_event_commitment()
+				.trace(|| execute(code));
+		});
+
+		let commitment = match events.pop() {
+			Some(Event::Tracer(TracerEvent::TraceEnd { commitment })) => commitment,
+			other => panic!("unexpected last event {:?}", other),
+		};
+		assert!(events.len() > 2);
+		assert_eq!(recompute(&events), commitment);
+
+		events.swap(1, 2);
+		assert_ne!(recompute(&events), commitment);
+		events.swap(1, 2);
+		events[2] = Event::CallListNew();
+		assert_ne!(recompute(&events), commitment);
+	}
+
+	#[test]
+	fn stream_id_routes_events_on_host() {
+		let evm_stream = host_events(|| {
+			EvmTracer::new()
+				.with_stream_id(evm_tracing_events::EVM_STREAM_ID)
+				.trace(|| ());
+		});
+		let other_stream = host_events(|| {
+			EvmTracer::new().with_stream_id(7).trace(|| ());
+		});
+
+		assert_eq!(evm_stream, vec![Event::CallListNew()]);
+		assert_eq!(other_stream, vec![]);
+	}
+}
@@ -0,0 +1,182 @@
+// Copyright 2019-2022 PureStake Inc.
+// This file is part of Moonbeam.
+
+// Moonbeam is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Moonbeam is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
+//! Capture of the data read from the chain by a transaction, reported in a
+//! `TracerEvent::Replay` to replay it without access to the state.
+
+use crate::state::Frame;
+use crate::tracer::EvmTracer;
+use ethereum_types::{H160, U256};
+use evm::Opcode;
+use evm_tracing_events::TracerEvent;
+use sp_std::vec::Vec;
+
+impl EvmTracer {
+	/// Records the outputs of the previous step, which are only available at the next one.
+	pub(crate) fn replay_step_output(&mut self, stack: &evm::Stack, memory: &evm::Memory) {
+		if let Some(number) = self.state.pending_block_hash.take() {
+			if let Ok(hash) = stack.peek(0) {
+				self.state.replay.block_hashes.push((number, hash));
+			}
+		}
+		if let Some((address, memory_offset, offset, size)) = self.state.pending_code_copy.take() {
+			let code = if size.is_zero() {
+				Vec::new()
+			} else {
+				memory.get(memory_offset.low_u64() as usize, size.low_u64() as usize)
+			};
+			self.state
+				.replay
+				.external_code
+				.push((address, offset, code));
+		}
+	}
+
+	/// Starts the capture of the output of the step, returning a `TracerEvent::Error` if its
+	/// stack is too short for the opcode.
+	pub(crate) fn replay_step_input(
+		&mut self,
+		opcode: Opcode,
+		stack: &evm::Stack,
+	) -> Option<TracerEvent> {
+		// Like the output of `SHA3`, the hash and the copied code are only available at the next
+		// step.
+		if opcode == Opcode::BLOCKHASH {
+			if let Ok(number) = stack.peek(0) {
+				self.state.pending_block_hash = Some(U256::from_big_endian(number.as_bytes()));
+			} else {
+				return Some(self.malformed_step(opcode));
+			}
+		}
+		if opcode == Opcode::EXTCODECOPY {
+			// Stack is `address, memory_offset, offset, size`.
+			if let (Ok(address), Ok(memory_offset), Ok(offset), Ok(size)) =
+				(stack.peek(0), stack.peek(1), stack.peek(2), stack.peek(3))
+			{
+				self.state.pending_code_copy = Some((
+					H160::from(address),
+					U256::from_big_endian(memory_offset.as_bytes()),
+					U256::from_big_endian(offset.as_bytes()),
+					U256::from_big_endian(size.as_bytes()),
+				));
+			} else {
+				return Some(self.malformed_step(opcode));
+			}
+		}
+		None
+	}
+
+	/// Records the storage read by the runtime event.
+	pub(crate) fn replay_storage(&mut self, event: &evm_runtime::tracing::Event) {
+		// Values read after a write of the transaction are recomputed by the replay.
+		match *event {
+			evm_runtime::tracing::Event::SLoad {
+				address,
+				index,
+				value,
+			} if self.state.replay_slots.insert((address, index)) => {
+				self.state.replay.storage.push((address, index, value));
+			}
+			evm_runtime::tracing::Event::SStore { address, index, .. } => {
+				self.state.replay_slots.insert((address, index));
+			}
+			_ => (),
+		}
+	}
+
+	/// Records the output of the exited frame, if it is a precompile.
+	pub(crate) fn replay_precompile_output(&mut self, frame: &Frame, output: &[u8]) {
+		if !frame.executed && !frame.is_create {
+			self.state.replay.precompile_outputs.push((
+				frame.code_address,
+				frame.input.clone(),
+				output.to_vec(),
+			));
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::*;
+	use evm_tracing_events::tracer::{ReplayRecord, TxContext};
+
+	#[test]
+	fn replay_capture_records_block_hashes() {
+		let contract = H160::repeat_byte(0x02);
+		// PUSH1 0 BLOCKHASH POP PUSH1 0 SLOAD POP STOP
+		let code = vec![0x60, 0x00, 0x40, 0x50, 0x60, 0x00, 0x54, 0x50, 0x00];
+
+		let events = host_events(|| {
+			EvmTracer::new().replay_capture().trace(|| execute(code));
+		});
+
+		assert_eq!(
+			events,
+			vec![
+				Event::CallListNew(),
+				Event::Tracer(TracerEvent::Replay(ReplayRecord {
+					block_hashes: vec![(U256::zero(), H256::repeat_byte(0xbb))],
+					storage: vec![(contract, H256::zero(), H256::zero())],
+					..Default::default()
+				}))
+			]
+		);
+	}
+
+	#[test]
+	fn replay_capture_delimits_the_transactions() {
+		let contract = H160::repeat_byte(0x02);
+		let tx = |nonce: u64| TxContext {
+			caller: H160::repeat_byte(0x01),
+			to: Some(contract),
+			value: 0.into(),
+			gas_limit: 100_000,
+			nonce: nonce.into(),
+		};
+		// PUSH1 0 BLOCKHASH POP STOP
+		let first = vec![0x60, 0x00, 0x40, 0x50, 0x00];
+		// PUSH1 1 SLOAD POP STOP
+		let second = vec![0x60, 0x01, 0x54, 0x50, 0x00];
+
+		let events = host_events(|| {
+			EvmTracer::new_with_tx(tx(0))
+				.replay_capture()
+				.trace(|| execute(first));
+			EvmTracer::new_with_tx(tx(1))
+				.replay_capture()
+				.trace(|| execute(second));
+		});
+
+		assert_eq!(
+			events,
+			vec![
+				Event::CallListNew(),
+				Event::Tracer(TracerEvent::Begin(tx(0))),
+				Event::Tracer(TracerEvent::Replay(ReplayRecord {
+					block_hashes: vec![(U256::zero(), H256::repeat_byte(0xbb))],
+					..Default::default()
+				})),
+				Event::CallListNew(),
+				Event::Tracer(TracerEvent::Begin(tx(1))),
+				Event::Tracer(TracerEvent::Replay(ReplayRecord {
+					storage: vec![(contract, H256::from_low_u64_be(1), H256::zero())],
+					..Default::default()
+				})),
+			]
+		);
+	}
+}
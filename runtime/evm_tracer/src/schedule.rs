@@ -0,0 +1,138 @@
+// Copyright 2019-2022 PureStake Inc.
+// This file is part of Moonbeam.
+
+// Moonbeam is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Moonbeam is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Static gas schedule of the opcodes, independent of their execution.
+
+use evm::Opcode;
+pub use evm_tracing_events::tracer::Hardfork;
+
+/// Hardfork introducing the opcode, `None` if it is defined since Istanbul or not at all.
+fn introduced_in(opcode: u8) -> Option<Hardfork> {
+	match opcode {
+		0x48 => Some(Hardfork::London),
+		0x5f => Some(Hardfork::Shanghai),
+		0x49 | 0x4a | 0x5c..=0x5e => Some(Hardfork::Cancun),
+		_ => None,
+	}
+}
+
+/// Name of the opcode under the hardfork, `None` if it is unknown to the tracer or not defined
+/// yet in the hardfork.
+pub fn opcode_name(opcode: u8, hardfork: Hardfork) -> Option<&'static str> {
+	match introduced_in(opcode) {
+		Some(fork) if hardfork < fork => None,
+		_ => evm_tracing_events::opcode_name(Opcode(opcode)),
+	}
+}
+
+/// Base gas cost of the opcode under the hardfork, charged before any cost depending on its
+/// operands or on the state. `None` if the opcode is not defined in the hardfork or its cost is
+/// purely dynamic.
+pub fn base_gas_cost(opcode: u8, hardfork: Hardfork) -> Option<u64> {
+	// Costs of the state accesses before EIP-2929 made them depend on the access list.
+	let state_access = |cost| {
+		if hardfork < Hardfork::Berlin {
+			Some(cost)
+		} else {
+			None
+		}
+	};
+	let since = |fork, cost| if hardfork >= fork { Some(cost) } else { None };
+
+	match opcode {
+		0x00 => Some(0),
+		0x01 | 0x03 => Some(3),
+		0x02 | 0x04..=0x07 | 0x0b => Some(5),
+		0x08 | 0x09 => Some(8),
+		0x0a => Some(10),
+		0x10..=0x1d => Some(3),
+		0x20 => Some(30),
+		0x31 | 0x3b | 0x3c | 0x3f => state_access(700),
+		0x30 | 0x32..=0x34 | 0x36 | 0x38 | 0x3a | 0x3d => Some(2),
+		0x35 | 0x37 | 0x39 | 0x3e => Some(3),
+		0x40 => Some(20),
+		0x41..=0x46 => Some(2),
+		0x47 => Some(5),
+		0x48 => since(Hardfork::London, 2),
+		0x49 => since(Hardfork::Cancun, 3),
+		0x4a => since(Hardfork::Cancun, 2),
+		0x50 | 0x58..=0x5a => Some(2),
+		0x51..=0x53 => Some(3),
+		0x54 => state_access(800),
+		0x55 => None,
+		0x56 => Some(8),
+		0x57 => Some(10),
+		0x5b => Some(1),
+		0x5c | 0x5d => since(Hardfork::Cancun, 100),
+		0x5e => since(Hardfork::Cancun, 3),
+		0x5f => since(Hardfork::Shanghai, 2),
+		0x60..=0x9f => Some(3),
+		0xa0..=0xa4 => Some(375 * (1 + (opcode - 0xa0) as u64)),
+		0xf0 | 0xf5 => Some(32_000),
+		0xf1 | 0xf2 | 0xf4 | 0xfa => state_access(700),
+		0xf3 | 0xfd => Some(0),
+		0xff => Some(5_000),
+		_ => None,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn base_gas_costs_follow_hardforks() {
+		let cases = [
+			// (opcode, Istanbul, London)
+			(0x01, Some(3), Some(3)),
+			(0x20, Some(30), Some(30)),
+			(0x31, Some(700), None),
+			(0x48, None, Some(2)),
+			(0x54, Some(800), None),
+			(0x55, None, None),
+			(0x5b, Some(1), Some(1)),
+			(0x5f, None, None),
+			(0xa2, Some(1_125), Some(1_125)),
+			(0xf1, Some(700), None),
+			(0xf5, Some(32_000), Some(32_000)),
+			(0xfe, None, None),
+		];
+
+		for (opcode, istanbul, london) in cases {
+			assert_eq!(
+				base_gas_cost(opcode, Hardfork::Istanbul),
+				istanbul,
+				"{:#x}",
+				opcode
+			);
+			assert_eq!(
+				base_gas_cost(opcode, Hardfork::London),
+				london,
+				"{:#x}",
+				opcode
+			);
+		}
+		assert_eq!(base_gas_cost(0x5f, Hardfork::Shanghai), Some(2));
+	}
+
+	#[test]
+	fn opcode_names_follow_hardforks() {
+		assert_eq!(opcode_name(0x01, Hardfork::Istanbul), Some("Add"));
+		assert_eq!(opcode_name(0x49, Hardfork::London), None);
+		assert_eq!(opcode_name(0x49, Hardfork::Cancun), Some("BlobHash"));
+		assert_eq!(opcode_name(0x0c, Hardfork::Cancun), None);
+	}
+}
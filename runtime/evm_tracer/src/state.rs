@@ -0,0 +1,440 @@
+// Copyright 2019-2022 PureStake Inc.
+// This file is part of Moonbeam.
+
+// Moonbeam is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Moonbeam is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Structural state the tracer maintains from the events it receives: the frames entered and the
+//! data of the transaction pending until a later event.
+
+use crate::summary::{is_precompile, Summary};
+use crate::tracer::EvmTracer;
+use ethereum_types::{H160, H256, U256};
+use evm::Opcode;
+use evm_tracing_events::tracer::ReplayRecord;
+use sp_std::{
+	collections::{btree_map::BTreeMap, btree_set::BTreeSet},
+	vec::Vec,
+};
+
+/// Bookkeeping of a call or create frame entered during the trace.
+#[derive(Clone)]
+pub(crate) struct Frame {
+	/// Address of the code executed in the frame.
+	pub(crate) code_address: H160,
+	/// Address whose storage and balance are used by the frame, which differs from
+	/// `code_address` in frames opened by a `DELEGATECALL` or `CALLCODE`.
+	pub(crate) address: H160,
+	/// Whether the interpreter executed a step of the frame. Subcalls of a frame that
+	/// didn't are made by a precompile.
+	pub(crate) executed: bool,
+	/// Precompile whose subcall opened this frame or one of its ancestors.
+	pub(crate) originating_precompile: Option<H160>,
+	/// The frame has been opened by a contract creation.
+	pub(crate) is_create: bool,
+	/// Size of the return data of the last subcall of the frame.
+	pub(crate) return_data_len: u64,
+	/// Last value emitted for each slot read by `SLOAD` in the frame.
+	pub(crate) sloads: BTreeMap<H256, H256>,
+	/// Maximum stack size observed in the steps of the frame.
+	pub(crate) max_stack_depth: u64,
+	/// Gas available at the start of the frame.
+	pub(crate) gas_at_start: u64,
+	/// Gas remaining in the frame after the last recorded cost.
+	pub(crate) gas_remaining: u64,
+	/// Identifier of the frame in the transaction, see `TracerEvent::FrameId`.
+	pub(crate) id: u64,
+	/// Addresses first accessed by the frame or its exited subcalls, which are cold again if
+	/// it is rolled back.
+	pub(crate) warmed: Vec<H160>,
+	/// Storage slots first accessed by the frame or its exited subcalls, rolled back like
+	/// `warmed`.
+	pub(crate) warmed_slots: Vec<(H160, H256)>,
+	/// Input of the frame, only kept in replay mode.
+	pub(crate) input: Vec<u8>,
+}
+
+impl Frame {
+	pub(crate) fn new(code_address: H160, parent: Option<&Frame>) -> Self {
+		let originating_precompile = parent.and_then(|parent| {
+			if parent.executed {
+				parent.originating_precompile
+			} else {
+				Some(parent.code_address)
+			}
+		});
+		Frame {
+			code_address,
+			address: code_address,
+			executed: false,
+			originating_precompile,
+			is_create: false,
+			return_data_len: 0,
+			sloads: BTreeMap::new(),
+			max_stack_depth: 0,
+			gas_at_start: 0,
+			gas_remaining: 0,
+			id: 0,
+			warmed: Vec::new(),
+			warmed_slots: Vec::new(),
+			input: Vec::new(),
+		}
+	}
+}
+
+/// Structural state the tracer maintains from the events it receives.
+#[derive(Clone, Default)]
+pub(crate) struct State {
+	/// Stack of the frames currently entered.
+	pub(crate) frames: Vec<Frame>,
+	/// `EvmEvent::TransactX` opens the transaction frame, which must not be opened again by
+	/// the `EvmEvent::Call/Create` that follows it.
+	pub(crate) skip_next_frame: bool,
+	/// Program counter of the last step.
+	pub(crate) pc: u64,
+	/// Opcode of the last step.
+	pub(crate) opcode: Option<Opcode>,
+	/// Contracts created during the transaction.
+	pub(crate) created: BTreeSet<H160>,
+	/// Memory offset and size hashed by the `SHA3` of the last step.
+	pub(crate) pending_keccak: Option<(U256, U256)>,
+	/// Block number whose hash is read by the `BLOCKHASH` of the last step, in replay mode.
+	pub(crate) pending_block_hash: Option<U256>,
+	/// Address, memory offset, code offset and size copied by the `EXTCODECOPY` of the last
+	/// step, in replay mode.
+	pub(crate) pending_code_copy: Option<(H160, U256, U256, U256)>,
+	/// Inputs of the transaction recorded so far in replay mode.
+	pub(crate) replay: ReplayRecord,
+	/// Storage slots already read or written by the transaction, in replay mode.
+	pub(crate) replay_slots: BTreeSet<(H160, H256)>,
+	/// Stipend of the frame just opened, whose forwarded gas is not recorded yet.
+	pub(crate) pending_frame_start: Option<u64>,
+	/// The last step is a `CALL` transferring value, whose dynamic cost is not recorded yet.
+	pub(crate) pending_value_call: bool,
+	/// Whether the callee of the `CALL` transferring value of the last step existed, deduced
+	/// from its dynamic cost.
+	pub(crate) pending_target_existed: Option<bool>,
+	/// Whether the account or storage slot accessed by the last step was cold, when the
+	/// accesses are tracked.
+	pub(crate) step_cold_access: Option<bool>,
+	/// The last step is a `CALL` or `CALLCODE` transferring value.
+	pub(crate) step_transfers_value: bool,
+	/// Amount of steps executed in the transaction.
+	pub(crate) step_index: u64,
+	/// Whether the opcode of the last step is not in the opcode filter.
+	pub(crate) step_filtered_out: bool,
+	/// Amount of frames entered in the transaction, which is the id of the next frame.
+	pub(crate) frames_entered: u64,
+	/// Addresses accessed during the transaction, apart from the ones warm from its start.
+	pub(crate) warmed: BTreeSet<H160>,
+	/// Storage slots accessed during the transaction.
+	pub(crate) warmed_slots: BTreeSet<(H160, H256)>,
+	pub(crate) summary: Summary,
+}
+
+impl State {
+	pub(crate) fn push_frame(&mut self, mut frame: Frame) {
+		frame.id = self.frames_entered;
+		self.frames_entered += 1;
+		self.frames.push(frame);
+	}
+
+	/// Records an access to the address, returning whether it is cold (EIP-2929).
+	pub(crate) fn access_address(&mut self, address: H160) -> bool {
+		if is_precompile(&address)
+			|| self.summary.warm.contains(&address)
+			|| !self.warmed.insert(address)
+		{
+			return false;
+		}
+		if let Some(frame) = self.frames.last_mut() {
+			frame.warmed.push(address);
+		}
+		true
+	}
+
+	/// Records an access to the storage slot of the address, returning whether it is cold
+	/// (EIP-2929).
+	pub(crate) fn access_slot(&mut self, address: H160, slot: H256) -> bool {
+		if !self.warmed_slots.insert((address, slot)) {
+			return false;
+		}
+		if let Some(frame) = self.frames.last_mut() {
+			frame.warmed_slots.push((address, slot));
+		}
+		true
+	}
+}
+
+/// Snapshot of the internal state of an `EvmTracer`, see `EvmTracer::checkpoint`.
+#[derive(Clone)]
+pub struct Checkpoint(pub(crate) State);
+
+impl EvmTracer {
+	/// Opens the transaction frame. The state of previous transactions traced by the same
+	/// tracer is reset since it is scoped to a transaction, like the created contracts and the
+	/// accessed slots of the EVM.
+	pub(crate) fn begin_transaction(&mut self, frame: Frame) {
+		self.state = State::default();
+		self.state.push_frame(frame);
+		self.state.skip_next_frame = true;
+		self.frame_buffers.clear();
+		if self.buffers_frames() {
+			self.frame_buffers.push(Vec::new());
+		}
+	}
+
+	/// Opens a new frame, unless it is the frame already opened by `EvmEvent::TransactX`.
+	/// Returns true if the entered frame is the transaction frame.
+	pub(crate) fn enter_frame(&mut self, code_address: H160, is_create: bool) -> bool {
+		if self.state.skip_next_frame {
+			self.state.skip_next_frame = false;
+		} else {
+			let mut frame = Frame::new(code_address, self.state.frames.last());
+			frame.is_create = is_create;
+			self.state.push_frame(frame);
+			if self.buffers_frames() {
+				self.frame_buffers.push(Vec::new());
+			}
+		}
+		self.state.frames.len() == 1
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::*;
+
+	#[test]
+	fn sibling_frames_have_distinct_ids() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let callee = H160::repeat_byte(0x03);
+
+		let events = trace_events_with(
+			|tracer| tracer.with_frame_ids(),
+			|tracer| {
+				transact_call(tracer, eoa, contract, 0.into());
+				call(tracer, eoa, contract, 0.into(), &[], None);
+				step(tracer, evm::Opcode::CALL, 0, &[]);
+				// Two sibling subcalls of the same depth.
+				for _ in 0..2 {
+					call(tracer, contract, callee, 0.into(), &[], None);
+					step(tracer, evm::Opcode::STOP, 0, &[]);
+					exit(tracer);
+				}
+				exit(tracer);
+			},
+		);
+
+		// Frame id following each step and exit.
+		let ids: Vec<_> = events
+			.windows(2)
+			.filter_map(|pair| match pair {
+				[Event::Runtime(RuntimeEvent::Step { .. }), Event::Tracer(TracerEvent::FrameId { frame_id })] => {
+					Some(("step", *frame_id))
+				}
+				[Event::Evm(EvmEvent::Exit { .. }), Event::Tracer(TracerEvent::FrameId { frame_id })] => {
+					Some(("exit", *frame_id))
+				}
+				_ => None,
+			})
+			.collect();
+		assert_eq!(
+			ids,
+			vec![
+				("step", 0),
+				("step", 1),
+				("exit", 1),
+				("step", 2),
+				("exit", 2),
+				("exit", 0),
+			]
+		);
+	}
+
+	#[test]
+	fn restore_discards_speculative_frames() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let callee = H160::repeat_byte(0x03);
+
+		let events = trace_events_with(
+			|tracer| tracer.with_call_details(),
+			|tracer| {
+				transact_call(tracer, eoa, contract, 0.into());
+				call(tracer, eoa, contract, 0.into(), &[], None);
+
+				let checkpoint = tracer.checkpoint();
+				// Discarded branch entering frames without exiting them.
+				call(tracer, contract, callee, 10.into(), &[], None);
+				call(tracer, callee, contract, 10.into(), &[], None);
+				tracer.restore(checkpoint);
+
+				call(tracer, contract, callee, 10.into(), &[], None);
+				exit(tracer);
+				exit(tracer);
+				// With all frames exited this is a new transaction frame, which has no stipend.
+				call(tracer, eoa, contract, 10.into(), &[], None);
+				exit(tracer);
+			},
+		);
+
+		let stipends: Vec<_> = tracer_events(&events)
+			.into_iter()
+			.filter_map(|event| match event {
+				TracerEvent::Call { stipend, .. } => Some(stipend),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(
+			stipends,
+			vec![None, Some(2300), Some(2300), Some(2300), None]
+		);
+	}
+
+	#[test]
+	fn transaction_state_is_reset_between_transactions() {
+		let eoa = H160::repeat_byte(0x01);
+		let created = H160::repeat_byte(0x02);
+
+		let events = trace_events_with(
+			|tracer| tracer.with_eip_6780(),
+			|tracer| {
+				// The first transaction creates the contract.
+				EvmListener::event(
+					tracer,
+					evm::tracing::Event::TransactCreate {
+						caller: eoa,
+						value: 0.into(),
+						init_code: &[],
+						gas_limit: 1_000_000,
+						address: created,
+					},
+				);
+				create(tracer, eoa, created);
+				exit(tracer);
+				// The second one is not the transaction creating it.
+				transact_call(tracer, eoa, created, 0.into());
+				call(tracer, eoa, created, 0.into(), &[], None);
+				suicide(tracer, created, eoa, 0.into());
+				exit(tracer);
+			},
+		);
+
+		let deleted: Vec<_> = tracer_events(&events)
+			.into_iter()
+			.filter_map(|event| match event {
+				TracerEvent::Selfdestruct { deleted, .. } => Some(deleted),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(deleted, vec![false]);
+	}
+
+	#[test]
+	fn slots_are_cold_again_in_the_next_transaction() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let slot = H256::repeat_byte(0x03);
+
+		let events = trace_events_with(
+			|tracer| tracer.with_account_accesses(),
+			|tracer| {
+				for _ in 0..2 {
+					transact_call(tracer, eoa, contract, 0.into());
+					call(tracer, eoa, contract, 0.into(), &[], None);
+					step(tracer, Opcode::SLOAD, 0, &[slot]);
+					step(tracer, Opcode::SLOAD, 1, &[slot]);
+					exit(tracer);
+				}
+			},
+		);
+
+		let accesses: Vec<_> = tracer_events(&events)
+			.into_iter()
+			.filter_map(|event| match event {
+				TracerEvent::StorageAccess {
+					address,
+					slot: accessed,
+					cold,
+				} => {
+					assert_eq!((address, accessed), (contract, slot));
+					Some(cold)
+				}
+				_ => None,
+			})
+			.collect();
+		assert_eq!(accesses, vec![true, false, true, false]);
+	}
+
+	#[test]
+	fn slots_accessed_by_a_reverted_frame_are_cold_again() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let callee = H160::repeat_byte(0x03);
+		let slot = H256::repeat_byte(0x04);
+		let revert = evm::ExitReason::Revert(evm::ExitRevert::Reverted);
+
+		let events = trace_events_with(
+			|tracer| tracer.with_account_accesses(),
+			|tracer| {
+				transact_call(tracer, eoa, contract, 0.into());
+				call(tracer, eoa, contract, 0.into(), &[], None);
+				call(tracer, contract, callee, 0.into(), &[], None);
+				step(tracer, Opcode::SLOAD, 0, &[slot]);
+				exit_with(tracer, revert, &[]);
+				call(tracer, contract, callee, 0.into(), &[], None);
+				step(tracer, Opcode::SLOAD, 0, &[slot]);
+				exit(tracer);
+				exit(tracer);
+			},
+		);
+
+		let accesses: Vec<_> = tracer_events(&events)
+			.into_iter()
+			.filter_map(|event| match event {
+				TracerEvent::StorageAccess { cold, .. } => Some(cold),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(accesses, vec![true, true]);
+	}
+
+	#[test]
+	fn refund_is_reset_between_transactions() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+
+		let result = trace_result(|tracer| {
+			transact_call(tracer, eoa, contract, 0.into());
+			call(tracer, eoa, contract, 0.into(), &[], None);
+			GasometerListener::event(
+				tracer,
+				evm_gasometer::tracing::Event::RecordRefund {
+					refund: 4_800,
+					snapshot: None,
+				},
+			);
+			exit(tracer);
+			// The second transaction records no refund.
+			transact_call(tracer, eoa, contract, 0.into());
+			call(tracer, eoa, contract, 0.into(), &[], None);
+			record_cost(tracer, 3);
+			exit(tracer);
+		});
+
+		assert_eq!(result.gas_breakdown.refund, 0);
+	}
+}
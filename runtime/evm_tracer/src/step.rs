@@ -0,0 +1,654 @@
+// Copyright 2019-2022 PureStake Inc.
+// This file is part of Moonbeam.
+
+// Moonbeam is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Moonbeam is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Inspection of the steps, deriving from their operands the accesses they make and filtering
+//! the step events to emit.
+
+use crate::tracer::EvmTracer;
+use ethereum_types::{H160, U256};
+use evm::Opcode;
+use evm_tracing_events::{tracer::TracerError, TracerEvent};
+use sp_std::vec::Vec;
+
+impl EvmTracer {
+	/// Whether the step events of the current frame must be emitted.
+	pub(crate) fn is_deep_traced(&self) -> bool {
+		match &self.config.deep_trace_addresses {
+			None => true,
+			Some(addresses) => self
+				.state
+				.frames
+				.last()
+				.map_or(false, |frame| addresses.contains(&frame.code_address)),
+		}
+	}
+
+	/// Whether the event is an `SLOAD` reading the value already emitted for the slot in the
+	/// current frame. Keeps track of the emitted values.
+	pub(crate) fn is_duplicate_sload(&mut self, event: &evm_runtime::tracing::Event) -> bool {
+		let frame = match self.state.frames.last_mut() {
+			Some(frame) => frame,
+			None => return false,
+		};
+		match event {
+			evm_runtime::tracing::Event::SLoad { index, value, .. } => {
+				frame.sloads.insert(*index, *value) == Some(*value)
+			}
+			evm_runtime::tracing::Event::SStore { index, .. } => {
+				// Next read of the slot must be emitted.
+				frame.sloads.remove(index);
+				false
+			}
+			_ => false,
+		}
+	}
+
+	/// Access to the return data made by the opcode, if it is `RETURNDATASIZE` or
+	/// `RETURNDATACOPY`.
+	pub(crate) fn return_data_access(
+		&self,
+		opcode: Opcode,
+		stack: &evm::Stack,
+	) -> Option<TracerEvent> {
+		let total_available = self.state.frames.last()?.return_data_len;
+		let (offset, size) = match opcode {
+			Opcode::RETURNDATASIZE => (U256::zero(), U256::zero()),
+			Opcode::RETURNDATACOPY => {
+				// Stack is `memory_offset, offset, size`.
+				let offset = stack.peek(1).ok()?;
+				let size = stack.peek(2).ok()?;
+				(
+					U256::from_big_endian(offset.as_bytes()),
+					U256::from_big_endian(size.as_bytes()),
+				)
+			}
+			_ => return None,
+		};
+		let out_of_bounds = offset
+			.checked_add(size)
+			.map_or(true, |end| end > U256::from(total_available));
+		Some(TracerEvent::ReturnData {
+			opcode: opcode.as_u8(),
+			offset,
+			size,
+			total_available,
+			out_of_bounds,
+		})
+	}
+
+	/// Read of the input made by the opcode, if it is `CALLDATALOAD` or `CALLDATACOPY`.
+	/// Reads of the transaction frame are recorded in the calldata coverage.
+	pub(crate) fn calldata_read(
+		&mut self,
+		opcode: Opcode,
+		stack: &evm::Stack,
+	) -> Option<TracerEvent> {
+		let (offset, size) = match opcode {
+			Opcode::CALLDATALOAD => (stack.peek(0).ok()?, U256::from(32)),
+			Opcode::CALLDATACOPY => {
+				// Stack is `memory_offset, offset, size`.
+				let size = stack.peek(2).ok()?;
+				(stack.peek(1).ok()?, U256::from_big_endian(size.as_bytes()))
+			}
+			_ => return None,
+		};
+		let offset = U256::from_big_endian(offset.as_bytes());
+
+		if self.state.frames.len() == 1 {
+			let summary = &mut self.state.summary;
+			let len = U256::from(summary.calldata_len);
+			let start = offset.min(len).as_usize();
+			let end = offset.saturating_add(size).min(len).as_usize();
+			for byte in start..end {
+				summary.calldata_coverage[byte / 8] |= 1 << (byte % 8);
+			}
+		}
+
+		Some(TracerEvent::CalldataRead { offset, size })
+	}
+
+	/// Access to an account made by the opcode, if any.
+	pub(crate) fn account_access(
+		&mut self,
+		opcode: Opcode,
+		stack: &evm::Stack,
+	) -> Option<TracerEvent> {
+		let address = match opcode {
+			Opcode::BALANCE
+			| Opcode::EXTCODESIZE
+			| Opcode::EXTCODECOPY
+			| Opcode::EXTCODEHASH
+			| Opcode::SUICIDE => stack.peek(0),
+			// Stack is `gas, address, ...`.
+			Opcode::CALL | Opcode::CALLCODE | Opcode::DELEGATECALL | Opcode::STATICCALL => {
+				stack.peek(1)
+			}
+			Opcode::SELFBALANCE => {
+				return Some(TracerEvent::AccountAccess {
+					address: self.state.frames.last()?.address,
+					cold: false,
+				});
+			}
+			_ => return None,
+		};
+		let address = match address {
+			Ok(address) => H160::from(address),
+			Err(_) => return Some(self.malformed_step(opcode)),
+		};
+		let cold = self.state.access_address(address);
+		Some(TracerEvent::AccountAccess { address, cold })
+	}
+
+	/// Access to a storage slot of the current frame made by the opcode, if any.
+	pub(crate) fn storage_access(
+		&mut self,
+		opcode: Opcode,
+		stack: &evm::Stack,
+	) -> Option<TracerEvent> {
+		if opcode != Opcode::SLOAD && opcode != Opcode::SSTORE {
+			return None;
+		}
+		let slot = match stack.peek(0) {
+			Ok(slot) => slot,
+			Err(_) => return Some(self.malformed_step(opcode)),
+		};
+		let address = self.state.frames.last()?.address;
+		let cold = self.state.access_slot(address, slot);
+		Some(TracerEvent::StorageAccess {
+			address,
+			slot,
+			cold,
+		})
+	}
+
+	/// Error of a step of the opcode whose stack is too short for the operands the tracer
+	/// reads.
+	pub(crate) fn malformed_step(&self, opcode: Opcode) -> TracerEvent {
+		TracerEvent::Error(TracerError::MalformedStep {
+			pc: self.state.pc,
+			opcode: opcode.0,
+		})
+	}
+
+	/// `TracerEvent::Keccak` of the `SHA3` of the previous step, whose output is the value it
+	/// pushed on the stack. The memory is also expanded at this point.
+	pub(crate) fn keccak_output(
+		&mut self,
+		stack: &evm::Stack,
+		memory: &evm::Memory,
+	) -> Option<TracerEvent> {
+		let (offset, size) = self.state.pending_keccak.take()?;
+		let output = stack.peek(0).ok()?;
+		let input = if size.is_zero() {
+			Vec::new()
+		} else {
+			memory.get(offset.low_u64() as usize, size.low_u64() as usize)
+		};
+		Some(TracerEvent::Keccak { input, output })
+	}
+
+	/// Records the memory hashed by the opcode if it is a `SHA3`, whose output is only available
+	/// at the next step. Returns the error of a malformed step.
+	pub(crate) fn keccak_input(
+		&mut self,
+		opcode: Opcode,
+		stack: &evm::Stack,
+	) -> Option<TracerEvent> {
+		if opcode != Opcode::SHA3 {
+			return None;
+		}
+		match (stack.peek(0), stack.peek(1)) {
+			(Ok(offset), Ok(size)) => {
+				self.state.pending_keccak = Some((
+					U256::from_big_endian(offset.as_bytes()),
+					U256::from_big_endian(size.as_bytes()),
+				));
+				None
+			}
+			_ => Some(self.malformed_step(opcode)),
+		}
+	}
+}
+
+/// 4-byte function selector of a call input, if it is long enough to contain one.
+pub(crate) fn selector(input: &[u8]) -> Option<[u8; 4]> {
+	if input.len() < 4 {
+		return None;
+	}
+	let mut selector = [0u8; 4];
+	selector.copy_from_slice(&input[..4]);
+	Some(selector)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::*;
+	use std::collections::BTreeSet;
+
+	#[test]
+	fn call_reports_selector() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let callee = H160::repeat_byte(0x03);
+
+		let events = trace_events_with(
+			|tracer| tracer.with_call_details(),
+			|tracer| {
+				transact_call(tracer, eoa, contract, 0.into());
+				call(
+					tracer,
+					eoa,
+					contract,
+					0.into(),
+					&[0xa9, 0x05, 0x9c, 0xbb, 0x01],
+					None,
+				);
+				call(tracer, contract, callee, 0.into(), &[0xa9, 0x05], None);
+				exit(tracer);
+				call(tracer, contract, callee, 1.into(), &[], None);
+				exit(tracer);
+				exit(tracer);
+			},
+		);
+
+		let selectors: Vec<_> = tracer_events(&events)
+			.into_iter()
+			.filter_map(|event| match event {
+				TracerEvent::Call { selector, .. } => Some(selector),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(selectors, vec![Some([0xa9, 0x05, 0x9c, 0xbb]), None, None]);
+	}
+
+	#[test]
+	fn call_input_is_byte_exact() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let callee = H160::repeat_byte(0x03);
+		// ABI encoded `f(uint256)` call with trailing zeros.
+		let mut input = vec![0xaa, 0xbb, 0xcc, 0xdd];
+		input.extend_from_slice(&[0u8; 31]);
+		input.push(0x01);
+		input.extend_from_slice(&[0u8; 7]);
+
+		let events = trace_events(|tracer| {
+			transact_call(tracer, eoa, contract, 0.into());
+			call(tracer, eoa, contract, 0.into(), &[], None);
+			call(tracer, contract, callee, 0.into(), &input, None);
+			exit(tracer);
+			exit(tracer);
+		});
+
+		let inputs: Vec<_> = events
+			.into_iter()
+			.filter_map(|event| match event {
+				Event::Evm(EvmEvent::Call { input, .. }) => Some(input),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(inputs, vec![vec![], input]);
+	}
+
+	#[test]
+	fn keccak_reports_preimage() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		// Slot of `balances[key]` for a mapping `balances` declared at slot 3.
+		let key = H256::from(H160::repeat_byte(0xaa));
+		let slot = H256::from_low_u64_be(3);
+		let preimage = [key.as_bytes(), slot.as_bytes()].concat();
+		let hash = H256::from(sp_core::keccak_256(&preimage));
+
+		let events = trace_events_with(
+			|tracer| tracer.with_keccak_preimages(),
+			|tracer| {
+				transact_call(tracer, eoa, contract, 0.into());
+				call(tracer, eoa, contract, 0.into(), &[], None);
+				// SHA3 of 64 bytes at offset 0.
+				step_with_memory(
+					tracer,
+					evm::Opcode::SHA3,
+					10,
+					&[H256::from_low_u64_be(64), H256::zero()],
+					&preimage,
+				);
+				step_result(tracer);
+				step_with_memory(tracer, evm::Opcode::SLOAD, 11, &[hash], &preimage);
+				step_result(tracer);
+				exit(tracer);
+			},
+		);
+
+		let keccaks: Vec<_> = tracer_events(&events)
+			.into_iter()
+			.filter(|event| matches!(event, TracerEvent::Keccak { .. }))
+			.collect();
+		assert_eq!(
+			keccaks,
+			vec![TracerEvent::Keccak {
+				input: preimage,
+				output: hash,
+			}]
+		);
+	}
+
+	#[test]
+	fn short_stack_is_a_malformed_step() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+
+		let events = trace_events_with(
+			|tracer| tracer.with_keccak_preimages(),
+			|tracer| {
+				transact_call(tracer, eoa, contract, 0.into());
+				call(tracer, eoa, contract, 0.into(), &[], None);
+				step(tracer, evm::Opcode::SHA3, 7, &[H256::zero()]);
+				exit(tracer);
+			},
+		);
+
+		assert!(
+			tracer_events(&events).contains(&TracerEvent::Error(TracerError::MalformedStep {
+				pc: 7,
+				opcode: 0x20
+			}))
+		);
+	}
+
+	#[test]
+	fn return_data_copy_reports_over_reads() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let callee = H160::repeat_byte(0x03);
+		let word = |value: u64| H256::from_low_u64_be(value);
+
+		let events = trace_events_with(
+			|tracer| tracer.with_return_data_accesses(),
+			|tracer| {
+				transact_call(tracer, eoa, contract, 0.into());
+				call(tracer, eoa, contract, 0.into(), &[], None);
+				call(tracer, contract, callee, 0.into(), &[], None);
+				exit_with(
+					tracer,
+					evm::ExitReason::Succeed(evm::ExitSucceed::Returned),
+					&[0u8; 64],
+				);
+				step(tracer, evm::Opcode::RETURNDATASIZE, 0, &[]);
+				// Stack is pushed bottom first: `size, offset, memory_offset`.
+				step(
+					tracer,
+					evm::Opcode::RETURNDATACOPY,
+					1,
+					&[word(64), word(0), word(0)],
+				);
+				step(
+					tracer,
+					evm::Opcode::RETURNDATACOPY,
+					2,
+					&[word(32), word(48), word(0)],
+				);
+				exit(tracer);
+			},
+		);
+
+		let accesses: Vec<_> = tracer_events(&events)
+			.into_iter()
+			.filter_map(|event| match event {
+				TracerEvent::ReturnData {
+					opcode,
+					offset,
+					size,
+					total_available,
+					out_of_bounds,
+				} => Some((opcode, offset, size, total_available, out_of_bounds)),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(
+			accesses,
+			vec![
+				(0x3d, 0.into(), 0.into(), 64, false),
+				(0x3e, 0.into(), 64.into(), 64, false),
+				(0x3e, 48.into(), 32.into(), 64, true),
+			]
+		);
+	}
+
+	#[test]
+	fn calldata_coverage_reports_read_bytes() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		// Selector followed by two parameters.
+		let input = [0xab; 68];
+
+		let mut result = None;
+		let events = host_events(|| {
+			let mut tracer = EvmTracer::new().with_calldata_reads();
+			EvmListener::event(
+				&mut tracer,
+				evm::tracing::Event::TransactCall {
+					caller: eoa,
+					address: contract,
+					value: 0.into(),
+					data: &input,
+					gas_limit: 1_000_000,
+				},
+			);
+			call(&mut tracer, eoa, contract, 0.into(), &input, None);
+			// Only the selector and the first parameter are read.
+			step(
+				&mut tracer,
+				evm::Opcode::CALLDATALOAD,
+				0,
+				&[H256::from_low_u64_be(0)],
+			);
+			step(
+				&mut tracer,
+				evm::Opcode::CALLDATALOAD,
+				5,
+				&[H256::from_low_u64_be(4)],
+			);
+			exit(&mut tracer);
+			result = Some(tracer.finish());
+		});
+
+		let reads: Vec<_> = tracer_events(&events)
+			.into_iter()
+			.filter(|event| matches!(event, TracerEvent::CalldataRead { .. }))
+			.collect();
+		assert_eq!(
+			reads,
+			vec![
+				TracerEvent::CalldataRead {
+					offset: 0.into(),
+					size: 32.into(),
+				},
+				TracerEvent::CalldataRead {
+					offset: 4.into(),
+					size: 32.into(),
+				},
+			]
+		);
+		assert_eq!(
+			result.unwrap().calldata_coverage,
+			vec![0xff, 0xff, 0xff, 0xff, 0x0f, 0x00, 0x00, 0x00, 0x00]
+		);
+	}
+
+	#[test]
+	fn account_accesses_start_from_prewarmed_addresses() {
+		let contract = H160::repeat_byte(0x02);
+		let unrelated = H160::repeat_byte(0x03);
+		// ADDRESS BALANCE POP SELFBALANCE POP
+		let mut code = vec![0x30, 0x31, 0x50, 0x47, 0x50];
+		for _ in 0..2 {
+			// PUSH20 unrelated BALANCE POP
+			code.push(0x73);
+			code.extend_from_slice(unrelated.as_bytes());
+			code.extend_from_slice(&[0x31, 0x50]);
+		}
+		// PUSH1 1 BALANCE POP STOP
+		code.extend_from_slice(&[0x60, 0x01, 0x31, 0x50, 0x00]);
+
+		let events = host_events(|| {
+			EvmTracer::new()
+				.with_account_accesses()
+				.trace(|| execute(code));
+		});
+
+		let accesses: Vec<_> = tracer_events(&events)
+			.into_iter()
+			.filter_map(|event| match event {
+				TracerEvent::AccountAccess { address, cold } => Some((address, cold)),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(
+			accesses,
+			vec![
+				(contract, false),
+				(contract, false),
+				(unrelated, true),
+				(unrelated, false),
+				(H160::from_low_u64_be(1), false),
+			]
+		);
+	}
+
+	#[test]
+	fn sload_dedup_emits_first_read_only() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let slot = H256::repeat_byte(0x10);
+		let (one, two) = (H256::from_low_u64_be(1), H256::from_low_u64_be(2));
+
+		let events = trace_events_with(
+			|tracer| tracer.with_sload_dedup(),
+			|tracer| {
+				transact_call(tracer, eoa, contract, 0.into());
+				call(tracer, eoa, contract, 0.into(), &[], None);
+				for _ in 0..10 {
+					sload(tracer, contract, slot, one);
+				}
+				sstore(tracer, contract, slot, two);
+				sload(tracer, contract, slot, two);
+				sload(tracer, contract, slot, two);
+				exit(tracer);
+			},
+		);
+
+		let accesses: Vec<_> = events
+			.into_iter()
+			.filter_map(|event| match event {
+				Event::Runtime(RuntimeEvent::SLoad { value, .. }) => Some(("sload", value)),
+				Event::Runtime(RuntimeEvent::SStore { value, .. }) => Some(("sstore", value)),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(
+			accesses,
+			vec![("sload", one), ("sstore", two), ("sload", two)]
+		);
+	}
+
+	#[test]
+	fn deep_trace_addresses_filter_steps() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let callee = H160::repeat_byte(0x03);
+
+		let events = trace_events_with(
+			|tracer| tracer.with_deep_trace_addresses(BTreeSet::from([callee])),
+			|tracer| {
+				transact_call(tracer, eoa, contract, 0.into());
+				call(tracer, eoa, contract, 0.into(), &[], None);
+				step(tracer, evm::Opcode::PUSH1, 0, &[]);
+				step_result(tracer);
+				call(tracer, contract, callee, 0.into(), &[], None);
+				step(tracer, evm::Opcode::PUSH1, 0, &[]);
+				step_result(tracer);
+				step(tracer, evm::Opcode::STOP, 2, &[]);
+				exit(tracer);
+				step(tracer, evm::Opcode::STOP, 1, &[]);
+				exit(tracer);
+			},
+		);
+
+		let steps: Vec<_> = events
+			.iter()
+			.filter_map(|event| match event {
+				Event::Runtime(RuntimeEvent::Step { position, .. }) => Some(position.clone()),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(steps, vec![Ok(0), Ok(2)]);
+		assert_eq!(
+			events
+				.iter()
+				.filter(|event| matches!(event, Event::Runtime(RuntimeEvent::StepResult { .. })))
+				.count(),
+			1
+		);
+		// Structural events are kept for all frames.
+		assert_eq!(
+			events
+				.iter()
+				.filter(|event| matches!(event, Event::Evm(EvmEvent::Call { .. })))
+				.count(),
+			2
+		);
+	}
+
+	#[test]
+	fn opcode_filter_drops_other_steps() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+
+		let events = trace_events_with(
+			|tracer| tracer.with_opcode_filter(&[evm::Opcode::SSTORE.0][..]),
+			|tracer| {
+				transact_call(tracer, eoa, contract, 0.into());
+				call(tracer, eoa, contract, 0.into(), &[], None);
+				step(tracer, evm::Opcode::PUSH1, 0, &[]);
+				step_result(tracer);
+				step(
+					tracer,
+					evm::Opcode::SSTORE,
+					2,
+					&[H256::zero(), H256::zero()],
+				);
+				step_result(tracer);
+				exit(tracer);
+			},
+		);
+
+		let steps: Vec<_> = events
+			.iter()
+			.filter_map(|event| match event {
+				Event::Runtime(RuntimeEvent::Step { opcode, .. }) => Some(opcode.clone()),
+				_ => None,
+			})
+			.collect();
+		let step_results = events
+			.iter()
+			.filter(|event| matches!(event, Event::Runtime(RuntimeEvent::StepResult { .. })))
+			.count();
+		assert_eq!(steps, vec![b"SStore".to_vec()]);
+		assert_eq!(step_results, 1);
+	}
+}
@@ -0,0 +1,534 @@
+// Copyright 2019-2022 PureStake Inc.
+// This file is part of Moonbeam.
+
+// Moonbeam is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Moonbeam is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Aggregated data of a transaction, reported in summary mode and in the result of its trace.
+
+use crate::schedule::Hardfork;
+use codec::{Decode, Encode};
+use ethereum_types::{H160, H256};
+use evm::Opcode;
+use evm_tracing_events::{
+	tracer::{GasBreakdown, TxContext},
+	Event, GasometerEvent, RuntimeEvent,
+};
+use sp_std::{
+	collections::{btree_map::BTreeMap, btree_set::BTreeSet},
+	vec::Vec,
+};
+
+/// Aggregated data of the transaction.
+#[derive(Clone, Default)]
+pub(crate) struct Summary {
+	/// Intrinsic cost of the transaction.
+	pub(crate) transaction_cost: u64,
+	/// Gas used by the transaction frame, including the gas forwarded to subcalls and not
+	/// returned.
+	pub(crate) frame_gas_used: u64,
+	pub(crate) touched: BTreeSet<H160>,
+	/// Set when the transaction frame exits.
+	pub(crate) failed: Option<bool>,
+	/// Whether the transaction frame exited with a revert.
+	pub(crate) top_message_reverted: bool,
+	/// Amount of subcalls that exited with a revert.
+	pub(crate) inner_messages_reverted: u64,
+	/// Length of the transaction input.
+	pub(crate) calldata_len: usize,
+	/// Bitmap of the bytes of the transaction input read by the transaction frame.
+	pub(crate) calldata_coverage: Vec<u8>,
+	/// Addresses accessed by the transaction, with the storage slots accessed for each.
+	pub(crate) accessed: BTreeMap<H160, BTreeSet<H256>>,
+	/// Addresses warm from the start of the transaction, apart from the precompiles.
+	pub(crate) warm: BTreeSet<H160>,
+	/// Cost of the input of the transaction, included in `transaction_cost`.
+	pub(crate) calldata_gas: u64,
+	/// Refund counter of the gasometer of the transaction frame.
+	pub(crate) refund: i64,
+}
+
+impl Summary {
+	pub(crate) fn touch(&mut self, event: &evm::tracing::Event) {
+		match event {
+			evm::tracing::Event::TransactCall {
+				caller, address, ..
+			}
+			| evm::tracing::Event::TransactCreate {
+				caller, address, ..
+			}
+			| evm::tracing::Event::TransactCreate2 {
+				caller, address, ..
+			}
+			| evm::tracing::Event::Create {
+				caller, address, ..
+			} => {
+				self.touch_address(*caller);
+				self.touch_address(*address);
+				if !matches!(event, evm::tracing::Event::Create { .. }) {
+					self.warm.insert(*caller);
+					self.warm.insert(*address);
+				}
+			}
+			evm::tracing::Event::Call {
+				code_address,
+				transfer,
+				context,
+				..
+			} => {
+				self.touch_address(*code_address);
+				self.touch_address(context.address);
+				if let Some(transfer) = transfer {
+					self.touch_address(transfer.target);
+				}
+			}
+			evm::tracing::Event::Suicide {
+				address, target, ..
+			} => {
+				self.touch_address(*address);
+				self.touch_address(*target);
+			}
+			_ => (),
+		}
+	}
+
+	/// Gas used by the transaction, before refunds.
+	pub(crate) fn gas_used(&self) -> u64 {
+		self.transaction_cost.saturating_add(self.frame_gas_used)
+	}
+
+	pub(crate) fn gas_breakdown(&self) -> GasBreakdown {
+		GasBreakdown {
+			intrinsic: self.transaction_cost.saturating_sub(self.calldata_gas),
+			calldata: self.calldata_gas,
+			execution: self.frame_gas_used,
+			refund: self.refund.max(0) as u64,
+		}
+	}
+
+	pub(crate) fn touch_address(&mut self, address: H160) {
+		self.touched.insert(address);
+		self.accessed.entry(address).or_default();
+	}
+
+	/// Records the addresses and storage slots accessed by the runtime event.
+	pub(crate) fn record_access(&mut self, event: &evm_runtime::tracing::Event) {
+		match event {
+			evm_runtime::tracing::Event::Step { opcode, stack, .. } => {
+				let accesses_account = [
+					Opcode::BALANCE,
+					Opcode::EXTCODESIZE,
+					Opcode::EXTCODECOPY,
+					Opcode::EXTCODEHASH,
+				]
+				.contains(opcode);
+				match stack.peek(0) {
+					Ok(address) if accesses_account => {
+						self.accessed.entry(H160::from(address)).or_default();
+					}
+					_ => (),
+				}
+			}
+			evm_runtime::tracing::Event::SLoad { address, index, .. }
+			| evm_runtime::tracing::Event::SStore { address, index, .. } => {
+				self.accessed.entry(*address).or_default().insert(*index);
+			}
+			_ => (),
+		}
+	}
+
+	/// Records the gas used by the transaction frame after a gasometer event of its
+	/// gasometer. The snapshot is taken before the event is recorded.
+	pub(crate) fn record_gas(&mut self, event: &GasometerEvent) {
+		self.frame_gas_used = match *event {
+			GasometerEvent::RecordTransaction { cost, .. } => {
+				self.transaction_cost = cost;
+				return;
+			}
+			GasometerEvent::RecordCost { cost, snapshot } => snapshot
+				.used_gas
+				.saturating_add(snapshot.memory_gas)
+				.saturating_add(cost),
+			GasometerEvent::RecordDynamicCost {
+				gas_cost,
+				memory_gas,
+				snapshot,
+				..
+			} => snapshot
+				.used_gas
+				.saturating_add(gas_cost)
+				.saturating_add(memory_gas),
+			GasometerEvent::RecordStipend { stipend, snapshot } => snapshot
+				.used_gas
+				.saturating_add(snapshot.memory_gas)
+				.saturating_sub(stipend),
+			GasometerEvent::RecordRefund { refund, snapshot } => {
+				self.refund = snapshot.refunded_gas.saturating_add(refund);
+				return;
+			}
+		};
+	}
+}
+
+/// Result of a trace, see `EvmTracer::finish`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+pub struct TraceResult {
+	/// Transaction traced, if given to `EvmTracer::new_with_tx`.
+	pub tx: Option<TxContext>,
+	/// Hardfork the transaction ran under, if given to `EvmTracer::with_hardfork`.
+	pub hardfork: Option<Hardfork>,
+	/// Gas used by the transaction, before refunds.
+	pub gas_used: u64,
+	/// Intrinsic cost of the transaction, included in `gas_used`.
+	pub intrinsic_gas: u64,
+	pub gas_breakdown: GasBreakdown,
+	/// Addresses touched by the transaction, in ascending order.
+	pub touched: Vec<H160>,
+	/// Whether the whole transaction committed. `None` if the transaction frame did not exit
+	/// during the trace.
+	pub tx_succeeded: Option<bool>,
+	/// Whether the transaction frame reverted. A transaction frame exiting with an error
+	/// fails without reverting.
+	pub top_message_reverted: bool,
+	/// Amount of subcalls that reverted. A reverted subcall caught by its caller doesn't fail
+	/// the transaction.
+	pub inner_messages_reverted: u64,
+	/// Whether events of the transaction were not sent because it succeeded, see
+	/// `EvmTracer::only_if_failed`.
+	pub events_discarded: bool,
+	/// Bitmap of the bytes of the transaction input read by the transaction frame, byte `i`
+	/// being bit `i % 8` of `calldata_coverage[i / 8]`. Empty unless
+	/// `EvmTracer::with_calldata_reads` is set.
+	pub calldata_coverage: Vec<u8>,
+	/// Addresses accessed by the transaction, with the storage slots accessed for each, both
+	/// in ascending order.
+	pub accessed: Vec<(H160, Vec<H256>)>,
+	/// Addresses warm from the start of the transaction apart from the precompiles: its
+	/// sender and recipient, and the coinbase since Shanghai if given to
+	/// `EvmTracer::with_coinbase`.
+	pub warm_addresses: Vec<H160>,
+	/// Events of the trace, in emission order. Empty unless `EvmTracer::capture_events` is
+	/// set.
+	pub events: Vec<Event>,
+}
+
+impl TraceResult {
+	/// EIP-2930 access list of the accesses of the transaction. Like `eth_createAccessList`,
+	/// addresses warm from the start of the transaction (sender, recipient, coinbase and the
+	/// precompiles of the specification) are only listed if storage slots of theirs were
+	/// accessed, since listing them alone only adds to the cost of the transaction.
+	pub fn access_list(&self) -> Vec<(H160, Vec<H256>)> {
+		self.accessed
+			.iter()
+			.filter(|(address, slots)| {
+				!slots.is_empty()
+					|| !(self.warm_addresses.contains(address) || is_precompile(address))
+			})
+			.cloned()
+			.collect()
+	}
+
+	/// Normalizes the result for comparison with golden files or the traces of other
+	/// clients. Events keep their emission order, which is deterministic, but the data only
+	/// modeled by the `evm` crates is cleared: the snapshots of the gasometer events and
+	/// the memory of the step events.
+	pub fn canonicalize(&mut self) {
+		self.touched.sort();
+		for event in self.events.iter_mut() {
+			match event {
+				Event::Gasometer(GasometerEvent::RecordCost { snapshot, .. })
+				| Event::Gasometer(GasometerEvent::RecordRefund { snapshot, .. })
+				| Event::Gasometer(GasometerEvent::RecordStipend { snapshot, .. })
+				| Event::Gasometer(GasometerEvent::RecordDynamicCost { snapshot, .. })
+				| Event::Gasometer(GasometerEvent::RecordTransaction { snapshot, .. }) => {
+					*snapshot = Default::default();
+				}
+				Event::Runtime(RuntimeEvent::Step { memory, .. }) => *memory = None,
+				_ => (),
+			}
+		}
+	}
+}
+
+/// Amount of precompiles of the Ethereum specification, at the addresses 1 to `PRECOMPILES`.
+/// Other precompiles of the chain are listed like any other account by
+/// `TraceResult::access_list`.
+pub const PRECOMPILES: u64 = 9;
+
+pub(crate) fn is_precompile(address: &H160) -> bool {
+	!address.is_zero() && *address <= H160::from_low_u64_be(PRECOMPILES)
+}
+
+/// Cost of the input of a transaction (EIP-2028).
+pub(crate) fn calldata_gas(data: &[u8]) -> u64 {
+	data.iter()
+		.map(|byte| if *byte == 0 { 4 } else { 16 })
+		.sum()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::*;
+
+	#[test]
+	fn finish_reports_trace_result() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let callee = H160::repeat_byte(0x03);
+
+		let mut result = None;
+		host_events(|| {
+			let (mut tracer, _) = EvmTracer::new().only_if_failed().trace_with_tracer(|| ());
+			transact_call(&mut tracer, eoa, contract, 0.into());
+			GasometerListener::event(
+				&mut tracer,
+				evm_gasometer::tracing::Event::RecordTransaction {
+					cost: 21_000,
+					snapshot: None,
+				},
+			);
+			call(&mut tracer, eoa, contract, 0.into(), &[], None);
+			call(&mut tracer, contract, callee, 0.into(), &[], None);
+			// Gas forwarded by the transaction frame.
+			record_cost(&mut tracer, 5_000);
+			exit(&mut tracer);
+			exit(&mut tracer);
+			result = Some(tracer.finish());
+		});
+
+		assert_eq!(
+			result,
+			Some(TraceResult {
+				tx: None,
+				hardfork: None,
+				gas_used: 26_000,
+				intrinsic_gas: 21_000,
+				gas_breakdown: GasBreakdown {
+					intrinsic: 21_000,
+					calldata: 0,
+					execution: 5_000,
+					refund: 0,
+				},
+				touched: vec![eoa, contract, callee],
+				tx_succeeded: Some(true),
+				top_message_reverted: false,
+				inner_messages_reverted: 0,
+				events_discarded: true,
+				calldata_coverage: Vec::new(),
+				accessed: vec![(eoa, vec![]), (contract, vec![]), (callee, vec![])],
+				warm_addresses: vec![eoa, contract],
+				events: Vec::new(),
+			})
+		);
+	}
+
+	#[test]
+	fn gas_breakdown_splits_calldata_cost() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let mut data = vec![0; 30];
+		data.extend_from_slice(&[0xff; 70]);
+		let calldata = 30 * 4 + 70 * 16;
+
+		let result = trace_result(|tracer| {
+			EvmListener::event(
+				tracer,
+				evm::tracing::Event::TransactCall {
+					caller: eoa,
+					address: contract,
+					value: 0.into(),
+					data: &data,
+					gas_limit: 1_000_000,
+				},
+			);
+			GasometerListener::event(
+				tracer,
+				evm_gasometer::tracing::Event::RecordTransaction {
+					cost: 21_000 + calldata,
+					snapshot: None,
+				},
+			);
+			call(tracer, eoa, contract, 0.into(), &data, None);
+			record_cost(tracer, 100_000);
+			record_cost_with_snapshot(tracer, 3, 100_000, 40_000);
+			GasometerListener::event(
+				tracer,
+				evm_gasometer::tracing::Event::RecordRefund {
+					refund: 4_800,
+					snapshot: Some(evm_gasometer::Snapshot {
+						gas_limit: 100_000,
+						memory_gas: 0,
+						used_gas: 40_003,
+						refunded_gas: 0,
+					}),
+				},
+			);
+			exit(tracer);
+		});
+
+		let breakdown = result.gas_breakdown;
+		assert_eq!(breakdown.calldata, calldata);
+		assert_eq!(breakdown.intrinsic, 21_000);
+		assert_eq!(breakdown.execution, 40_003);
+		assert_eq!(breakdown.refund, 4_800);
+		assert_eq!(
+			breakdown.intrinsic + breakdown.calldata + breakdown.execution,
+			result.gas_used
+		);
+	}
+
+	#[test]
+	fn top_level_revert_fails_transaction() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let revert = evm::ExitReason::Revert(evm::ExitRevert::Reverted);
+
+		let result = trace_result(|tracer| {
+			transact_call(tracer, eoa, contract, 0.into());
+			call(tracer, eoa, contract, 0.into(), &[], None);
+			exit_with(tracer, revert, &[]);
+		});
+
+		assert_eq!(result.tx_succeeded, Some(false));
+		assert!(result.top_message_reverted);
+		assert_eq!(result.inner_messages_reverted, 0);
+	}
+
+	#[test]
+	fn caught_inner_revert_doesnt_fail_transaction() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let callee = H160::repeat_byte(0x03);
+		let revert = evm::ExitReason::Revert(evm::ExitRevert::Reverted);
+
+		let result = trace_result(|tracer| {
+			transact_call(tracer, eoa, contract, 0.into());
+			call(tracer, eoa, contract, 0.into(), &[], None);
+			call(tracer, contract, callee, 0.into(), &[], None);
+			exit_with(tracer, revert, &[]);
+			exit(tracer);
+		});
+
+		assert_eq!(result.tx_succeeded, Some(true));
+		assert!(!result.top_message_reverted);
+		assert_eq!(result.inner_messages_reverted, 1);
+	}
+
+	#[test]
+	fn summary_only_reports_touched_addresses() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let callee = H160::repeat_byte(0x03);
+		let created = H160::repeat_byte(0x04);
+		let beneficiary = H160::repeat_byte(0x05);
+
+		let events = trace_events_with(
+			|tracer| tracer.summary_only(),
+			|tracer| {
+				transact_call(tracer, eoa, contract, 0.into());
+				call(tracer, eoa, contract, 0.into(), &[], None);
+				step(tracer, evm::Opcode::CALL, 0, &[]);
+				call(tracer, contract, callee, 1.into(), &[], None);
+				// Gas forwarded by the transaction frame.
+				record_cost(tracer, 50_000);
+				// Gas recorded by subcalls is not the transaction gas.
+				record_cost(tracer, 1_000_000);
+				create(tracer, callee, created);
+				suicide(tracer, created, beneficiary, 0.into());
+				exit(tracer);
+				exit(tracer);
+				record_dynamic_cost(tracer, 100, 6, 3);
+				exit(tracer);
+			},
+		);
+
+		assert_eq!(
+			events,
+			vec![Event::Tracer(TracerEvent::Summary {
+				gas_used: 106,
+				touched: vec![eoa, contract, callee, created, beneficiary],
+				gas_breakdown: GasBreakdown {
+					execution: 106,
+					..Default::default()
+				},
+				hardfork: None,
+			})]
+		);
+	}
+
+	#[test]
+	fn access_list_is_reconstructed() {
+		let contract = H160::repeat_byte(0x02);
+		let account = H160::repeat_byte(0xaa);
+		let mut code = vec![
+			0x60, 0x01, 0x54, 0x50, // PUSH1 0x01 SLOAD POP
+			0x60, 0x05, 0x60, 0x02, 0x55, // PUSH1 0x05 PUSH1 0x02 SSTORE
+			0x73, // PUSH20 account
+		];
+		code.extend_from_slice(account.as_bytes());
+		code.extend_from_slice(&[
+			0x31, 0x50, // BALANCE POP
+			0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, // PUSH1 0x00 (x4)
+			0x60, 0x04, 0x5a, 0xfa, 0x50, // PUSH1 0x04 GAS STATICCALL POP
+			0x00, // STOP
+		]);
+
+		let mut result = None;
+		host_events(|| {
+			let (_, trace_result) = EvmTracer::new().trace_with_result(|| execute(code));
+			result = Some(trace_result);
+		});
+
+		assert_eq!(
+			result.unwrap().access_list(),
+			vec![
+				(
+					contract,
+					vec![H256::from_low_u64_be(1), H256::from_low_u64_be(2)]
+				),
+				(account, vec![]),
+			]
+		);
+	}
+
+	#[test]
+	fn canonical_trace_matches_golden_file() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let canonical_trace = || {
+			let mut tracer = EvmTracer::new().capture_events();
+			transact_call(&mut tracer, eoa, contract, 0.into());
+			call(&mut tracer, eoa, contract, 0.into(), &[], None);
+			record_cost_with_snapshot(&mut tracer, 21_000, 100_000, 0);
+			exit(&mut tracer);
+			let mut result = tracer.finish();
+			result.canonicalize();
+			result
+		};
+
+		let mut results = Vec::new();
+		host_events(|| {
+			results.push(canonical_trace());
+			results.push(canonical_trace());
+		});
+
+		assert_eq!(results[0], results[1]);
+		let events: String = results[0]
+			.events
+			.iter()
+			.map(|event| format!("{:?}\n", event))
+			.collect();
+		assert_eq!(events, include_str!("../golden/canonical_trace.txt"));
+	}
+}
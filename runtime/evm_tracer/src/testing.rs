@@ -0,0 +1,336 @@
+// Copyright 2019-2022 PureStake Inc.
+// This file is part of Moonbeam.
+
+// Moonbeam is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Moonbeam is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Helpers of the tests, driving a tracer with the events of the `evm` crates and collecting the
+//! events received by the host.
+
+pub use crate::tracer::{EvmTracer, TraceResult};
+pub use ethereum_types::{H160, H256, U256};
+pub use evm::tracing::EventListener as EvmListener;
+pub use evm_gasometer::tracing::EventListener as GasometerListener;
+pub use evm_runtime::tracing::EventListener as RuntimeListener;
+pub use evm_tracing_events::{Event, EvmEvent, GasometerEvent, RuntimeEvent, TracerEvent};
+use evm_tracing_events::{Listener, StepEventFilter};
+
+#[derive(Default)]
+struct Collector {
+	events: Vec<Event>,
+}
+
+impl Listener for Collector {
+	fn event(&mut self, event: Event) {
+		self.events.push(event);
+	}
+
+	fn step_event_filter(&self) -> StepEventFilter {
+		StepEventFilter::default()
+	}
+}
+
+/// Run `f` with a new tracer, returning all the events received by the host.
+pub fn trace_events<F: FnOnce(&mut EvmTracer)>(f: F) -> Vec<Event> {
+	trace_events_with(|tracer| tracer, f)
+}
+
+/// Run `f` with a new tracer configured by `config`, returning all the events received by
+/// the host.
+pub fn trace_events_with<C, F>(config: C, f: F) -> Vec<Event>
+where
+	C: FnOnce(EvmTracer) -> EvmTracer,
+	F: FnOnce(&mut EvmTracer),
+{
+	host_events(|| {
+		let mut tracer = config(EvmTracer::new());
+		f(&mut tracer);
+	})
+}
+
+/// Run `f` in an environment with host functions, returning all the events they received.
+pub fn host_events<F: FnOnce()>(f: F) -> Vec<Event> {
+	let mut collector = Collector::default();
+	sp_io::TestExternalities::default()
+		.execute_with(|| evm_tracing_events::using(&mut collector, f));
+	collector.events
+}
+
+/// Run `f` with a new tracer and return its result.
+pub fn trace_result<F: FnOnce(&mut EvmTracer)>(f: F) -> TraceResult {
+	let mut result = None;
+	host_events(|| {
+		let mut tracer = EvmTracer::new();
+		f(&mut tracer);
+		result = Some(tracer.finish());
+	});
+	result.unwrap()
+}
+
+pub fn context(address: H160, caller: H160, apparent_value: U256) -> evm::Context {
+	evm::Context {
+		address,
+		caller,
+		apparent_value,
+	}
+}
+
+pub fn transact_call(tracer: &mut EvmTracer, caller: H160, address: H160, value: U256) {
+	EvmListener::event(
+		tracer,
+		evm::tracing::Event::TransactCall {
+			caller,
+			address,
+			value,
+			data: &[],
+			gas_limit: 1_000_000,
+		},
+	);
+}
+
+pub fn call(
+	tracer: &mut EvmTracer,
+	caller: H160,
+	address: H160,
+	value: U256,
+	input: &[u8],
+	target_gas: Option<u64>,
+) {
+	let transfer = Some(evm::Transfer {
+		source: caller,
+		target: address,
+		value,
+	});
+	let context = context(address, caller, value);
+	EvmListener::event(
+		tracer,
+		evm::tracing::Event::Call {
+			code_address: address,
+			transfer: &transfer,
+			input,
+			target_gas,
+			is_static: false,
+			context: &context,
+		},
+	);
+}
+
+pub fn create(tracer: &mut EvmTracer, caller: H160, address: H160) {
+	EvmListener::event(
+		tracer,
+		evm::tracing::Event::Create {
+			caller,
+			address,
+			scheme: evm::CreateScheme::Legacy { caller },
+			value: U256::zero(),
+			init_code: &[],
+			target_gas: None,
+		},
+	);
+}
+
+pub fn suicide(tracer: &mut EvmTracer, address: H160, target: H160, balance: U256) {
+	EvmListener::event(
+		tracer,
+		evm::tracing::Event::Suicide {
+			address,
+			target,
+			balance,
+		},
+	);
+}
+
+pub fn exit(tracer: &mut EvmTracer) {
+	exit_with(
+		tracer,
+		evm::ExitReason::Succeed(evm::ExitSucceed::Stopped),
+		&[],
+	);
+}
+
+pub fn exit_with(tracer: &mut EvmTracer, reason: evm::ExitReason, return_value: &[u8]) {
+	EvmListener::event(
+		tracer,
+		evm::tracing::Event::Exit {
+			reason: &reason,
+			return_value,
+		},
+	);
+}
+
+pub fn record_cost(tracer: &mut EvmTracer, cost: u64) {
+	GasometerListener::event(
+		tracer,
+		evm_gasometer::tracing::Event::RecordCost {
+			cost,
+			snapshot: None,
+		},
+	);
+}
+
+pub fn record_cost_with_snapshot(tracer: &mut EvmTracer, cost: u64, gas_limit: u64, used_gas: u64) {
+	GasometerListener::event(
+		tracer,
+		evm_gasometer::tracing::Event::RecordCost {
+			cost,
+			snapshot: Some(evm_gasometer::Snapshot {
+				gas_limit,
+				memory_gas: 0,
+				used_gas,
+				refunded_gas: 0,
+			}),
+		},
+	);
+}
+
+pub fn record_dynamic_cost(
+	tracer: &mut EvmTracer,
+	gas_cost: u64,
+	memory_gas: u64,
+	old_memory_gas: u64,
+) {
+	GasometerListener::event(
+		tracer,
+		evm_gasometer::tracing::Event::RecordDynamicCost {
+			gas_cost,
+			memory_gas,
+			gas_refund: 0,
+			snapshot: Some(evm_gasometer::Snapshot {
+				gas_limit: 1_000_000,
+				memory_gas: old_memory_gas,
+				used_gas: 0,
+				refunded_gas: 0,
+			}),
+		},
+	);
+}
+
+pub fn sload(tracer: &mut EvmTracer, address: H160, index: H256, value: H256) {
+	RuntimeListener::event(
+		tracer,
+		evm_runtime::tracing::Event::SLoad {
+			address,
+			index,
+			value,
+		},
+	);
+}
+
+pub fn sstore(tracer: &mut EvmTracer, address: H160, index: H256, value: H256) {
+	RuntimeListener::event(
+		tracer,
+		evm_runtime::tracing::Event::SStore {
+			address,
+			index,
+			value,
+		},
+	);
+}
+
+pub fn step(tracer: &mut EvmTracer, opcode: evm::Opcode, pc: usize, stack: &[H256]) {
+	step_with_memory(tracer, opcode, pc, stack, &[]);
+}
+
+pub fn step_with_memory(
+	tracer: &mut EvmTracer,
+	opcode: evm::Opcode,
+	pc: usize,
+	stack: &[H256],
+	memory_data: &[u8],
+) {
+	let context = context(H160::default(), H160::default(), U256::zero());
+	let mut evm_stack = evm::Stack::new(1024);
+	for value in stack {
+		evm_stack.push(*value).expect("stack limit not reached");
+	}
+	let mut memory = evm::Memory::new(usize::MAX);
+	memory
+		.set(0, memory_data, None)
+		.expect("memory limit not reached");
+	RuntimeListener::event(
+		tracer,
+		evm_runtime::tracing::Event::Step {
+			context: &context,
+			opcode,
+			position: &Ok(pc),
+			stack: &evm_stack,
+			memory: &memory,
+		},
+	);
+}
+
+pub fn step_result(tracer: &mut EvmTracer) {
+	RuntimeListener::event(
+		tracer,
+		evm_runtime::tracing::Event::StepResult {
+			result: &Ok(()),
+			return_value: &[],
+		},
+	);
+}
+
+pub fn tracer_events(events: &[Event]) -> Vec<TracerEvent> {
+	events
+		.iter()
+		.filter_map(|event| match event {
+			Event::Tracer(event) => Some(event.clone()),
+			_ => None,
+		})
+		.collect()
+}
+
+/// Execute `code` in a transaction calling the contract deploying it.
+pub fn execute(code: Vec<u8>) -> evm::ExitReason {
+	execute_with_balance(code, 0.into())
+}
+
+/// Execute `code` with the given balance in the executing contract.
+pub fn execute_with_balance(code: Vec<u8>, balance: U256) -> evm::ExitReason {
+	use evm::backend::{MemoryAccount, MemoryBackend, MemoryVicinity};
+	use evm::executor::stack::{MemoryStackState, StackExecutor, StackSubstateMetadata};
+
+	let eoa = H160::repeat_byte(0x01);
+	let contract = H160::repeat_byte(0x02);
+	let config = evm::Config::london();
+	let vicinity = MemoryVicinity {
+		gas_price: 1.into(),
+		origin: eoa,
+		chain_id: 1.into(),
+		// Hash of the block 0.
+		block_hashes: vec![H256::repeat_byte(0xbb)],
+		block_number: 1.into(),
+		block_coinbase: H160::zero(),
+		block_timestamp: 1.into(),
+		block_difficulty: 0.into(),
+		block_gas_limit: 15_000_000.into(),
+		block_base_fee_per_gas: 0.into(),
+	};
+	let mut accounts = std::collections::BTreeMap::new();
+	accounts.insert(
+		contract,
+		MemoryAccount {
+			nonce: 1.into(),
+			balance,
+			storage: Default::default(),
+			code,
+		},
+	);
+	let backend = MemoryBackend::new(&vicinity, accounts);
+	let metadata = StackSubstateMetadata::new(1_000_000, &config);
+	let state = MemoryStackState::new(metadata, &backend);
+	let precompiles = ();
+	let mut executor = StackExecutor::new_with_precompiles(state, &config, &precompiles);
+	let (reason, _) =
+		executor.transact_call(eoa, contract, 0.into(), Vec::new(), 1_000_000, Vec::new());
+	reason
+}
@@ -0,0 +1,1596 @@
+// Copyright 2019-2022 PureStake Inc.
+// This file is part of Moonbeam.
+
+// Moonbeam is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Moonbeam is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
+//! Tracer proxying the events of the EVM to the host, alongside the events it derives from them.
+
+pub mod blob;
+
+pub use crate::buffer::RevertPolicy;
+pub use crate::listener::{RawEvent, Subsystem};
+pub use crate::opcode_filter::OpcodeFilter;
+pub use crate::output::{
+	chain_commitment, BlackholeSink, ByteInterceptor, EventKind, TraceTransform,
+};
+pub use crate::schedule::{base_gas_cost, opcode_name, Hardfork};
+pub use crate::state::Checkpoint;
+pub use crate::summary::{TraceResult, PRECOMPILES};
+
+use crate::config::Config;
+use crate::listener::ListenerProxy;
+use crate::state::{Frame, State};
+use crate::step::selector;
+use crate::summary::calldata_gas;
+use evm_tracing_events::{
+	tracer::{TracerError, TxContext, CALL_STIPEND},
+	Encoding, Event, EvmEvent, GasometerEvent, RuntimeEvent, StepEventFilter, TracerEvent,
+};
+
+use ethereum_types::{H160, H256, U256};
+use evm::tracing::{using as evm_using, EventListener as EvmListener};
+use evm::Opcode;
+use evm_gasometer::tracing::{using as gasometer_using, EventListener as GasometerListener};
+use evm_runtime::tracing::{using as runtime_using, EventListener as RuntimeListener};
+use sp_std::{boxed::Box, cell::RefCell, collections::btree_set::BTreeSet, rc::Rc, vec::Vec};
+
+pub struct EvmTracer {
+	pub(crate) step_event_filter: StepEventFilter,
+	pub(crate) config: Config,
+	pub(crate) transforms: Vec<Box<dyn TraceTransform>>,
+	/// Additional listeners receiving the original events alongside the host.
+	pub(crate) evm_listeners: Vec<Box<dyn EvmListener>>,
+	pub(crate) gasometer_listeners: Vec<Box<dyn GasometerListener>>,
+	pub(crate) runtime_listeners: Vec<Box<dyn RuntimeListener>>,
+	pub(crate) raw_listeners: Vec<Box<dyn FnMut(RawEvent)>>,
+	/// Called with each event delivered, see `with_event_callback`.
+	pub(crate) event_callbacks: Vec<Box<dyn FnMut(&Event)>>,
+	/// If set, root of the hash chain over the events delivered so far, emitted in a
+	/// `TracerEvent::TraceEnd` at the end of the trace.
+	pub(crate) event_commitment: Option<H256>,
+	/// Transaction emitted in a `TracerEvent::Begin` at the start of the trace.
+	pub(crate) tx_context: Option<TxContext>,
+	/// Unknown opcodes already reported in the trace.
+	pub(crate) unknown_opcodes: OpcodeFilter,
+	pub(crate) state: State,
+	/// If set, a `TracerEvent::Sstore` is emitted for each `SSTORE`, with the original value
+	/// of the slot read by this hook.
+	pub(crate) read_storage: Option<Box<dyn Fn(H160, H256) -> H256>>,
+	/// If set, a `TracerEvent::CreatorNonce` is emitted for each `CREATE`, with the nonce of
+	/// the creator read by this hook.
+	pub(crate) read_nonce: Option<Box<dyn Fn(H160) -> U256>>,
+	/// Time of the first step, if the trace is time limited.
+	#[cfg(feature = "std")]
+	pub(crate) started: Option<std::time::Instant>,
+	/// The time limit was exceeded, events are not emitted anymore.
+	pub(crate) timed_out: bool,
+	/// If set, events are buffered until the transaction frame exits, and only sent to the
+	/// host if it reverted or errored.
+	pub(crate) failed_only_buffer: Option<Vec<Event>>,
+	/// Applied to the encoded events before they are passed to the host.
+	pub(crate) byte_interceptor: Option<Box<dyn ByteInterceptor>>,
+	/// Events of the frames currently entered, if `buffers_frames`.
+	pub(crate) frame_buffers: Vec<Vec<Event>>,
+	/// If set, events are kept in memory instead of being sent to the host.
+	pub(crate) captured_events: Option<Vec<Event>>,
+	/// If set, events are encoded and dropped instead of being sent to the host.
+	pub(crate) blackhole: Option<BlackholeSink>,
+}
+
+impl EvmTracer {
+	pub fn new() -> Self {
+		Self {
+			step_event_filter: moonbeam_primitives_ext::moonbeam_ext::step_event_filter(),
+			config: Config::default(),
+			transforms: Vec::new(),
+			evm_listeners: Vec::new(),
+			gasometer_listeners: Vec::new(),
+			runtime_listeners: Vec::new(),
+			raw_listeners: Vec::new(),
+			event_callbacks: Vec::new(),
+			event_commitment: None,
+			tx_context: None,
+			unknown_opcodes: OpcodeFilter::new(),
+			state: State::default(),
+			read_storage: None,
+			read_nonce: None,
+			#[cfg(feature = "std")]
+			started: None,
+			timed_out: false,
+			failed_only_buffer: None,
+			byte_interceptor: None,
+			frame_buffers: Vec::new(),
+			captured_events: None,
+			blackhole: None,
+		}
+	}
+
+	/// Tracer of the given transaction, emitted in a `TracerEvent::Begin` at the start of the
+	/// trace so that it describes the transaction it replays.
+	pub fn new_with_tx(tx: TxContext) -> Self {
+		Self {
+			tx_context: Some(tx),
+			..Self::new()
+		}
+	}
+
+	/// Hardfork of the EVM configuration of the runtime, reported at the start of the trace
+	/// and in the summary. The opcodes it doesn't define yet are reported as unknown.
+	pub fn with_hardfork(mut self, hardfork: Hardfork) -> Self {
+		self.config.hardfork = Some(hardfork);
+		self
+	}
+
+	/// Coinbase of the block of the traced transactions, warm from their start since Shanghai
+	/// (EIP-3651) if the hardfork is given to `with_hardfork`.
+	pub fn with_coinbase(mut self, coinbase: H160) -> Self {
+		self.config.coinbase = Some(coinbase);
+		self
+	}
+
+	/// Append a transform to the pipeline applied to each event before emission.
+	pub fn with_transform<T: TraceTransform + 'static>(mut self, transform: T) -> Self {
+		self.transforms.push(Box::new(transform));
+		self
+	}
+
+	/// Register an additional listener of `evm::tracing::Event`.
+	///
+	/// Listeners are boxed so that registering many listener types doesn't increase the size
+	/// of the runtime through monomorphization.
+	pub fn with_evm_listener(mut self, listener: Box<dyn EvmListener>) -> Self {
+		self.evm_listeners.push(listener);
+		self
+	}
+
+	/// Register an additional listener of `evm_gasometer::tracing::Event`.
+	pub fn with_gasometer_listener(mut self, listener: Box<dyn GasometerListener>) -> Self {
+		self.gasometer_listeners.push(listener);
+		self
+	}
+
+	/// Register an additional listener of `evm_runtime::tracing::Event`.
+	pub fn with_runtime_listener(mut self, listener: Box<dyn RuntimeListener>) -> Self {
+		self.runtime_listeners.push(listener);
+		self
+	}
+
+	/// Register a listener receiving all the events unmodified, as emitted by the `evm`
+	/// crates. Unlike the events sent to the host, no data is lost in a conversion (e.g. the
+	/// stack and memory of steps are always available).
+	pub fn with_raw_listener<L: FnMut(RawEvent) + 'static>(mut self, listener: L) -> Self {
+		self.raw_listeners.push(Box::new(listener));
+		self
+	}
+
+	/// Register a callback called inline with each event, as it is sent to the host (or
+	/// captured), after the transforms and the buffering of the tracer. Unlike a listener it
+	/// receives the converted events.
+	///
+	/// The callback runs while the tracer is borrowed by the `evm` listeners, so it must not
+	/// execute EVM code, which would re-enter the tracer.
+	pub fn with_event_callback<C: FnMut(&Event) + 'static>(mut self, callback: C) -> Self {
+		self.event_callbacks.push(Box::new(callback));
+		self
+	}
+
+	/// Commit to the events of the trace, whose root is emitted in a `TracerEvent::TraceEnd`
+	/// at its end. Consumers recompute it from the received events with `chain_commitment`
+	/// to verify the trace. If the trace times out, the events are not committed to.
+	pub fn with_event_commitment(mut self) -> Self {
+		self.event_commitment = Some(H256::zero());
+		self
+	}
+
+	/// Emit a `TracerEvent::MemoryGrowth` each time an opcode expands the memory.
+	pub fn with_memory_growth(mut self) -> Self {
+		self.config.memory_growth = true;
+		self
+	}
+
+	/// Report `SELFDESTRUCT` with EIP-6780 semantics, deleting only the contracts created in the
+	/// same transaction.
+	pub fn with_eip_6780(mut self) -> Self {
+		self.config.eip_6780 = true;
+		self
+	}
+
+	/// Only emit the step events of the frames executing the code of one of `addresses`.
+	/// Other events are still emitted for all frames, which allows to build the call list
+	/// of the whole transaction while fully tracing only the contracts of interest.
+	pub fn with_deep_trace_addresses(mut self, addresses: BTreeSet<H160>) -> Self {
+		self.config.deep_trace_addresses = Some(addresses);
+		self
+	}
+
+	/// Emit a `TracerEvent::Keccak` with the preimage and output of each `SHA3` opcode.
+	/// Disabled by default due to the amount of events it generates.
+	pub fn with_keccak_preimages(mut self) -> Self {
+		self.config.keccak_preimages = true;
+		self
+	}
+
+	/// Emit a `TracerEvent::ReturnData` for each access to the return data of a subcall by
+	/// `RETURNDATASIZE` or `RETURNDATACOPY`.
+	pub fn with_return_data_accesses(mut self) -> Self {
+		self.config.return_data_accesses = true;
+		self
+	}
+
+	/// Within a frame, only emit the `SLOAD`s of a slot reading a different value than the
+	/// last one emitted for it, such as repeated reads in a loop. The first read of each slot
+	/// and all `SSTORE`s are always emitted, so the accessed slots are still all reported.
+	pub fn with_sload_dedup(mut self) -> Self {
+		self.config.sload_dedup = true;
+		self
+	}
+
+	/// Emit a `TracerEvent::FrameSummary` when exiting each frame, reporting the maximum stack
+	/// depth reached in the frame.
+	pub fn with_frame_summaries(mut self) -> Self {
+		self.config.frame_summaries = true;
+		self
+	}
+
+	/// Only emit a `TracerEvent::Summary` of each transaction, with its used gas and the
+	/// addresses it touched. Step events are not listened to, making it much cheaper than
+	/// other modes when scanning many transactions.
+	pub fn summary_only(mut self) -> Self {
+		self.config.summary_only = true;
+		self
+	}
+
+	/// Only emit a `TracerEvent::Replay` of each transaction, with the inputs the EVM consumed
+	/// that can't be recomputed from the bytecode: the results of `BLOCKHASH`, the outputs
+	/// of the precompiles, the code read by `EXTCODECOPY` and the storage values read. The
+	/// `Event::CallListNew` and `TracerEvent::Begin` starting the trace are still emitted.
+	pub fn replay_capture(mut self) -> Self {
+		self.config.replay_capture = true;
+		self
+	}
+
+	/// Emit a `TracerEvent::GasSample` with the gas remaining in the current frame every `n`
+	/// steps, starting with the first step. Provides a lightweight gas over time series.
+	pub fn with_gas_sampling(mut self, n: u64) -> Self {
+		self.config.gas_sampling = Some(n.max(1));
+		self
+	}
+
+	/// Emit a `TracerEvent::Jumpdest` with the gas used so far in the frame at each `JUMPDEST`,
+	/// to be joined off-chain with the function entries of the contract.
+	pub fn with_jumpdest_gas_tracking(mut self) -> Self {
+		self.config.jumpdest_gas_tracking = true;
+		self
+	}
+
+	/// Encoding of the events sent to the host, SCALE by default.
+	pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+		self.config.encoding = encoding;
+		self
+	}
+
+	/// Emit a `TracerEvent::Reentrancy` when a call enters an address already executing in
+	/// the frame stack. This is a heuristic for security analysis.
+	pub fn with_reentrancy_detection(mut self) -> Self {
+		self.config.reentrancy_detection = true;
+		self
+	}
+
+	/// Emit a `TracerEvent::PrecompileGas` when exiting the frame of a precompile, reporting
+	/// the gas it charged, which no step event accounts for.
+	pub fn with_precompile_gas(mut self) -> Self {
+		self.config.precompile_gas = true;
+		self
+	}
+
+	/// Emit a `TracerEvent::Call` after each call, describing its stipend, selector and
+	/// senders, and a `TracerEvent::FrameStart` once the gas forwarded to a frame is recorded.
+	pub fn with_call_details(mut self) -> Self {
+		self.config.call_details = true;
+		self
+	}
+
+	/// Emit a `TracerEvent::FrameId` after each step and exit event, to group steps by frame
+	/// even across sibling frames.
+	pub fn with_frame_ids(mut self) -> Self {
+		self.config.frame_ids = true;
+		self
+	}
+
+	/// Replace the bytes of the inputs, outputs and memory in the emitted events by zeros,
+	/// keeping their length. The call structure and the gas stay available while the data is
+	/// hidden, unlike with the truncation of the step memory by the step event filter.
+	/// Applied after the transforms.
+	pub fn with_data_redaction(mut self) -> Self {
+		self.config.data_redaction = true;
+		self
+	}
+
+	/// Pass the encoded bytes of each event through `interceptor` before sending them to the
+	/// host. The bytes are passed unmodified by default.
+	pub fn with_byte_interceptor<I: ByteInterceptor + 'static>(mut self, interceptor: I) -> Self {
+		self.byte_interceptor = Some(Box::new(interceptor));
+		self
+	}
+
+	/// Prefix the events sent to the host with the id of their stream, to be demultiplexed from
+	/// the events of other tracers sharing the host. Events of the default `EVM_STREAM_ID`
+	/// are sent unprefixed, those of other streams are always SCALE encoded.
+	pub fn with_stream_id(mut self, stream_id: u16) -> Self {
+		self.config.stream_id = stream_id;
+		self
+	}
+
+	/// Register the listeners of each subsystem in the given order, innermost first, to check
+	/// that the events don't depend on it.
+	#[cfg(test)]
+	pub fn with_listener_order(mut self, order: [Subsystem; 3]) -> Self {
+		self.config.listener_order = order;
+		self
+	}
+
+	/// Only emit the step events of the opcodes in the filter. The other events are not
+	/// affected.
+	pub fn with_opcode_filter<F: Into<OpcodeFilter>>(mut self, filter: F) -> Self {
+		self.config.opcode_filter = Some(filter.into());
+		self
+	}
+
+	/// Emit a `TracerEvent::CalldataRead` for each read of the input, and report which bytes
+	/// of the transaction input were read in the `TraceResult`.
+	pub fn with_calldata_reads(mut self) -> Self {
+		self.config.calldata_reads = true;
+		self
+	}
+
+	/// Emit a `TracerEvent::AccountAccess` for each access to an account by an opcode, and a
+	/// `TracerEvent::StorageAccess` for each `SLOAD` and `SSTORE`, telling whether it was warm
+	/// or cold. The addresses and slots accessed by a frame rolled back are cold again
+	/// afterwards, like in the EVM.
+	pub fn with_account_accesses(mut self) -> Self {
+		self.config.account_accesses = true;
+		self
+	}
+
+	/// Emit a `TracerEvent::GasExplanation` after each gas event, with the pricing rule
+	/// accounting for most of the charge, for tools explaining the costs of the opcodes. The
+	/// rule is decided from the opcode of the step, the value it transfers and whether the
+	/// account or slot it accesses is warm, which the tracer then tracks as with
+	/// `with_account_accesses`. The access costs of EIP-2929 are assumed unless an earlier
+	/// hardfork is given to `with_hardfork`.
+	pub fn with_gas_explanations(mut self) -> Self {
+		self.config.gas_explanations = true;
+		self
+	}
+
+	/// Emit a `TracerEvent::Sstore` with the original and new values for each `SSTORE`, to
+	/// compute state diffs. `read_storage` returns the current value of a slot (e.g. from
+	/// `pallet_evm::AccountStorages`), which is the original one since it is called before the
+	/// EVM writes the slot.
+	pub fn with_sstore_values<R: Fn(H160, H256) -> H256 + 'static>(
+		mut self,
+		read_storage: R,
+	) -> Self {
+		self.read_storage = Some(Box::new(read_storage));
+		self
+	}
+
+	/// Emit a `TracerEvent::CreatorNonce` for each `CREATE`, to verify the address it derives
+	/// from the creator and its nonce. `read_nonce` returns the current nonce of an account
+	/// (e.g. from `pallet_evm::Pallet::account_basic`), which is the one used for the address
+	/// since it is called before the EVM increments it. `CREATE2` addresses don't depend on
+	/// the nonce.
+	pub fn with_creator_nonces<R: Fn(H160) -> U256 + 'static>(mut self, read_nonce: R) -> Self {
+		self.read_nonce = Some(Box::new(read_nonce));
+		self
+	}
+
+	/// Stop emitting events once the trace ran for longer than `limit` since its first step,
+	/// emitting a last `TracerEvent::Timeout`. The clock is only read every
+	/// `with_time_check_interval` steps.
+	#[cfg(feature = "std")]
+	pub fn with_time_limit(mut self, limit: std::time::Duration) -> Self {
+		self.config.time_limit = Some(limit);
+		self
+	}
+
+	/// Amount of steps between two checks of the time limit, 1000 by default.
+	#[cfg(feature = "std")]
+	pub fn with_time_check_interval(mut self, steps: u64) -> Self {
+		self.config.time_check_interval = steps.max(1);
+		self
+	}
+
+	/// Only emit the events of the frames using at least `threshold` gas, including the gas
+	/// forwarded to their subcalls. The events of each frame are buffered until it exits.
+	pub fn with_min_frame_gas(mut self, threshold: u64) -> Self {
+		self.config.min_frame_gas = Some(threshold);
+		self
+	}
+
+	/// Handling of the events of the frames rolled back, `RevertPolicy::Keep` by default. Other
+	/// policies buffer the events of each frame until it exits.
+	pub fn with_revert_policy(mut self, policy: RevertPolicy) -> Self {
+		self.config.revert_policy = policy;
+		self
+	}
+
+	/// Keep the events in memory instead of sending them to the host. They are returned in
+	/// the `TraceResult` by `finish`.
+	pub fn capture_events(mut self) -> Self {
+		self.captured_events = Some(Vec::new());
+		self
+	}
+
+	/// Benchmark mode: run all the processing of the events, including their encoding, but
+	/// drop them instead of sending them to the host, to measure the cost of the tracer apart
+	/// from the cost of the host calls. See `blackhole`.
+	pub fn bench_mode(mut self) -> Self {
+		self.blackhole = Some(BlackholeSink::default());
+		self
+	}
+
+	/// Sink of the events in benchmark mode.
+	pub fn blackhole(&self) -> Option<&BlackholeSink> {
+		self.blackhole.as_ref()
+	}
+
+	/// Only send the events of the trace to the host if the transaction frame reverted or
+	/// errored. Events are buffered until the transaction frame exits, and discarded if it
+	/// succeeded.
+	pub fn only_if_failed(mut self) -> Self {
+		self.failed_only_buffer = Some(Vec::new());
+		self
+	}
+
+	/// Capture the structural state of the tracer (entered frames and pending data).
+	///
+	/// Allows speculative execution: if the executed branch is discarded, `restore` brings
+	/// back the tracer to the state it had before the branch. Events already emitted are
+	/// not affected.
+	pub fn checkpoint(&self) -> Checkpoint {
+		Checkpoint(self.state.clone())
+	}
+
+	/// Restore the structural state captured by `checkpoint`.
+	pub fn restore(&mut self, checkpoint: Checkpoint) {
+		self.state = checkpoint.0;
+	}
+
+	/// Setup event listeners and execute provided closure, returning its return value.
+	///
+	/// An `Event::CallListNew` marker is emitted before any event of the trace, except in
+	/// summary mode. Since the tracer is consumed it is emitted exactly once per trace. It is
+	/// followed by the `TracerEvent::Begin` of the transaction, if known.
+	pub fn trace<R, F: FnOnce() -> R>(self, f: F) -> R {
+		self.trace_with_tracer(f).1
+	}
+
+	/// Like `trace`, also returning the result of the trace, see `finish`.
+	pub fn trace_with_result<R, F: FnOnce() -> R>(self, f: F) -> (R, TraceResult) {
+		let (tracer, result) = self.trace_with_tracer(f);
+		(result, tracer.finish())
+	}
+
+	/// Like `trace`, also returning the tracer, to be inspected or finalized with `finish`.
+	pub fn trace_with_tracer<R, F: FnOnce() -> R>(mut self, f: F) -> (Self, R) {
+		let summary_only = self.config.summary_only;
+		if !summary_only {
+			self.emit(Event::CallListNew());
+			if let Some(tx) = self.tx_context.clone() {
+				self.emit(Event::Tracer(TracerEvent::Begin(tx)));
+			}
+			if let Some(hardfork) = self.config.hardfork {
+				self.emit(Event::Tracer(TracerEvent::Hardfork(hardfork)));
+			}
+		}
+
+		let wrapped = Rc::new(RefCell::new(self));
+
+		let listener_order = wrapped.borrow().config.listener_order;
+		let mut f: Box<dyn FnOnce() -> R + '_> = Box::new(f);
+
+		// Each subsystem wraps the previous `f` into a `using` call, the first one being the
+		// innermost. Listening to new events results in adding one new subsystem.
+		// Order is irrelevant when registering listeners.
+		for subsystem in listener_order {
+			let mut proxy = ListenerProxy(Rc::clone(&wrapped));
+			f = match subsystem {
+				// Summary mode doesn't need step events, which are the most frequent ones.
+				Subsystem::Runtime if summary_only => f,
+				Subsystem::Runtime => Box::new(move || runtime_using(&mut proxy, f)),
+				Subsystem::Gasometer => Box::new(move || gasometer_using(&mut proxy, f)),
+				Subsystem::Evm => Box::new(move || evm_using(&mut proxy, f)),
+			};
+		}
+		// The proxies are dropped with the closures.
+		let result = f();
+
+		let mut tracer = match Rc::try_unwrap(wrapped) {
+			Ok(tracer) => tracer.into_inner(),
+			// A proxy kept by the environment past the closure would get a fresh tracer, rather
+			// than making the runtime panic.
+			Err(wrapped) => wrapped.replace(EvmTracer::new()),
+		};
+
+		// Events of a transaction frame that never exited are not sent.
+		if let Some(buffer) = &mut tracer.failed_only_buffer {
+			buffer.clear();
+		}
+		if let Some(commitment) = tracer.event_commitment {
+			tracer.deliver(Event::Tracer(TracerEvent::TraceEnd { commitment }));
+		}
+		(tracer, result)
+	}
+
+	/// Finalize the tracer, returning the result of the last transaction it traced.
+	pub fn finish(self) -> TraceResult {
+		let summary = self.state.summary;
+		TraceResult {
+			tx: self.tx_context,
+			hardfork: self.config.hardfork,
+			gas_used: summary.gas_used(),
+			intrinsic_gas: summary.transaction_cost,
+			gas_breakdown: summary.gas_breakdown(),
+			touched: summary.touched.into_iter().collect(),
+			tx_succeeded: summary.failed.map(|failed| !failed),
+			top_message_reverted: summary.top_message_reverted,
+			inner_messages_reverted: summary.inner_messages_reverted,
+			events_discarded: self.failed_only_buffer.is_some() && summary.failed == Some(false),
+			calldata_coverage: summary.calldata_coverage,
+			accessed: summary
+				.accessed
+				.into_iter()
+				.map(|(address, slots)| (address, slots.into_iter().collect()))
+				.collect(),
+			warm_addresses: summary.warm.into_iter().collect(),
+			events: self.captured_events.unwrap_or_default(),
+		}
+	}
+
+	/// Emit a `TracerEvent::Marker` with the label, to correlate runtime code (e.g. a
+	/// precompile) with the EVM events around it. Can be called from anywhere during a trace.
+	/// The marker is sent to the host immediately, so it is neither buffered nor captured
+	/// with the events of the tracer.
+	pub fn emit_marker(label: &str) {
+		moonbeam_primitives_ext::moonbeam_ext::trace_marker(label.as_bytes().to_vec());
+	}
+
+	#[deprecated(note = "`trace` now emits the new call list marker itself")]
+	pub fn emit_new() {
+		moonbeam_primitives_ext::moonbeam_ext::call_list_new();
+	}
+
+	/// Stops the emission if the time limit is exceeded at the step, which is only checked
+	/// every `time_check_interval` steps.
+	#[cfg(feature = "std")]
+	fn check_time_limit(&mut self, step_index: u64) {
+		let limit = match self.config.time_limit {
+			Some(limit) if !self.timed_out && step_index % self.config.time_check_interval == 0 => {
+				limit
+			}
+			_ => return,
+		};
+		let elapsed = self
+			.started
+			.get_or_insert_with(std::time::Instant::now)
+			.elapsed();
+		if elapsed > limit {
+			self.deliver(Event::Tracer(TracerEvent::Error(
+				TracerError::BudgetExceeded,
+			)));
+			self.deliver(Event::Tracer(TracerEvent::Timeout {
+				elapsed: elapsed.as_millis() as u64,
+			}));
+			self.timed_out = true;
+		}
+	}
+
+	/// Since Shanghai the coinbase is warm from the start of the transaction (EIP-3651).
+	fn warm_coinbase(&mut self) {
+		match (self.config.coinbase, self.config.hardfork) {
+			(Some(coinbase), Some(hardfork)) if hardfork >= Hardfork::Shanghai => {
+				self.state.summary.warm.insert(coinbase);
+			}
+			_ => (),
+		}
+	}
+
+	/// Opens the frame of a transaction whose input is `input`.
+	fn begin_transact(&mut self, frame: Frame, input: &[u8]) {
+		self.begin_transaction(frame);
+		self.warm_coinbase();
+		self.state.summary.calldata_gas = calldata_gas(input);
+	}
+
+	/// Enters the frame of a call, returning the `TracerEvent::Call` describing it.
+	fn enter_call(
+		&mut self,
+		code_address: H160,
+		transfer: &Option<evm::Transfer>,
+		input: &[u8],
+		context: &evm::Context,
+		effects: &mut Effects,
+	) -> TracerEvent {
+		let is_transaction_frame = self.enter_frame(code_address, false);
+		if let Some((entered, parents)) = self.state.frames.split_last_mut() {
+			entered.address = context.address;
+			if self.config.replay_capture {
+				entered.input = input.to_vec();
+			}
+			let original_depth = parents
+				.iter()
+				.position(|frame| frame.address == context.address);
+			match original_depth {
+				Some(original_depth) if self.config.reentrancy_detection => {
+					effects.extra_events.push(TracerEvent::Reentrancy {
+						address: context.address,
+						original_depth: original_depth as u64,
+						reentry_depth: parents.len() as u64,
+					});
+				}
+				_ => (),
+			}
+		}
+		let target_existed = if is_transaction_frame {
+			None
+		} else {
+			self.state.pending_target_existed.take()
+		};
+		// The EVM adds the stipend to value-bearing subcalls only.
+		let stipend = match transfer {
+			Some(transfer) if !is_transaction_frame && !transfer.value.is_zero() => {
+				Some(CALL_STIPEND)
+			}
+			_ => None,
+		};
+		self.state.pending_frame_start = Some(stipend.unwrap_or(0));
+		let frames = &self.state.frames;
+		let immediate_caller = match frames.len().checked_sub(2) {
+			Some(parent) => frames[parent].code_address,
+			None => context.caller,
+		};
+		TracerEvent::Call {
+			stipend,
+			selector: selector(input),
+			originating_precompile: frames.last().and_then(|frame| frame.originating_precompile),
+			msg_sender: context.caller,
+			immediate_caller,
+			target_existed,
+			new_account_cost_charged: target_existed == Some(false),
+		}
+	}
+
+	/// Enters the frame of a contract creation, returning the `TracerEvent::CreatorNonce` of
+	/// `with_creator_nonces`.
+	fn enter_create(&mut self, address: H160, scheme: &evm::CreateScheme) -> Option<TracerEvent> {
+		self.enter_frame(address, true);
+		self.state.created.insert(address);
+		if self.config.account_accesses {
+			self.state.access_address(address);
+		}
+		self.state.pending_frame_start = Some(0);
+		match scheme {
+			evm::CreateScheme::Legacy { caller } if self.read_nonce.is_some() => {
+				Some(match self.creator_nonce(*caller) {
+					Ok(creator_nonce) => TracerEvent::CreatorNonce { creator_nonce },
+					Err(error) => TracerEvent::Error(error),
+				})
+			}
+			_ => None,
+		}
+	}
+
+	/// Exits the current frame, returning the `TracerEvent::FrameSummary` of
+	/// `with_frame_summaries`.
+	fn exit_frame(
+		&mut self,
+		reason: &evm::ExitReason,
+		return_value: &[u8],
+		effects: &mut Effects,
+	) -> Option<TracerEvent> {
+		let succeeded = matches!(reason, evm::ExitReason::Succeed(_));
+		let reverted = matches!(reason, evm::ExitReason::Revert(_));
+		let exited = self.state.frames.pop();
+		// Accesses of a frame rolled back are rolled back with it.
+		match (&exited, self.state.frames.last_mut()) {
+			(Some(frame), Some(parent)) if succeeded => {
+				parent.warmed.extend(&frame.warmed);
+				parent.warmed_slots.extend(&frame.warmed_slots);
+			}
+			(Some(frame), _) if !succeeded => {
+				for address in &frame.warmed {
+					self.state.warmed.remove(address);
+				}
+				for slot in &frame.warmed_slots {
+					self.state.warmed_slots.remove(slot);
+				}
+			}
+			_ => (),
+		}
+		effects.exited_frame_id = exited.as_ref().map(|frame| frame.id);
+		effects.exited_frame = exited.as_ref().map(|frame| {
+			(
+				frame.gas_at_start.saturating_sub(frame.gas_remaining),
+				succeeded,
+			)
+		});
+		let summary = match &exited {
+			Some(frame) if self.config.frame_summaries => Some(TracerEvent::FrameSummary {
+				max_stack_depth: frame.max_stack_depth,
+			}),
+			_ => None,
+		};
+		match (reason, &exited) {
+			(evm::ExitReason::Error(evm::ExitError::CallTooDeep), _) => {
+				effects.extra_events.push(TracerEvent::DepthLimit {
+					attempted_depth: self.state.frames.len() as u64,
+				});
+			}
+			(evm::ExitReason::Error(evm::ExitError::CreateCollision), Some(frame))
+				if frame.is_create =>
+			{
+				effects.extra_events.push(TracerEvent::CreateCollision {
+					address: frame.code_address,
+				});
+			}
+			_ => (),
+		}
+		// Precompiles charge gas in their frame without executing any step.
+		match &exited {
+			Some(frame) if self.config.precompile_gas && !frame.executed => {
+				let gas_used = frame.gas_at_start.saturating_sub(frame.gas_remaining);
+				if gas_used > 0 {
+					effects.extra_events.push(TracerEvent::PrecompileGas {
+						address: frame.code_address,
+						gas_used,
+					});
+				}
+			}
+			_ => (),
+		}
+		match &exited {
+			Some(frame) if self.config.replay_capture => {
+				self.replay_precompile_output(frame, return_value);
+			}
+			_ => (),
+		}
+		match self.state.frames.last_mut() {
+			Some(parent) => {
+				// A successful creation returns the deployed code, which is not available as
+				// return data.
+				let is_create = exited.map_or(false, |frame| frame.is_create);
+				parent.return_data_len = if is_create && succeeded {
+					0
+				} else {
+					return_value.len() as u64
+				};
+				if reverted {
+					self.state.summary.inner_messages_reverted += 1;
+				}
+			}
+			None => {
+				effects.transaction_failed = Some(!succeeded);
+				self.state.summary.top_message_reverted = reverted;
+			}
+		}
+		// A `SHA3`, `BLOCKHASH` or `EXTCODECOPY` exiting the frame failed.
+		self.state.pending_keccak = None;
+		self.state.pending_block_hash = None;
+		self.state.pending_code_copy = None;
+		// The frame exited before its gas was forwarded (e.g. call too deep).
+		self.state.pending_frame_start = None;
+		summary
+	}
+
+	/// Handles a step, emitting the events of the previous step only available at this point.
+	/// Returns the tracer events to emit after the step event.
+	fn enter_step(
+		&mut self,
+		opcode: Opcode,
+		position: &Result<usize, evm::ExitReason>,
+		stack: &evm::Stack,
+		memory: &evm::Memory,
+	) -> Vec<TracerEvent> {
+		let mut tracer_events = Vec::new();
+		if let Ok(position) = position {
+			self.state.pc = *position as u64;
+		}
+		self.state.opcode = Some(opcode);
+		self.state.step_transfers_value = matches!(opcode, Opcode::CALL | Opcode::CALLCODE)
+			&& stack.peek(2).map_or(false, |value| !value.is_zero());
+		self.state.pending_value_call = opcode == Opcode::CALL && self.state.step_transfers_value;
+		self.state.pending_target_existed = None;
+		self.state.step_filtered_out = self
+			.config
+			.opcode_filter
+			.map_or(false, |filter| !filter.contains(opcode.0));
+		if let Some(frame) = self.state.frames.last_mut() {
+			frame.executed = true;
+			frame.max_stack_depth = frame.max_stack_depth.max(stack.data().len() as u64);
+		}
+
+		if let Some(keccak) = self.keccak_output(stack, memory) {
+			self.emit(Event::Tracer(keccak));
+		}
+		self.replay_step_output(stack, memory);
+
+		if self.config.return_data_accesses {
+			tracer_events.extend(self.return_data_access(opcode, stack));
+		}
+		if self.config.calldata_reads {
+			tracer_events.extend(self.calldata_read(opcode, stack));
+		}
+		// The gas explanations price the accesses depending on whether they are warm.
+		if self.config.account_accesses || self.config.gas_explanations {
+			let access = self
+				.account_access(opcode, stack)
+				.or_else(|| self.storage_access(opcode, stack));
+			self.state.step_cold_access = match access {
+				Some(TracerEvent::AccountAccess { cold, .. })
+				| Some(TracerEvent::StorageAccess { cold, .. }) => Some(cold),
+				_ => None,
+			};
+			if self.config.account_accesses {
+				tracer_events.extend(access);
+			}
+		}
+
+		let name = match self.config.hardfork {
+			Some(hardfork) => opcode_name(opcode.0, hardfork),
+			None => evm_tracing_events::opcode_name(opcode),
+		};
+		if name.is_none() && !self.unknown_opcodes.contains(opcode.0) {
+			self.unknown_opcodes.insert(opcode.0);
+			tracer_events.push(TracerEvent::UnknownOpcode {
+				pc: self.state.pc,
+				opcode: opcode.0,
+			});
+		}
+
+		let step_index = self.state.step_index;
+		self.state.step_index += 1;
+		#[cfg(feature = "std")]
+		self.check_time_limit(step_index);
+		tracer_events.extend(self.step_gas_events(opcode, step_index));
+
+		if self.config.keccak_preimages {
+			tracer_events.extend(self.keccak_input(opcode, stack));
+		}
+		if self.config.replay_capture {
+			tracer_events.extend(self.replay_step_input(opcode, stack));
+		}
+		tracer_events
+	}
+}
+
+/// Consequences of an `evm::tracing::Event` handled once the event is emitted.
+#[derive(Default)]
+struct Effects {
+	/// Result of the transaction when its frame exits.
+	transaction_failed: Option<bool>,
+	/// Tracer events emitted after the one derived from the event.
+	extra_events: Vec<TracerEvent>,
+	/// Gas used by the frame exited by the event, and whether it succeeded.
+	exited_frame: Option<(u64, bool)>,
+	/// Id of the frame exited by the event.
+	exited_frame_id: Option<u64>,
+}
+
+impl EvmListener for EvmTracer {
+	/// Proxies `evm::tracing::Event` to the host.
+	fn event(&mut self, event: evm::tracing::Event) {
+		for listener in self.evm_listeners.iter_mut() {
+			listener.event(event);
+		}
+		for listener in self.raw_listeners.iter_mut() {
+			listener(RawEvent::Evm(event));
+		}
+
+		let mut effects = Effects::default();
+		let tracer_event = match &event {
+			evm::tracing::Event::TransactCall { address, data, .. } => {
+				self.begin_transact(Frame::new(*address, None), data);
+				if self.config.calldata_reads {
+					let summary = &mut self.state.summary;
+					summary.calldata_len = data.len();
+					summary.calldata_coverage.resize((data.len() + 7) / 8, 0);
+				}
+				None
+			}
+			evm::tracing::Event::TransactCreate {
+				address, init_code, ..
+			}
+			| evm::tracing::Event::TransactCreate2 {
+				address, init_code, ..
+			} => {
+				let mut frame = Frame::new(*address, None);
+				frame.is_create = true;
+				self.begin_transact(frame, init_code);
+				self.state.created.insert(*address);
+				None
+			}
+			evm::tracing::Event::Call {
+				code_address,
+				transfer,
+				input,
+				context,
+				..
+			} => Some(self.enter_call(*code_address, transfer, input, context, &mut effects)),
+			evm::tracing::Event::Create {
+				address, scheme, ..
+			} => self.enter_create(*address, scheme),
+			evm::tracing::Event::Suicide {
+				address,
+				target,
+				balance,
+			} => Some(TracerEvent::Selfdestruct {
+				contract: *address,
+				beneficiary: *target,
+				balance: *balance,
+				deleted: !self.config.eip_6780 || self.state.created.contains(address),
+			}),
+			evm::tracing::Event::Exit {
+				reason,
+				return_value,
+			} => self.exit_frame(reason, return_value, &mut effects),
+			_ => None,
+		};
+		let transaction_failed = effects.transaction_failed;
+
+		let summary = &mut self.state.summary;
+		summary.touch(&event);
+		if transaction_failed.is_some() {
+			summary.failed = transaction_failed;
+		}
+
+		if self.config.summary_only {
+			if transaction_failed.is_some() {
+				let summary = &self.state.summary;
+				let gas_used = summary.gas_used();
+				let touched = summary.touched.iter().copied().collect();
+				let gas_breakdown = summary.gas_breakdown();
+				let hardfork = self.config.hardfork;
+				self.emit(Event::Tracer(TracerEvent::Summary {
+					gas_used,
+					touched,
+					gas_breakdown,
+					hardfork,
+				}));
+			}
+		} else {
+			let event: EvmEvent = event.into();
+			self.emit(Event::Evm(event));
+			match effects.exited_frame_id {
+				Some(frame_id) if self.config.frame_ids => {
+					self.emit(Event::Tracer(TracerEvent::FrameId { frame_id }));
+				}
+				_ => (),
+			}
+
+			match tracer_event {
+				Some(TracerEvent::Call { .. }) if !self.config.call_details => (),
+				Some(tracer_event) => self.emit(Event::Tracer(tracer_event)),
+				None => (),
+			}
+			for tracer_event in effects.extra_events {
+				self.emit(Event::Tracer(tracer_event));
+			}
+		}
+
+		if let Some((gas_used, succeeded)) = effects.exited_frame {
+			self.close_frame_buffer(gas_used, succeeded);
+		}
+		if transaction_failed.is_some() && self.config.replay_capture {
+			let record = sp_std::mem::take(&mut self.state.replay);
+			self.emit(Event::Tracer(TracerEvent::Replay(record)));
+		}
+		if let Some(failed) = transaction_failed {
+			self.flush_failed_only_buffer(failed);
+		}
+	}
+}
+
+impl GasometerListener for EvmTracer {
+	/// Proxies `evm_gasometer::tracing::Event` to the host.
+	fn event(&mut self, event: evm_gasometer::tracing::Event) {
+		for listener in self.gasometer_listeners.iter_mut() {
+			listener.event(event);
+		}
+		for listener in self.raw_listeners.iter_mut() {
+			listener(RawEvent::Gasometer(event));
+		}
+
+		let event: GasometerEvent = event.into();
+		let frame_start = self.record_frame_gas(&event);
+
+		// Depth of the frame whose gasometer recorded the event.
+		let depth = self.state.frames.len() - frame_start.is_some() as usize;
+		// Subcalls are accounted in the gasometer of the transaction frame.
+		if depth == 1 {
+			self.state.summary.record_gas(&event);
+		}
+		if self.config.summary_only {
+			return;
+		}
+
+		let memory_growth = if self.config.memory_growth {
+			self.memory_growth(&event)
+		} else {
+			None
+		};
+		let explanation = if self.config.gas_explanations {
+			Some(self.gas_rule(&event, frame_start.is_some()))
+		} else {
+			None
+		};
+
+		self.emit(Event::Gasometer(event));
+
+		if let Some(rule) = explanation {
+			self.emit(Event::Tracer(TracerEvent::GasExplanation { rule }));
+		}
+		let frame_start = frame_start.filter(|_| self.config.call_details);
+		if let Some(tracer_event) = memory_growth.or(frame_start) {
+			self.emit(Event::Tracer(tracer_event));
+		}
+	}
+}
+
+impl RuntimeListener for EvmTracer {
+	/// Proxies `evm_runtime::tracing::Event` to the host.
+	fn event(&mut self, event: evm_runtime::tracing::Event) {
+		for listener in self.runtime_listeners.iter_mut() {
+			listener.event(event);
+		}
+		for listener in self.raw_listeners.iter_mut() {
+			listener(RawEvent::Runtime(event));
+		}
+
+		if self.config.summary_only {
+			return;
+		}
+
+		// Tracer events emitted after the runtime event.
+		let mut tracer_events = match &event {
+			evm_runtime::tracing::Event::Step {
+				opcode,
+				position,
+				stack,
+				memory,
+				..
+			} => self.enter_step(*opcode, position, stack, memory),
+			_ => Vec::new(),
+		};
+
+		self.state.summary.record_access(&event);
+		if self.config.replay_capture {
+			self.replay_storage(&event);
+		}
+		if self.read_storage.is_some() {
+			tracer_events.extend(self.sstore_values(&event));
+		}
+
+		if self.config.sload_dedup && self.is_duplicate_sload(&event) {
+			return;
+		}
+
+		// Results of steps that don't trap or exit are only relevant alongside their step.
+		let is_step = matches!(
+			event,
+			evm_runtime::tracing::Event::Step { .. }
+				| evm_runtime::tracing::Event::StepResult { result: Ok(_), .. }
+		);
+		if !is_step || (self.is_deep_traced() && !self.state.step_filtered_out) {
+			let is_step_event = matches!(event, evm_runtime::tracing::Event::Step { .. });
+			let event = RuntimeEvent::from_evm_event(event, self.step_event_filter);
+			self.emit(Event::Runtime(event));
+			match self.state.frames.last() {
+				Some(frame) if self.config.frame_ids && is_step_event => {
+					let frame_id = frame.id;
+					self.emit(Event::Tracer(TracerEvent::FrameId { frame_id }));
+				}
+				_ => (),
+			}
+		}
+
+		for tracer_event in tracer_events {
+			self.emit(Event::Tracer(tracer_event));
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::*;
+
+	#[test]
+	fn time_limit_stops_emission() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+
+		let events = trace_events_with(
+			|tracer| {
+				tracer
+					.with_time_limit(std::time::Duration::from_millis(5))
+					.with_time_check_interval(2)
+					// Slow sink of the events.
+					.with_raw_listener(|_| std::thread::sleep(std::time::Duration::from_millis(2)))
+			},
+			|tracer| {
+				transact_call(tracer, eoa, contract, 0.into());
+				call(tracer, eoa, contract, 0.into(), &[], None);
+				for pc in 0..20 {
+					step(tracer, evm::Opcode::JUMPDEST, pc, &[]);
+				}
+				exit(tracer);
+			},
+		);
+
+		let timeout = events
+			.iter()
+			.position(|event| matches!(event, Event::Tracer(TracerEvent::Timeout { .. })))
+			.expect("the trace times out");
+		assert_eq!(timeout, events.len() - 1);
+		assert_eq!(
+			events[timeout - 1],
+			Event::Tracer(TracerEvent::Error(TracerError::BudgetExceeded))
+		);
+		match &events[timeout] {
+			Event::Tracer(TracerEvent::Timeout { elapsed }) => assert!(*elapsed >= 5),
+			_ => unreachable!(),
+		}
+		let steps = events
+			.iter()
+			.filter(|event| matches!(event, Event::Runtime(RuntimeEvent::Step { .. })))
+			.count();
+		assert!(steps < 20);
+	}
+
+	#[test]
+	fn markers_are_interleaved_with_evm_events() {
+		// PUSH1 0x01 PUSH1 0x00 SSTORE STOP
+		let code = vec![0x60, 0x01, 0x60, 0x00, 0x55, 0x00];
+		let marker = |label: &[u8]| {
+			Event::Tracer(TracerEvent::Marker {
+				label: label.to_vec(),
+			})
+		};
+
+		let events = host_events(|| {
+			EvmTracer::new().trace(|| {
+				EvmTracer::emit_marker("before");
+				execute(code);
+				EvmTracer::emit_marker("after");
+			});
+		});
+
+		assert_eq!(events[1], marker(b"before"));
+		assert!(matches!(
+			events[2],
+			Event::Evm(EvmEvent::TransactCall { .. })
+		));
+		let last_exit = events
+			.iter()
+			.rposition(|event| matches!(event, Event::Evm(EvmEvent::Exit { .. })))
+			.unwrap();
+		assert_eq!(&events[last_exit + 1..], &[marker(b"after")]);
+	}
+
+	#[test]
+	fn tx_context_is_emitted_after_call_list_new() {
+		let tx = TxContext {
+			caller: H160::repeat_byte(0x01),
+			to: Some(H160::repeat_byte(0x02)),
+			value: 3.into(),
+			gas_limit: 100_000,
+			nonce: 4.into(),
+		};
+
+		let events = host_events(|| {
+			EvmTracer::new_with_tx(tx.clone()).trace(|| ());
+		});
+
+		assert_eq!(
+			events,
+			vec![Event::CallListNew(), Event::Tracer(TracerEvent::Begin(tx))]
+		);
+	}
+
+	#[test]
+	fn call_list_new_precedes_trace_events() {
+		let cost = GasometerEvent::RecordTransaction {
+			cost: 21_000,
+			snapshot: Default::default(),
+		};
+
+		let events = host_events(|| {
+			EvmTracer::new()
+				.trace(|| moonbeam_primitives_ext::moonbeam_ext::gasometer_event(cost.encode()));
+		});
+
+		assert_eq!(events, vec![Event::CallListNew(), Event::Gasometer(cost)]);
+	}
+
+	#[test]
+	fn selfdestruct_reports_beneficiary() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let created = H160::repeat_byte(0x03);
+		let beneficiary = H160::repeat_byte(0x04);
+		let trace = |tracer: &mut EvmTracer| {
+			transact_call(tracer, eoa, contract, 0.into());
+			call(tracer, eoa, contract, 0.into(), &[], None);
+			create(tracer, contract, created);
+			suicide(tracer, created, beneficiary, 5.into());
+			exit(tracer);
+			suicide(tracer, contract, beneficiary, 7.into());
+			exit(tracer);
+		};
+		let selfdestructs = |events: Vec<Event>| -> Vec<TracerEvent> {
+			tracer_events(&events)
+				.into_iter()
+				.filter(|event| matches!(event, TracerEvent::Selfdestruct { .. }))
+				.collect()
+		};
+
+		assert_eq!(
+			selfdestructs(trace_events(trace)),
+			vec![
+				TracerEvent::Selfdestruct {
+					contract: created,
+					beneficiary,
+					balance: 5.into(),
+					deleted: true,
+				},
+				TracerEvent::Selfdestruct {
+					contract,
+					beneficiary,
+					balance: 7.into(),
+					deleted: true,
+				},
+			]
+		);
+		// Only the contract created in the transaction is deleted with EIP-6780.
+		assert_eq!(
+			selfdestructs(trace_events_with(|tracer| tracer.with_eip_6780(), trace)),
+			vec![
+				TracerEvent::Selfdestruct {
+					contract: created,
+					beneficiary,
+					balance: 5.into(),
+					deleted: true,
+				},
+				TracerEvent::Selfdestruct {
+					contract,
+					beneficiary,
+					balance: 7.into(),
+					deleted: false,
+				},
+			]
+		);
+	}
+
+	#[test]
+	fn precompile_subcalls_report_originating_precompile() {
+		let eoa = H160::repeat_byte(0x01);
+		let batch = H160::from_low_u64_be(2056);
+		let contract = H160::repeat_byte(0x02);
+		let callee = H160::repeat_byte(0x03);
+
+		let events = trace_events_with(
+			|tracer| tracer.with_call_details(),
+			|tracer| {
+				transact_call(tracer, eoa, batch, 0.into());
+				call(tracer, eoa, batch, 0.into(), &[], None);
+				// The precompile calls a contract without executing any step.
+				call(tracer, batch, contract, 0.into(), &[], None);
+				step(tracer, evm::Opcode::CALL, 0, &[]);
+				call(tracer, contract, callee, 0.into(), &[], None);
+				exit(tracer);
+				exit(tracer);
+				exit(tracer);
+			},
+		);
+
+		let origins: Vec<_> = tracer_events(&events)
+			.into_iter()
+			.filter_map(|event| match event {
+				TracerEvent::Call {
+					originating_precompile,
+					..
+				} => Some(originating_precompile),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(origins, vec![None, Some(batch), Some(batch)]);
+	}
+
+	#[test]
+	fn frame_summaries_report_max_stack_depth() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let stack = |size: usize| vec![H256::zero(); size];
+
+		// The contract calls itself recursively, using less stack at each level.
+		let events = trace_events_with(
+			|tracer| tracer.with_frame_summaries(),
+			|tracer| {
+				transact_call(tracer, eoa, contract, 0.into());
+				call(tracer, eoa, contract, 0.into(), &[], None);
+				for depth in [8, 5, 2] {
+					step(tracer, evm::Opcode::PUSH1, 0, &stack(depth - 1));
+					step(tracer, evm::Opcode::CALL, 2, &stack(depth));
+					call(tracer, contract, contract, 0.into(), &[], None);
+				}
+				step(tracer, evm::Opcode::STOP, 0, &[]);
+				for _ in 0..4 {
+					exit(tracer);
+				}
+			},
+		);
+
+		let depths: Vec<_> = tracer_events(&events)
+			.into_iter()
+			.filter_map(|event| match event {
+				TracerEvent::FrameSummary { max_stack_depth } => Some(max_stack_depth),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(depths, vec![0, 2, 5, 8]);
+	}
+
+	#[test]
+	fn reentrancy_reports_depths() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let callee = H160::repeat_byte(0x03);
+
+		let events = trace_events_with(
+			|tracer| tracer.with_reentrancy_detection(),
+			|tracer| {
+				transact_call(tracer, eoa, contract, 0.into());
+				call(tracer, eoa, contract, 0.into(), &[], None);
+				call(tracer, contract, callee, 0.into(), &[], None);
+				// The callee calls back into the contract, which calls itself.
+				call(tracer, callee, contract, 0.into(), &[], None);
+				call(tracer, contract, contract, 0.into(), &[], None);
+				exit(tracer);
+				exit(tracer);
+				exit(tracer);
+				exit(tracer);
+			},
+		);
+
+		let reentrancies: Vec<_> = tracer_events(&events)
+			.into_iter()
+			.filter(|event| matches!(event, TracerEvent::Reentrancy { .. }))
+			.collect();
+		assert_eq!(
+			reentrancies,
+			vec![
+				TracerEvent::Reentrancy {
+					address: contract,
+					original_depth: 0,
+					reentry_depth: 2,
+				},
+				TracerEvent::Reentrancy {
+					address: contract,
+					original_depth: 0,
+					reentry_depth: 3,
+				},
+			]
+		);
+	}
+
+	#[test]
+	fn create_collision_is_reported() {
+		// Deploys twice an empty contract with the same salt:
+		// PUSH1 0x00 (salt) PUSH1 0x00 (size) PUSH1 0x00 (offset) PUSH1 0x00 (value) CREATE2 POP
+		let create2 = [0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0xf5, 0x50];
+		let mut code = create2.to_vec();
+		code.extend_from_slice(&create2);
+		code.push(0x00);
+
+		let events = host_events(|| {
+			EvmTracer::new().trace(|| execute(code));
+		});
+
+		let created: Vec<_> = events
+			.iter()
+			.filter_map(|event| match event {
+				Event::Evm(EvmEvent::Create { address, .. }) => Some(*address),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(created.len(), 2);
+		assert_eq!(created[0], created[1]);
+		let collisions: Vec<_> = tracer_events(&events)
+			.into_iter()
+			.filter(|event| matches!(event, TracerEvent::CreateCollision { .. }))
+			.collect();
+		assert_eq!(
+			collisions,
+			vec![TracerEvent::CreateCollision {
+				address: created[1]
+			}]
+		);
+	}
+
+	#[test]
+	fn depth_limit_is_reported() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+
+		let events = trace_events(|tracer| {
+			transact_call(tracer, eoa, contract, 0.into());
+			call(tracer, eoa, contract, 0.into(), &[], None);
+			// The contract recurses until the call depth limit.
+			for _ in 0..1024 {
+				step(tracer, evm::Opcode::CALL, 0, &[]);
+				call(tracer, contract, contract, 0.into(), &[], None);
+			}
+			step(tracer, evm::Opcode::CALL, 0, &[]);
+			call(tracer, contract, contract, 0.into(), &[], None);
+			exit_with(
+				tracer,
+				evm::ExitReason::Error(evm::ExitError::CallTooDeep),
+				&[],
+			);
+			// The other frames revert.
+			for _ in 0..=1024 {
+				exit_with(
+					tracer,
+					evm::ExitReason::Revert(evm::ExitRevert::Reverted),
+					&[],
+				);
+			}
+		});
+
+		let depth_limits: Vec<_> = tracer_events(&events)
+			.into_iter()
+			.filter(|event| matches!(event, TracerEvent::DepthLimit { .. }))
+			.collect();
+		assert_eq!(
+			depth_limits,
+			vec![TracerEvent::DepthLimit {
+				attempted_depth: 1025
+			}]
+		);
+	}
+
+	#[test]
+	fn unknown_opcodes_are_reported_once() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+
+		let events = trace_events(|tracer| {
+			transact_call(tracer, eoa, contract, 0.into());
+			call(tracer, eoa, contract, 0.into(), &[], None);
+			step(tracer, evm::Opcode(0x0c), 0, &[]);
+			step(tracer, evm::Opcode::ADD, 1, &[]);
+			step(tracer, evm::Opcode(0x0c), 2, &[]);
+			exit(tracer);
+		});
+
+		let unknown_opcodes: Vec<_> = tracer_events(&events)
+			.into_iter()
+			.filter(|event| matches!(event, TracerEvent::UnknownOpcode { .. }))
+			.collect();
+		assert_eq!(
+			unknown_opcodes,
+			vec![TracerEvent::UnknownOpcode {
+				pc: 0,
+				opcode: 0x0c
+			}]
+		);
+	}
+
+	#[test]
+	fn hardfork_is_reported_and_restricts_opcodes() {
+		let eoa = H160::repeat_byte(0x01);
+		let contract = H160::repeat_byte(0x02);
+		let trace = |hardfork: Option<Hardfork>| {
+			let mut result = None;
+			let events = host_events(|| {
+				let mut tracer = EvmTracer::new();
+				if let Some(hardfork) = hardfork {
+					tracer = tracer.with_hardfork(hardfork);
+				}
+				let (mut tracer, _) = tracer.capture_events().trace_with_tracer(|| ());
+				transact_call(&mut tracer, eoa, contract, 0.into());
+				call(&mut tracer, eoa, contract, 0.into(), &[], None);
+				// BLOBHASH, introduced by Cancun.
+				step(&mut tracer, evm::Opcode(0x49), 0, &[]);
+				exit(&mut tracer);
+				result = Some(tracer.finish());
+			});
+			assert_eq!(events, vec![]);
+			result.unwrap()
+		};
+		let unknown_opcodes = |result: &TraceResult| {
+			tracer_events(&result.events)
+				.into_iter()
+				.filter(|event| matches!(event, TracerEvent::UnknownOpcode { .. }))
+				.count()
+		};
+
+		let london = trace(Some(Hardfork::London));
+		assert_eq!(london.hardfork, Some(Hardfork::London));
+		assert_eq!(
+			london.events[..2],
+			[
+				Event::CallListNew(),
+				Event::Tracer(TracerEvent::Hardfork(Hardfork::London))
+			]
+		);
+		assert_eq!(unknown_opcodes(&london), 1);
+
+		assert_eq!(unknown_opcodes(&trace(Some(Hardfork::Cancun))), 0);
+		let unconfigured = trace(None);
+		assert_eq!(unconfigured.hardfork, None);
+		assert_eq!(unknown_opcodes(&unconfigured), 0);
+	}
+
+	#[test]
+	fn msg_sender_is_preserved_through_delegatecall() {
+		let eoa = H160::repeat_byte(0x01);
+		let proxy = H160::repeat_byte(0x02);
+		let implementation = H160::repeat_byte(0x03);
+		let third = H160::repeat_byte(0x04);
+
+		let events = trace_events_with(
+			|tracer| tracer.with_call_details(),
+			|tracer| {
+				transact_call(tracer, eoa, proxy, 0.into());
+				call(tracer, eoa, proxy, 0.into(), &[], None);
+				step(tracer, evm::Opcode::DELEGATECALL, 0, &[]);
+				// The implementation runs in the context of the proxy.
+				let context = context(proxy, eoa, 0.into());
+				EvmListener::event(
+					tracer,
+					evm::tracing::Event::Call {
+						code_address: implementation,
+						transfer: &None,
+						input: &[],
+						target_gas: None,
+						is_static: false,
+						context: &context,
+					},
+				);
+				step(tracer, evm::Opcode::CALL, 0, &[]);
+				call(tracer, proxy, third, 0.into(), &[], None);
+				exit(tracer);
+				exit(tracer);
+				exit(tracer);
+			},
+		);
+
+		let senders: Vec<_> = tracer_events(&events)
+			.into_iter()
+			.filter_map(|event| match event {
+				TracerEvent::Call {
+					msg_sender,
+					immediate_caller,
+					..
+				} => Some((msg_sender, immediate_caller)),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(
+			senders,
+			vec![(eoa, eoa), (eoa, proxy), (proxy, implementation)]
+		);
+	}
+}
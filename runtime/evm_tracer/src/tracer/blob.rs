@@ -0,0 +1,136 @@
+// Copyright 2019-2022 PureStake Inc.
+// This file is part of Moonbeam.
+
+// Moonbeam is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Moonbeam is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Moonbeam.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Self-describing archival format of a trace session: a version byte followed by the
+//! SCALE-encoded `TraceResult`, whose `tx` describes the traced transaction and `events` holds
+//! the captured events.
+
+use super::TraceResult;
+use codec::{Decode, Encode};
+use evm_tracing_events::tracer::TracerError;
+use sp_std::vec::Vec;
+
+/// Version of the format written by `encode_session`.
+pub const VERSION: u8 = 1;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BlobError {
+	/// The blob doesn't even contain a version.
+	Empty,
+	/// The blob was written by an unknown version of the format.
+	UnsupportedVersion(u8),
+	/// The content of the blob doesn't match its version.
+	Invalid,
+}
+
+impl From<BlobError> for TracerError {
+	fn from(_: BlobError) -> Self {
+		TracerError::SerializationFailed
+	}
+}
+
+pub fn encode_session(result: &TraceResult) -> Vec<u8> {
+	let mut blob = Vec::new();
+	blob.push(VERSION);
+	result.encode_to(&mut blob);
+	blob
+}
+
+pub fn decode_session(blob: &[u8]) -> Result<TraceResult, BlobError> {
+	match blob.split_first() {
+		None => Err(BlobError::Empty),
+		Some((&VERSION, mut content)) => {
+			let result = TraceResult::decode(&mut content).map_err(|_| BlobError::Invalid)?;
+			if !content.is_empty() {
+				return Err(BlobError::Invalid);
+			}
+			Ok(result)
+		}
+		Some((&version, _)) => Err(BlobError::UnsupportedVersion(version)),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use ethereum_types::{H160, H256};
+	use evm_tracing_events::{
+		tracer::{GasBreakdown, Hardfork, TxContext},
+		Event,
+	};
+
+	fn result() -> TraceResult {
+		TraceResult {
+			tx: Some(TxContext {
+				caller: H160::repeat_byte(0x01),
+				to: Some(H160::repeat_byte(0x02)),
+				value: 3.into(),
+				gas_limit: 100_000,
+				nonce: 4.into(),
+			}),
+			hardfork: Some(Hardfork::London),
+			gas_used: 26_000,
+			intrinsic_gas: 21_000,
+			gas_breakdown: GasBreakdown {
+				intrinsic: 21_000,
+				calldata: 0,
+				execution: 5_000,
+				refund: 0,
+			},
+			touched: vec![H160::repeat_byte(0x01), H160::repeat_byte(0x02)],
+			tx_succeeded: Some(true),
+			top_message_reverted: false,
+			inner_messages_reverted: 1,
+			events_discarded: false,
+			calldata_coverage: vec![0x0f],
+			accessed: vec![
+				(H160::repeat_byte(0x01), vec![]),
+				(H160::repeat_byte(0x02), vec![H256::repeat_byte(0x03)]),
+			],
+			warm_addresses: vec![H160::repeat_byte(0x01), H160::repeat_byte(0x02)],
+			events: vec![Event::CallListNew()],
+		}
+	}
+
+	#[test]
+	fn session_round_trips() {
+		let blob = encode_session(&result());
+
+		assert_eq!(blob[0], VERSION);
+		assert_eq!(decode_session(&blob), Ok(result()));
+	}
+
+	#[test]
+	fn unknown_version_is_rejected() {
+		let mut blob = encode_session(&result());
+		blob[0] = VERSION + 1;
+
+		assert_eq!(
+			decode_session(&blob),
+			Err(BlobError::UnsupportedVersion(VERSION + 1))
+		);
+		assert_eq!(decode_session(&[]), Err(BlobError::Empty));
+		assert_eq!(decode_session(&[VERSION, 0x01]), Err(BlobError::Invalid));
+	}
+
+	#[test]
+	fn invalid_blob_fails_serialization() {
+		assert_eq!(
+			decode_session(&[VERSION, 0x01]).map_err(TracerError::from),
+			Err(TracerError::SerializationFailed)
+		);
+	}
+}